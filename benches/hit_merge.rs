@@ -0,0 +1,104 @@
+//Compares merging the hits of many CoMPASS files into time order using a heap (HitMerger) against the previous
+//approach of scanning the top hit of every file for each hit. Run with `cargo bench --bench hit_merge`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use spsevb::{CompassData, CompassFile, EVBError, HitMerger};
+
+const TOTAL_HITS: usize = 200_000;
+const FILE_COUNTS: [usize; 4] = [8, 32, 128, 256];
+const HEADER_ENERGY: u16 = 0x0001; //Energy only: board, channel, timestamp, energy, flags = 18 bytes per hit
+
+//Synthetic CoMPASS file contents for one channel, with random gaps between hits (timestamps in ps)
+fn make_file(board: u16, channel: u16, n_hits: usize, rng: &mut StdRng) -> Vec<u8> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(2 + 18 * n_hits);
+    buffer.extend_from_slice(&HEADER_ENERGY.to_le_bytes());
+    let mut timestamp: u64 = rng.gen_range(0..1_000_000);
+    for _ in 0..n_hits {
+        timestamp += rng.gen_range(1..2_000_000);
+        buffer.extend_from_slice(&board.to_le_bytes());
+        buffer.extend_from_slice(&channel.to_le_bytes());
+        buffer.extend_from_slice(&timestamp.to_le_bytes());
+        buffer.extend_from_slice(&rng.gen_range(0u16..4096).to_le_bytes());
+        buffer.extend_from_slice(&0u32.to_le_bytes());
+    }
+    buffer
+}
+
+fn make_run(n_files: usize) -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(17);
+    (0..n_files).map(|i| make_file((i / 16) as u16, (i % 16) as u16, TOTAL_HITS / n_files, &mut rng)).collect()
+}
+
+fn open_files(run: &[Vec<u8>]) -> Vec<CompassFile<'static>> {
+    run.iter().map(|buffer| CompassFile::from_buffer(buffer.clone(), &None).unwrap()).collect()
+}
+
+//The linear scan used before HitMerger: check the top hit of every file to find the earliest
+fn pop_earliest_hit_linear(files: &mut [CompassFile]) -> Result<Option<CompassData>, EVBError> {
+    let mut earliest_file_index: Option<usize> = None;
+    for i in 0..files.len() {
+        if !files[i].is_eof() {
+            let hit = files[i].get_top_hit()?;
+            if hit.is_default() {
+                continue;
+            }
+
+            earliest_file_index = match earliest_file_index {
+                None => Some(i),
+                Some(index) => {
+                    if hit.timestamp < files[index].get_top_hit()?.timestamp {
+                        Some(i)
+                    } else {
+                        Some(index)
+                    }
+                }
+            };
+        }
+    }
+
+    match earliest_file_index {
+        None => Ok(None),
+        Some(i) => {
+            let hit = files[i].get_top_hit()?.clone();
+            files[i].set_hit_used();
+            Ok(Some(hit))
+        }
+    }
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hit_merge");
+    group.sample_size(10);
+    for n_files in FILE_COUNTS {
+        let run = make_run(n_files);
+        group.throughput(Throughput::Elements(((TOTAL_HITS / n_files) * n_files) as u64));
+
+        group.bench_with_input(BenchmarkId::new("linear", n_files), &run, |b, run| {
+            b.iter(|| {
+                let mut files = open_files(run);
+                let mut count = 0;
+                while let Some(_hit) = pop_earliest_hit_linear(&mut files).unwrap() {
+                    count += 1;
+                }
+                count
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("heap", n_files), &run, |b, run| {
+            b.iter(|| {
+                let mut hits = HitMerger::new(open_files(run)).unwrap();
+                let mut count = 0;
+                while let Some(_hit) = hits.pop_earliest_hit().unwrap() {
+                    count += 1;
+                }
+                count
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_merge);
+criterion_main!(benches);
@@ -0,0 +1,183 @@
+//Compares the file size and the read speed of the parquet output for each compression setting, using synthetic
+//focal plane events (the etc/ChannelMap.txt detectors), and the size of the same hits as a gzipped CoMPASS archive.
+//Run with `cargo bench --bench parquet_output`. The results are printed as a table.
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use flate2::Compression as GzCompression;
+use flate2::write::GzEncoder;
+use polars::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use spsevb::{
+    ChannelMap, DetectorGeometry, EventContext, SPSData, CompassData, RawCompassData, BatchWriter, Compression,
+    OutputSettings
+};
+
+const N_EVENTS: usize = 1_000_000;
+const READ_REPEATS: usize = 5;
+const BYTES_PER_HIT: u64 = 20; //Energy and short energy: board, channel, timestamp, energy, energy short, flags
+
+//Board, channel of each detector in etc/ChannelMap.txt
+const SCINT_RIGHT: (u16, u16) = (8, 0);
+const SCINT_LEFT: (u16, u16) = (8, 1);
+const CATHODE: (u16, u16) = (8, 7);
+const DELAY_FRONT_LEFT: (u16, u16) = (8, 8);
+const DELAY_FRONT_RIGHT: (u16, u16) = (8, 9);
+const DELAY_BACK_LEFT: (u16, u16) = (8, 10);
+const DELAY_BACK_RIGHT: (u16, u16) = (8, 11);
+const ANODE_FRONT: (u16, u16) = (8, 13);
+const ANODE_BACK: (u16, u16) = (8, 15);
+
+//Gaussian sampling with the Box-Muller transform, to avoid pulling in rand_distr
+fn sample_normal(rng: &mut StdRng, mean: f64, sigma: f64) -> f64 {
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.gen();
+    mean + sigma * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+fn make_hit(detector: (u16, u16), timestamp_ps: u64, energy: f64, rng: &mut StdRng) -> RawCompassData {
+    let energy = energy.clamp(0.0, 16383.0) as u16;
+    RawCompassData {
+        board: detector.0,
+        channel: detector.1,
+        timestamp: timestamp_ps,
+        energy,
+        energy_calibrated: None,
+        energy_short: (energy as f64 * rng.gen_range(0.2..0.4)) as u16,
+        flags: 0
+    }
+}
+
+//A focal plane event: the scintillator, then the anodes, cathode, and delay lines with the delay line times
+//set by the position. The position comes from a few states on a flat background. Some events only have
+//some of the detectors, as in real data.
+fn make_event(t0_ps: u64, rng: &mut StdRng) -> Vec<RawCompassData> {
+    let states = [-150.0, -40.0, 90.0];
+    let x = if rng.gen_bool(0.7) {
+        let state = states[rng.gen_range(0..states.len())];
+        sample_normal(rng, state, 2.0)
+    } else {
+        rng.gen_range(-300.0..300.0)
+    };
+    let ns: u64 = 1000; //ps per ns
+    let delay_time = |pos: f64| ((pos / 0.5) * ns as f64) as i64;
+
+    let mut event = vec![
+        make_hit(SCINT_LEFT, t0_ps, sample_normal(rng, 2000.0, 300.0), rng),
+        make_hit(SCINT_RIGHT, t0_ps + 2 * ns, sample_normal(rng, 1800.0, 300.0), rng)
+    ];
+    if rng.gen_bool(0.2) {
+        return event;
+    }
+    let base = (t0_ps + 500 * ns) as i64;
+    event.push(make_hit(ANODE_FRONT, t0_ps + 600 * ns, sample_normal(rng, 3000.0, 400.0), rng));
+    event.push(make_hit(ANODE_BACK, t0_ps + 620 * ns, sample_normal(rng, 2500.0, 400.0), rng));
+    event.push(make_hit(CATHODE, t0_ps + 650 * ns, sample_normal(rng, 1500.0, 300.0), rng));
+    if rng.gen_bool(0.9) {
+        event.push(make_hit(DELAY_FRONT_LEFT, (base - delay_time(x)) as u64 + 600 * ns, sample_normal(rng, 800.0, 100.0), rng));
+        event.push(make_hit(DELAY_FRONT_RIGHT, (base + delay_time(x)) as u64 + 600 * ns, sample_normal(rng, 800.0, 100.0), rng));
+    }
+    if rng.gen_bool(0.9) {
+        let x2 = x + rng.gen_range(-3.0..3.0);
+        event.push(make_hit(DELAY_BACK_LEFT, (base - delay_time(x2)) as u64 + 600 * ns, sample_normal(rng, 800.0, 100.0), rng));
+        event.push(make_hit(DELAY_BACK_RIGHT, (base + delay_time(x2)) as u64 + 600 * ns, sample_normal(rng, 800.0, 100.0), rng));
+    }
+    event.sort_by_key(|hit| hit.timestamp);
+    event
+}
+
+fn make_run() -> Vec<Vec<RawCompassData>> {
+    let mut rng = StdRng::seed_from_u64(12);
+    let mut t0_ps: u64 = 1_000_000;
+    (0..N_EVENTS).map(|_| {
+        t0_ps += rng.gen_range(10_000_000..200_000_000); //10-200 us between events
+        make_event(t0_ps, &mut rng)
+    }).collect()
+}
+
+//Size of the hits as CoMPASS binary files in a gzipped archive (the archive is one compressed stream)
+fn get_archive_size(run: &[Vec<RawCompassData>]) -> Result<u64, std::io::Error> {
+    let mut encoder = GzEncoder::new(vec![], GzCompression::default());
+    let detectors = [SCINT_RIGHT, SCINT_LEFT, CATHODE, DELAY_FRONT_LEFT, DELAY_FRONT_RIGHT, DELAY_BACK_LEFT, DELAY_BACK_RIGHT, ANODE_FRONT, ANODE_BACK];
+    for detector in detectors {
+        encoder.write_all(&0x0005u16.to_le_bytes())?;
+        for hit in run.iter().flatten().filter(|hit| (hit.board, hit.channel) == detector) {
+            encoder.write_all(&hit.board.to_le_bytes())?;
+            encoder.write_all(&hit.channel.to_le_bytes())?;
+            encoder.write_all(&hit.timestamp.to_le_bytes())?;
+            encoder.write_all(&hit.energy.to_le_bytes())?;
+            encoder.write_all(&hit.energy_short.to_le_bytes())?;
+            encoder.write_all(&hit.flags.to_le_bytes())?;
+        }
+    }
+    Ok(encoder.finish()?.len() as u64)
+}
+
+fn write_run(data: &SPSData, dir: &Path, settings: &OutputSettings) -> Result<Duration, PolarsError> {
+    let start = Instant::now();
+    let mut writer = BatchWriter::new(dir, "bench", "", settings);
+    let batch_size = settings.batch_size;
+    let columns = data.clone().convert_to_series();
+    let df = DataFrame::new(columns)?;
+    let mut offset = 0;
+    while offset < df.height() {
+        writer.write_batch(df.slice(offset as i64, batch_size).get_columns().to_vec())?;
+        offset += batch_size;
+    }
+    writer.finish()?;
+    Ok(start.elapsed())
+}
+
+fn read_run(path: &Path) -> Result<Duration, PolarsError> {
+    let mut times: Vec<Duration> = vec![];
+    for _ in 0..READ_REPEATS {
+        let start = Instant::now();
+        let df = ParquetReader::new(File::open(path)?).finish()?;
+        times.push(start.elapsed());
+        assert_eq!(df.height(), N_EVENTS);
+    }
+    times.sort();
+    Ok(times[times.len() / 2])
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let channel_map = ChannelMap::new(Path::new("etc/ChannelMap.txt"))?;
+    let geometry = DetectorGeometry::default();
+    let context = EventContext::new(&channel_map, &geometry);
+    let run = make_run();
+    let n_hits: usize = run.iter().map(|event| event.len()).sum();
+
+    let mut data = SPSData::default();
+    for event in run.iter() {
+        let hits: Vec<CompassData> = event.iter().map(|raw| CompassData::new(raw, &None)).collect();
+        data.append_event(hits, &context);
+    }
+
+    let dir = std::env::temp_dir().join("spsevb_parquet_bench");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("bench.parquet");
+
+    println!("{} events, {} hits", N_EVENTS, n_hits);
+    println!("CoMPASS binary: {:.1} MB, gzipped archive: {:.1} MB", (n_hits as u64 * BYTES_PER_HIT) as f64 / 1.0e6, get_archive_size(&run)? as f64 / 1.0e6);
+    println!("| Compression | Size (MB) | Write (s) | Read (s) |");
+    println!("|-------------|-----------|-----------|----------|");
+    let settings = [(Compression::Uncompressed, 0), (Compression::Snappy, 0), (Compression::Lz4, 0),
+                    (Compression::Zstd, 1), (Compression::Zstd, 3), (Compression::Zstd, 9), (Compression::Zstd, 19)];
+    for (compression, level) in settings {
+        let settings = OutputSettings { compression, compression_level: level, ..Default::default() };
+        let write_time = write_run(&data, &dir, &settings)?;
+        let size = std::fs::metadata(&path)?.len();
+        let read_time = read_run(&path)?;
+        let name = match compression.get_level_range() {
+            Some(_) => format!("{} {}", compression, level),
+            None => compression.to_string()
+        };
+        println!("| {} | {:.1} | {:.2} | {:.3} |", name, size as f64 / 1.0e6, write_time.as_secs_f64(), read_time.as_secs_f64());
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
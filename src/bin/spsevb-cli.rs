@@ -0,0 +1,317 @@
+use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use spsevb::{
+    ChannelMap, SPSChannelType, process_runs, LogCapture, LogStore, get_job_log_name, AppParams, EVBError, MassMap,
+    Progress, format_duration, JobControl, ScalerList, ShiftMap, CalibrationMap, ExReconstructor,
+    FocalPlaneCalibration, fit_focal_plane, read_calibration_points, calibrate_shifts
+};
+
+const PROGRESS_POLL_MS: u64 = 100;
+
+//Headless command line interface to the event builder
+#[derive(Debug, Parser)]
+#[command(name = "spsevb-cli", version, about = "Event builder for the Super-Enge Split-Pole Spectrograph")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Event build the runs specified by a config file, without a GUI
+    Build {
+        /// YAML config file (as saved from the GUI)
+        config: PathBuf,
+        /// Override the config's first run
+        #[arg(long)]
+        run_min: Option<i32>,
+        /// Override the config's last run (inclusive)
+        #[arg(long)]
+        run_max: Option<i32>,
+        /// Override the config's number of worker threads (runs processed at once)
+        #[arg(short = 'j', long)]
+        workers: Option<usize>
+    },
+    /// Check that a config file and all of the files it references are usable
+    ValidateConfig {
+        /// YAML config file (as saved from the GUI)
+        config: PathBuf
+    },
+    /// List the run archives found in the config's workspace
+    ListRuns {
+        /// YAML config file (as saved from the GUI)
+        config: PathBuf
+    },
+    /// Generate a shift map by histogramming each channel's timestamp relative to a reference detector
+    CalibrateShifts {
+        /// YAML config file (as saved from the GUI)
+        config: PathBuf,
+        /// Shift map file to write
+        output: PathBuf,
+        /// Reference detector, as named in the channel map
+        #[arg(short, long, default_value = "ScintLeft")]
+        reference: String,
+        /// Histogram bin width in ns
+        #[arg(short, long, default_value_t = 1.0)]
+        bin_width: f64,
+        /// Report of the fitted peaks to write (defaults to the output with a .report.txt extension)
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Override the config's first run
+        #[arg(long)]
+        run_min: Option<i32>,
+        /// Override the config's last run (inclusive)
+        #[arg(long)]
+        run_max: Option<i32>
+    },
+    /// Fit a focal plane calibration (Xavg -> rho) from peaks identified with known states
+    FitFocalPlane {
+        /// YAML config file (as saved from the GUI), giving the reaction
+        config: PathBuf,
+        /// Peak list: Xavg centroid (mm), excitation energy (MeV), and optionally the centroid uncertainty (mm)
+        peaks: PathBuf,
+        /// Focal plane calibration file to write
+        output: PathBuf,
+        /// Order of the polynomial
+        #[arg(short, long, default_value_t = 1)]
+        order: usize,
+        /// Report of the fit to write (defaults to the output with a .report.txt extension)
+        #[arg(long)]
+        report: Option<PathBuf>
+    }
+}
+
+fn main() {
+    //Log to the terminal, and to the job log file in the workspace while building
+    let log_store = Arc::new(LogStore::new(0));
+    simplelog::CombinedLogger::init(vec![
+        simplelog::TermLogger::new(simplelog::LevelFilter::Info,
+                                   simplelog::Config::default(),
+                                   simplelog::TerminalMode::Mixed,
+                                   simplelog::ColorChoice::Auto),
+        LogCapture::new(simplelog::LevelFilter::Info, log_store.clone())
+    ]).unwrap();
+
+    let args = Cli::parse();
+    let result = match args.command {
+        Command::Build { config, run_min, run_max, workers } => build(&config, run_min, run_max, workers, &log_store),
+        Command::ValidateConfig { config } => validate_config(&config),
+        Command::ListRuns { config } => list_runs(&config),
+        Command::CalibrateShifts { config, output, reference, bin_width, report, run_min, run_max } => {
+            calibrate(&config, &output, &reference, bin_width, report, run_min, run_max)
+        }
+        Command::FitFocalPlane { config, peaks, output, order, report } => fit(&config, &peaks, &output, order, report)
+    };
+
+    //Close the job log after any error has been written to it
+    if let Err(e) = result {
+        error!("{}", e);
+        log_store.stop_job_log();
+        std::process::exit(1);
+    }
+    log_store.stop_job_log();
+}
+
+fn build(config: &Path, run_min: Option<i32>, run_max: Option<i32>, workers: Option<usize>, log_store: &LogStore) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params = AppParams::read_from_file(config)?;
+    if let Some(min) = run_min {
+        params.run_min = min;
+    }
+    if let Some(max) = run_max {
+        params.run_max = max;
+    }
+    if let Some(n) = workers {
+        params.n_workers = n;
+    }
+    let r_params = params.get_process_params()?;
+    let k_params = params.kinematics.clone();
+
+    if let Some(ws) = &params.workspace {
+        let log_path = ws.get_log_dir()?.join(get_job_log_name());
+        log_store.start_job_log(&log_path)?;
+        info!("Writing job log to {}", log_path.display());
+    }
+
+    info!("Starting processor for runs {} to {} with {} workers...", params.run_min, params.run_max, r_params.n_workers.max(1));
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let prog = progress.clone();
+
+    //The first Ctrl-C cancels the job (cleaning up the runs in progress), a second one exits immediately
+    let control = Arc::new(JobControl::default());
+    let cancel_flag = control.get_cancel_flag();
+    signal_hook::flag::register_conditional_shutdown(signal_hook::consts::SIGINT, 1, cancel_flag.clone())?;
+    signal_hook::flag::register(signal_hook::consts::SIGINT, cancel_flag)?;
+    let ctrl = control.clone();
+    let handle = std::thread::spawn(|| process_runs(r_params, k_params, prog, ctrl));
+
+    let bar = ProgressBar::new(100);
+    bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>3}% {msg}")?);
+    let mut reported_runs: HashSet<i32> = HashSet::new();
+    loop {
+        let is_finished = handle.is_finished();
+        match progress.lock() {
+            Ok(x) => {
+                report_finished_runs(&x, &mut reported_runs, &bar);
+                bar.set_position((x.get_total_fraction() * 100.0) as u64);
+                if control.is_cancelled() {
+                    bar.set_message("Cancelling, press Ctrl-C again to exit immediately...");
+                } else {
+                    bar.set_message(get_progress_message(&x));
+                }
+            }
+            Err(_) => return Err(Box::new(EVBError::SyncError))
+        };
+        if is_finished {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(PROGRESS_POLL_MS));
+    }
+    bar.finish_and_clear();
+
+    match handle.join() {
+        Ok(result) => result?,
+        Err(_) => return Err("An error occured in joining the processing thread!".into())
+    };
+    info!("Finished processing the runs");
+    Ok(())
+}
+
+//Summary line for the progress bar: runs, hits, rate, events, ETA, and the runs being processed
+fn get_progress_message(progress: &Progress) -> String {
+    let eta = match progress.get_eta() {
+        Some(duration) => format_duration(duration),
+        None => String::from("--:--:--")
+    };
+    let active: Vec<String> = progress.get_active_runs()
+                                      .map(|r| format!("run {} {:.0}%", r.run_number, r.get_fraction() * 100.0))
+                                      .collect();
+    format!("{}/{} runs | {} hits ({:.0} hits/s) | {} events | ETA {} | {}",
+            progress.get_number_finished(), progress.runs.len(), progress.get_hits_processed(), progress.get_hit_rate(),
+            progress.get_events(), eta, active.join(", "))
+}
+
+//Print a status line for each run as it finishes
+fn report_finished_runs(progress: &Progress, reported_runs: &mut HashSet<i32>, bar: &ProgressBar) {
+    for run in progress.runs.iter().filter(|r| r.is_finished()) {
+        if reported_runs.insert(run.run_number) {
+            bar.println(format!("Run {}: {} | {} hits | {} events | {:.0} hits/s | {}", run.run_number, run.status, run.hits_processed,
+                                run.events, run.get_hit_rate(), format_duration(run.get_elapsed())));
+        }
+    }
+}
+
+fn validate_config(config: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params = AppParams::read_from_file(config)?;
+    let r_params = params.get_process_params()?;
+    info!("Workspace: {}", params.workspace.as_ref().map(|ws| ws.get_parent_str()).unwrap_or("None"));
+
+    ChannelMap::new(&r_params.channel_map_filepath)?;
+    info!("Channel map: {} ok", r_params.channel_map_filepath.display());
+    if let Some(path) = &r_params.scaler_list_filepath {
+        ScalerList::new(path)?;
+        info!("Scaler list: {} ok", path.display());
+    }
+    if let Some(path) = &r_params.shift_map_filepath {
+        ShiftMap::new(path)?;
+        info!("Shift map: {} ok", path.display());
+    }
+    if let Some(path) = &r_params.calibration_map_filepath {
+        CalibrationMap::new(path)?;
+        info!("Calibration map: {} ok", path.display());
+    }
+
+    let mass_map = MassMap::load(&params.mass_file)?;
+    info!("Mass evaluation: {}", mass_map.get_evaluation());
+    params.kinematics.resolve_reaction(&mass_map)?;
+    if let Some(path) = &r_params.fp_calibration_filepath {
+        ExReconstructor::new(FocalPlaneCalibration::new(path)?, &params.kinematics, &mass_map)?;
+        info!("Focal plane calibration: {} ok", path.display());
+    }
+    info!("Reaction: {}", params.kinematics.generate_rxn_eqn(&mass_map));
+    info!("Coincidence window: {} ns", params.coincidence_window);
+    info!("Runs: {} to {}", params.run_min, params.run_max);
+    info!("Config {} is valid", config.display());
+    Ok(())
+}
+
+fn list_runs(config: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let params = AppParams::read_from_file(config)?;
+    let archive_dir = match &params.workspace {
+        Some(ws) => ws.get_archive_dir()?,
+        None => return Err("Config does not specify a workspace".into())
+    };
+
+    let mut runs: Vec<(i32, u64)> = vec![];
+    for item in archive_dir.read_dir()? {
+        let entry = item?;
+        let name = entry.file_name();
+        let run = name.to_str()
+                      .and_then(|n| n.strip_prefix("run_"))
+                      .and_then(|n| n.strip_suffix(".tar.gz"))
+                      .and_then(|n| n.parse::<i32>().ok());
+        if let Some(number) = run {
+            runs.push((number, entry.metadata()?.len()));
+        }
+    }
+    runs.sort();
+
+    println!("Run archives in {}:", archive_dir.display());
+    for (number, size) in runs.iter() {
+        let marker = if *number >= params.run_min && *number <= params.run_max { "*" } else { " " };
+        println!("{} run_{}.tar.gz {:>10.3} MB", marker, number, *size as f64 * 1.0e-6);
+    }
+    println!("{} archives found, * marks runs in the configured range [{}, {}]", runs.len(), params.run_min, params.run_max);
+    Ok(())
+}
+
+fn calibrate(config: &Path, output: &Path, reference: &str, bin_width: f64, report: Option<PathBuf>,
+             run_min: Option<i32>, run_max: Option<i32>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params = AppParams::read_from_file(config)?;
+    if let Some(min) = run_min {
+        params.run_min = min;
+    }
+    if let Some(max) = run_max {
+        params.run_max = max;
+    }
+    let r_params = params.get_process_params()?;
+    let ref_type = match SPSChannelType::get_channel_vec().into_iter().find(|t| t.as_ref() == reference) {
+        Some(t) => t,
+        None => return Err(format!("{} is not a valid detector name", reference).into())
+    };
+    if bin_width <= 0.0 {
+        return Err("Bin width must be greater than zero".into());
+    }
+
+    info!("Calibrating time shifts to {} with runs {} to {}...", reference, params.run_min, params.run_max);
+    let calibration = calibrate_shifts(&r_params, &ref_type, bin_width)?;
+    let report_path = report.unwrap_or_else(|| output.with_extension("report.txt"));
+    calibration.write_shift_map(output)?;
+    calibration.write_report(&report_path)?;
+    info!("Wrote shift map to {} and report to {}", output.display(), report_path.display());
+    Ok(())
+}
+
+fn fit(config: &Path, peaks: &Path, output: &Path, order: usize, report: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params = AppParams::read_from_file(config)?;
+    let mass_map = MassMap::load(&params.mass_file)?;
+    params.kinematics.resolve_reaction(&mass_map)?;
+    let points = read_calibration_points(peaks)?;
+
+    info!("Fitting {} peaks for {} with a polynomial of order {}...", points.len(), params.kinematics.generate_rxn_eqn(&mass_map), order);
+    let result = fit_focal_plane(&points, order, &params.kinematics, &mass_map)?;
+    for res in result.residuals.iter() {
+        info!("Xavg {:.3} mm Ex {:.4} MeV -> fit Ex {:.4} MeV, residual {:.2} keV", res.point.xavg, res.point.ex, res.ex_fit, res.residual_kev);
+    }
+
+    let report_path = report.unwrap_or_else(|| output.with_extension("report.txt"));
+    result.calibration.write_to_file(output)?;
+    result.write_report(&report_path)?;
+    info!("Wrote focal plane calibration to {} and report to {}", output.display(), report_path.display());
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+use std::io::{BufReader, BufRead};
+use std::num::ParseIntError;
+use std::num::ParseFloatError;
+use serde::{Serialize, Deserialize};
+
+use super::compass_data::{generate_board_channel_uuid, CompassData};
+
+#[derive(Debug)]
+pub enum CalibrationError {
+    FileError(std::io::Error),
+    ChannelError(ParseIntError),
+    CoefficientError(ParseFloatError),
+    SerializerError(serde_yaml::Error),
+    MissingCoefficients(u32, u32),
+    IncompleteLine(usize, String)
+}
+
+impl Display for CalibrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationError::FileError(x) => write!(f, "CalibrationMap had an IO error: {}", x),
+            CalibrationError::ChannelError(x) => write!(f, "CalibrationMap could not parse board/channel: {}", x),
+            CalibrationError::CoefficientError(x) => write!(f, "CalibrationMap could not parse coefficient: {}", x),
+            CalibrationError::SerializerError(x) => write!(f, "CalibrationMap could not parse yaml: {}", x),
+            CalibrationError::MissingCoefficients(b, c) => write!(f, "CalibrationMap has no coefficients for board {} channel {}", b, c),
+            CalibrationError::IncompleteLine(n, line) => write!(f, "CalibrationMap line {} needs a board, channel, and at least one coefficient: \"{}\"", n, line)
+        }
+    }
+}
+
+impl From<std::io::Error> for CalibrationError {
+    fn from(value: std::io::Error) -> Self {
+        CalibrationError::FileError(value)
+    }
+}
+
+impl From<ParseIntError> for CalibrationError {
+    fn from(value: ParseIntError) -> Self {
+        CalibrationError::ChannelError(value)
+    }
+}
+
+impl From<ParseFloatError> for CalibrationError {
+    fn from(value: ParseFloatError) -> Self {
+        CalibrationError::CoefficientError(value)
+    }
+}
+
+impl From<serde_yaml::Error> for CalibrationError {
+    fn from(value: serde_yaml::Error) -> Self {
+        CalibrationError::SerializerError(value)
+    }
+}
+
+impl std::error::Error for CalibrationError {
+
+}
+
+//A single entry of the yaml variant of the calibration map
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CalibrationEntry {
+    board: u32,
+    channel: u32,
+    coefficients: Vec<f64>
+}
+
+/// Map of digitizer board/channel to energy calibration polynomial coefficients, read from a calibration map file.
+/// The coefficients are in increasing order, i.e. E = c0 + c1*x + c2*x^2 + ... where x is the raw energy.
+#[derive(Debug, Clone)]
+pub struct CalibrationMap {
+    map: HashMap<u32, Vec<f64>>
+}
+
+impl CalibrationMap {
+    /// Read a calibration map. Files ending in .yaml or .yml are read as yaml, all others are read as text.
+    pub fn new(path: &Path) -> Result<CalibrationMap, CalibrationError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => Self::read_yaml(path),
+            _ => Self::read_text(path)
+        }
+    }
+
+    fn read_text(path: &Path) -> Result<CalibrationMap, CalibrationError> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(file);
+        let mut junk = String::new();
+        let mut mapper = CalibrationMap {
+            map: HashMap::new()
+        };
+
+        reader.read_line(&mut junk)?;
+        for (index, line) in reader.lines().enumerate() {
+            match line {
+                Ok(line_str) => {
+                    let entries: Vec<&str> = line_str.split_whitespace().collect();
+                    if entries.is_empty() {
+                        continue;
+                    } else if entries.len() < 3 {
+                        //Line numbers count from 1, after the header line
+                        return Err(CalibrationError::IncompleteLine(index + 2, line_str));
+                    }
+                    let board: u32 = entries[0].parse()?;
+                    let channel: u32 = entries[1].parse()?;
+                    let mut coefficients: Vec<f64> = vec![];
+                    for entry in entries[2..].iter() {
+                        coefficients.push(entry.parse()?);
+                    }
+                    mapper.insert(board, channel, coefficients)?;
+                },
+                Err(x) => return Err(CalibrationError::from(x))
+            };
+        }
+        return Ok(mapper);
+    }
+
+    fn read_yaml(path: &Path) -> Result<CalibrationMap, CalibrationError> {
+        let yaml_str = std::fs::read_to_string(path)?;
+        let entries = serde_yaml::from_str::<Vec<CalibrationEntry>>(&yaml_str)?;
+        let mut mapper = CalibrationMap {
+            map: HashMap::new()
+        };
+        for entry in entries {
+            mapper.insert(entry.board, entry.channel, entry.coefficients)?;
+        }
+        return Ok(mapper);
+    }
+
+    fn insert(&mut self, board: u32, channel: u32, coefficients: Vec<f64>) -> Result<(), CalibrationError> {
+        if coefficients.is_empty() {
+            return Err(CalibrationError::MissingCoefficients(board, channel));
+        }
+        self.map.insert(generate_board_channel_uuid(&board, &channel), coefficients);
+        Ok(())
+    }
+
+    /// Calibrated energy for the given channel, or None if the channel is not in the map
+    pub fn get_calibrated_energy(&self, id: &u32, energy: f64) -> Option<f64> {
+        //Horner's method
+        self.map.get(id).map(|coefficients| coefficients.iter().rev().fold(0.0, |acc, c| acc * energy + c))
+    }
+
+    /// Set the calibrated energy of a hit from its raw energy. The map takes precedence: for channels in the map,
+    /// any calibrated energy from CoMPASS is replaced. Hits from channels which are not in the map keep their
+    /// calibrated energy from CoMPASS.
+    pub fn calibrate(&self, hit: &mut CompassData) {
+        if let Some(value) = self.get_calibrated_energy(&hit.uuid, hit.energy) {
+            hit.energy_calibrated = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Write a calibration map file to a unique temporary path
+    fn write_map(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("spsevb_calibration_{}_{}.txt", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_text_map() {
+        let path = write_map("good", "Board Channel Coefficients\n8 0 1.0 2.0 0.5\n\n8 1 0.0 0.0025\n");
+        let map = CalibrationMap::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(map.get_calibrated_energy(&generate_board_channel_uuid(&8, &0), 2.0), Some(1.0 + 4.0 + 2.0));
+        assert_eq!(map.get_calibrated_energy(&generate_board_channel_uuid(&8, &1), 1000.0), Some(2.5));
+        assert_eq!(map.get_calibrated_energy(&generate_board_channel_uuid(&8, &2), 1000.0), None);
+    }
+
+    #[test]
+    fn rejects_incomplete_lines() {
+        for (name, line) in [("board", "8"), ("channel", "8 0")] {
+            let path = write_map(name, &format!("Board Channel Coefficients\n8 1 0.0 1.0\n{}\n", line));
+            let result = CalibrationMap::new(&path);
+            std::fs::remove_file(&path).unwrap();
+            match result {
+                Err(CalibrationError::IncompleteLine(3, text)) => assert_eq!(text, line),
+                other => panic!("Expected an incomplete line error for {:?}, got {:?}", line, other)
+            }
+        }
+    }
+
+    #[test]
+    fn map_overrides_compass_calibration() {
+        let path = write_map("override", "Board Channel Coefficients\n8 0 0.0 2.0\n");
+        let map = CalibrationMap::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut in_map = CompassData { uuid: generate_board_channel_uuid(&8, &0), energy: 10.0, energy_calibrated: 1.5, ..Default::default() };
+        let mut not_in_map = CompassData { uuid: generate_board_channel_uuid(&8, &1), energy: 10.0, energy_calibrated: 1.5, ..Default::default() };
+        map.calibrate(&mut in_map);
+        map.calibrate(&mut not_in_map);
+        assert_eq!(in_map.energy_calibrated, 20.0);
+        assert_eq!(not_in_map.energy_calibrated, 1.5);
+    }
+}
@@ -84,6 +84,7 @@ impl Default for ChannelData {
     }
 }
 
+/// Map of digitizer board/channel to detector component, read from a channel map file
 #[derive(Debug)]
 pub struct ChannelMap {
     map: HashMap<u32, ChannelData>
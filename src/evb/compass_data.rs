@@ -2,6 +2,9 @@ use bitflags::bitflags;
 use rand::Rng;
 use super::shift_map::ShiftMap;
 
+/// Value of a field which has no data (i.e. a detector without a hit, or a file without calibrated energies)
+pub const INVALID_VALUE: f64 = -1.0e6;
+
 bitflags! {
     pub struct CompassDataType: u16 {
         const ENERGY = 0x0001;
@@ -13,14 +16,62 @@ bitflags! {
     }
 }
 
+//Hit flags (the "extras" word) written by CoMPASS for each hit
+bitflags! {
+    pub struct CompassFlags: u32 {
+        const DEAD_TIME = 0x00000001;
+        const TIMESTAMP_ROLLOVER = 0x00000002;
+        const TIMESTAMP_RESET = 0x00000004;
+        const FAKE_EVENT = 0x00000008;
+        const MEMORY_FULL = 0x00000010;
+        const TRIGGER_LOST = 0x00000020;
+        const N_TRIGGERS_LOST = 0x00000040;
+        const SATURATION_IN_GATE = 0x00000080;
+        const TRIGGERS_1024_COUNTED = 0x00000100;
+        const INPUT_SATURATING = 0x00000400;
+        const N_TRIGGERS_COUNTED = 0x00000800;
+        const EVENT_NOT_MATCHED = 0x00001000;
+        const FINE_TIMESTAMP = 0x00004000;
+        const PILE_UP = 0x00008000;
+        const PLL_LOCK_LOSS = 0x00080000;
+        const OVER_TEMPERATURE = 0x00100000;
+        const ADC_SHUTDOWN = 0x00200000;
+    }
+}
+
+//Largest waveform accepted in a hit. CoMPASS record lengths are far shorter than this; a larger number of samples
+//means the file is corrupt, and is rejected rather than allocating a buffer for it.
+pub const MAX_WAVE_SAMPLES: u32 = 1 << 20;
+
+#[derive(Debug)]
+pub enum CompassDataError {
+    WaveformTooLong(u32),
+    WaveformPastEndOfFile(u32)
+}
+
+impl std::fmt::Display for CompassDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompassDataError::WaveformTooLong(x) => write!(f, "CoMPASS hit has a waveform of {} samples, more than the maximum of {}; the file is likely corrupt", x, MAX_WAVE_SAMPLES),
+            CompassDataError::WaveformPastEndOfFile(x) => write!(f, "CoMPASS hit has a waveform of {} samples, which is larger than the file; the file is likely corrupt or truncated", x)
+        }
+    }
+}
+
+impl std::error::Error for CompassDataError {
+
+}
+
 #[derive(Debug, Clone)]
 pub struct RawCompassData {
     pub board: u16,
     pub channel: u16,
     pub timestamp: u64,
     pub energy: u16,
-    pub energy_calibrated: u64,
-    pub energy_short: u16
+    /// Raw bits of the calibrated energy (a double), if the file has it
+    pub energy_calibrated: Option<u64>,
+    pub energy_short: u16,
+    pub flags: u32
 }
 
 pub const fn generate_board_channel_uuid(board: &u32, channel: &u32) -> u32 {
@@ -40,12 +91,23 @@ pub fn decompose_uuid_to_board_channel(uuid: &u32) -> (u32, u32) {
     }
 }
 
+/// Digitized waveform attached to a hit. The probe is the CoMPASS waveform (probe) code.
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    pub probe: u8,
+    pub samples: Vec<u16>
+}
+
+/// A single digitizer hit, with the energies dithered and the timestamp (ns) shifted
 #[derive(Debug, Clone)]
 pub struct CompassData {
     pub uuid: u32,
     pub energy: f64,
+    pub energy_calibrated: f64,
     pub energy_short: f64,
-    pub timestamp: f64
+    pub timestamp: f64,
+    pub flags: CompassFlags,
+    pub waveform: Option<Waveform>
 }
 
 impl CompassData {
@@ -57,11 +119,17 @@ impl CompassData {
         CompassData {
             uuid: id,
             energy: raw.energy as f64 + rng.gen::<f64>(),
+            energy_calibrated: match raw.energy_calibrated {
+                Some(bits) => f64::from_bits(bits), //CoMPASS stores the calibrated energy as a double
+                None => INVALID_VALUE
+            },
             energy_short: raw.energy_short as f64 + rng.gen::<f64>(),
             timestamp: match shifts {
                 Some(map) => raw.timestamp as f64 * 1.0e-3 + map.get_timeshift(&id),
                 None => raw.timestamp as f64 * 1.0e-3
-            }
+            },
+            flags: CompassFlags::from_bits_truncate(raw.flags),
+            waveform: None
         }
     }
 
@@ -81,6 +149,25 @@ impl CompassData {
 
 impl Default for CompassData {
     fn default() -> Self {
-        CompassData { uuid: 0, energy: 0.0, energy_short: 0.0, timestamp: 0.0 }
+        CompassData { uuid: 0, energy: 0.0, energy_calibrated: INVALID_VALUE, energy_short: 0.0, timestamp: 0.0, flags: CompassFlags::empty(), waveform: None }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_raw(energy_calibrated: Option<u64>) -> RawCompassData {
+        RawCompassData { board: 0, channel: 0, timestamp: 1000, energy: 100, energy_calibrated, energy_short: 50, flags: 0 }
+    }
+
+    #[test]
+    fn calibrated_energy_is_decoded() {
+        assert_eq!(CompassData::new(&make_raw(Some(1.25f64.to_bits())), &None).energy_calibrated, 1.25);
+        assert_eq!(CompassData::new(&make_raw(Some(0.0f64.to_bits())), &None).energy_calibrated, 0.0);
+    }
+
+    #[test]
+    fn missing_calibrated_energy_is_invalid() {
+        assert_eq!(CompassData::new(&make_raw(None), &None).energy_calibrated, INVALID_VALUE);
+    }
+}
@@ -1,14 +1,22 @@
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{BufReader, Cursor};
 use std::path;
-use super::compass_data::{CompassDataType, RawCompassData, CompassData};
+use super::compass_data::{CompassDataType, RawCompassData, CompassData, CompassDataError, Waveform, MAX_WAVE_SAMPLES};
 use super::error::EVBError;
 use super::shift_map::ShiftMap;
 
 use nom::number::complete::*;
 
 const BUFFER_SIZE_HITS: usize = 24000; // Size in Compass hits of the buffer for each binary data file
+const WAVE_HEADER_SIZE: usize = 5; // Size in bytes of the waveform code (u8) and number of samples (u32)
+
+fn parse_u8(buffer: &[u8]) -> Result<(&[u8], u8), EVBError> {
+    match le_u8::<&[u8], nom::error::Error<&[u8]>>(buffer) {
+        Err(_x) => Err(EVBError::ParserError),
+        Ok(x) => Ok(x)
+    }
+}
 
 fn parse_u16(buffer: &[u8]) -> Result<(&[u8], u16), EVBError> {
     match le_u16::<&[u8], nom::error::Error<&[u8]>>(buffer) {
@@ -31,12 +39,17 @@ fn parse_u64(buffer: &[u8]) -> Result<(&[u8], u64), EVBError> {
     }
 }
 
-#[derive(Debug)]
+/// A single CoMPASS binary data file (one digitizer channel), read one hit at a time.
+/// The current hit is held until it is marked used, at which point the next call to
+/// `get_top_hit` reads the next hit from the file. The data can come from a file on disk or from
+/// any other reader (e.g. a decompressed in-memory buffer).
 pub struct CompassFile<'a> {
-    file_handle: BufReader<File>,
+    file_handle: BufReader<Box<dyn Read + Send + 'a>>,
     size_bytes: u64,
     data_type: CompassDataType,
     data_size_bytes: usize,
+    wave_samples: usize,
+    keep_waves: bool,
     current_hit: CompassData,
     shift_map: &'a Option<ShiftMap>,
     is_used: bool,
@@ -44,12 +57,23 @@ pub struct CompassFile<'a> {
 }
 
 impl<'a> CompassFile<'a> {
+    /// Open a CoMPASS binary file and read its header
     pub fn new(path: &path::Path, shifts: &'a Option<ShiftMap>) -> Result<CompassFile<'a>, EVBError> {
-        let mut file: File = File::open(path)?;
+        let file: File = File::open(path)?;
         let total_size = file.metadata()?.len();
+        Self::from_reader(Box::new(file), total_size, shifts)
+    }
 
+    /// Use an in-memory buffer containing the contents of a CoMPASS binary file
+    pub fn from_buffer(buffer: Vec<u8>, shifts: &'a Option<ShiftMap>) -> Result<CompassFile<'a>, EVBError> {
+        let total_size = buffer.len() as u64;
+        Self::from_reader(Box::new(Cursor::new(buffer)), total_size, shifts)
+    }
+
+    /// Read CoMPASS data from any reader, where total_size is the size of the data in bytes (including the header)
+    pub fn from_reader(mut reader: Box<dyn Read + Send + 'a>, total_size: u64, shifts: &'a Option<ShiftMap>) -> Result<CompassFile<'a>, EVBError> {
         let mut header:[u8; 2] = [0; 2];
-        file.read_exact(&mut header)?;
+        reader.read_exact(&mut header)?;
         let header_word = u16::from_le_bytes(header);
 
         let mut datatype = CompassDataType::NONE;
@@ -68,23 +92,51 @@ impl<'a> CompassFile<'a> {
             datasize += 8;
         }
         if header_word & CompassDataType::WAVES.bits() != 0 {
-            return Err(EVBError::WavesError);
+            datatype |= CompassDataType::WAVES;
         }
-        
 
-        return Ok(CompassFile {
-            file_handle: BufReader::with_capacity(datasize * BUFFER_SIZE_HITS, file),
+        let mut compass_file = CompassFile {
+            file_handle: BufReader::with_capacity(datasize * BUFFER_SIZE_HITS, reader),
             size_bytes: total_size,
             data_type: datatype,
             data_size_bytes: datasize,
+            wave_samples: 0,
+            keep_waves: false,
             current_hit: CompassData::default(),
             shift_map: shifts,
-            is_used: false,
+            is_used: true, //Nothing read yet, so the first call to get_top_hit reads the first hit
             is_eof: false
-        });
+        };
+
+        if compass_file.has_waves() {
+            compass_file.wave_samples = compass_file.peek_wave_samples()?;
+        }
 
+        return Ok(compass_file);
     }
 
+    /// Store the waveform samples of each hit in the CompassData. By default, waveforms are skipped.
+    pub fn set_keep_waves(&mut self, keep: bool) {
+        self.keep_waves = keep;
+    }
+
+    pub fn has_waves(&self) -> bool {
+        self.data_type.bits() & CompassDataType::WAVES.bits() != 0
+    }
+
+    //CoMPASS uses a fixed record length per channel, so the number of samples in the first hit
+    //gives the size of every hit in the file. Peek at it without consuming anything.
+    fn peek_wave_samples(&mut self) -> Result<usize, EVBError> {
+        let buffer = self.file_handle.fill_buf()?;
+        if buffer.len() < self.data_size_bytes + WAVE_HEADER_SIZE {
+            return Ok(0);
+        }
+        let (_, n_samples) = parse_u32(&buffer[(self.data_size_bytes + 1)..])?;
+        Ok(n_samples as usize)
+    }
+
+    /// Get the current hit, reading the next one from the file if the current hit was used.
+    /// At the end of the file a default hit is returned and `is_eof` becomes true.
     pub fn get_top_hit(&mut self) -> Result<&CompassData, EVBError> {
         if self.is_used {
             self.current_hit = match self.parse_top_hit() {
@@ -102,7 +154,7 @@ impl<'a> CompassFile<'a> {
 
     fn parse_top_hit(&mut self) -> Result<CompassData, EVBError> {
 
-        let mut raw_data = RawCompassData{board: 0, channel: 0, timestamp: 0, energy: 0, energy_calibrated: 0, energy_short: 0};
+        let mut raw_data = RawCompassData{board: 0, channel: 0, timestamp: 0, energy: 0, energy_calibrated: None, energy_short: 0, flags: 0};
 
         let mut dataword: Vec<u8> = vec![0; self.data_size_bytes];
         self.file_handle.read_exact(&mut dataword)?;
@@ -115,25 +167,153 @@ impl<'a> CompassFile<'a> {
             (dataslice, raw_data.energy) = parse_u16(dataslice)?;
         }
         if self.data_type.bits() & CompassDataType::ENERGY_CALIBRATED.bits() != 0 {
-            (dataslice, raw_data.energy_calibrated) = parse_u64(dataslice)?;
+            let energy_calibrated;
+            (dataslice, energy_calibrated) = parse_u64(dataslice)?;
+            raw_data.energy_calibrated = Some(energy_calibrated);
         }
         if self.data_type.bits() & CompassDataType::ENERGY_SHORT.bits() != 0 {
             (dataslice, raw_data.energy_short) = parse_u16(dataslice)?;
         }
-        let (_dataslice, _flags) = parse_u32(dataslice)?;
+        (_, raw_data.flags) = parse_u32(dataslice)?;
+
+        let mut data = CompassData::new(&raw_data, &self.shift_map);
+        if self.has_waves() {
+            data.waveform = self.parse_waveform()?;
+        }
+        Ok(data)
+    }
+
+    //Waveform block: probe code (u8), number of samples (u32), samples (u16 each)
+    fn parse_waveform(&mut self) -> Result<Option<Waveform>, EVBError> {
+        let mut waveheader: [u8; WAVE_HEADER_SIZE] = [0; WAVE_HEADER_SIZE];
+        self.file_handle.read_exact(&mut waveheader)?;
+        let (waveslice, probe) = parse_u8(&waveheader)?;
+        let (_waveslice, n_samples) = parse_u32(waveslice)?;
+        //The number of samples comes straight from the file, so check it before allocating
+        if n_samples > MAX_WAVE_SAMPLES {
+            return Err(CompassDataError::WaveformTooLong(n_samples).into());
+        }
+        if 2 * n_samples as u64 > self.size_bytes {
+            return Err(CompassDataError::WaveformPastEndOfFile(n_samples).into());
+        }
+
+        //Waveforms which are not kept are skipped without being read into a buffer
+        let n_bytes = 2 * n_samples as u64;
+        if !self.keep_waves {
+            let skipped = std::io::copy(&mut (&mut self.file_handle).take(n_bytes), &mut std::io::sink())?;
+            if skipped != n_bytes {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "File ended within a waveform").into());
+            }
+            return Ok(None);
+        }
+
+        let mut sampleword: Vec<u8> = vec![0; n_bytes as usize];
+        self.file_handle.read_exact(&mut sampleword)?;
 
-        Ok(CompassData::new(&raw_data, &self.shift_map))
+        let samples: Vec<u16> = sampleword.chunks_exact(2)
+                                          .map(|word| u16::from_le_bytes([word[0], word[1]]))
+                                          .collect();
+        Ok(Some(Waveform { probe, samples }))
     }
 
     pub fn is_eof(&self) -> bool {
         return self.is_eof;
     }
 
+    /// Mark the current hit as used (consumed)
     pub fn set_hit_used(&mut self) {
         self.is_used = true;
     }
 
+    /// Number of hits in the file, estimated from the file size
     pub fn get_number_of_hits(&self) -> u64 {
-        self.size_bytes / (self.data_size_bytes as u64)
+        let mut hit_size = self.data_size_bytes as u64;
+        if self.has_waves() {
+            hit_size += (WAVE_HEADER_SIZE + 2 * self.wave_samples) as u64;
+        }
+        self.size_bytes / hit_size
+    }
+}
+/// Iterating a CompassFile takes its hits in file order, marking each one used
+impl<'a> Iterator for CompassFile<'a> {
+    type Item = Result<CompassData, EVBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_eof {
+            return None;
+        }
+        if let Err(e) = self.get_top_hit() {
+            return Some(Err(e));
+        }
+        if self.is_eof {
+            return None;
+        }
+        self.set_hit_used();
+        Some(Ok(std::mem::take(&mut self.current_hit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A file with energy and waveforms, holding one hit whose waveform has the given number of samples
+    //(with only as many samples actually written as given in samples_written)
+    fn make_wave_file(n_samples: u32, samples_written: usize) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend_from_slice(&(CompassDataType::ENERGY.bits() | CompassDataType::WAVES.bits()).to_le_bytes());
+        buffer.extend_from_slice(&1u16.to_le_bytes()); //board
+        buffer.extend_from_slice(&2u16.to_le_bytes()); //channel
+        buffer.extend_from_slice(&1000u64.to_le_bytes()); //timestamp
+        buffer.extend_from_slice(&100u16.to_le_bytes()); //energy
+        buffer.extend_from_slice(&0u32.to_le_bytes()); //flags
+        buffer.push(1); //probe
+        buffer.extend_from_slice(&n_samples.to_le_bytes());
+        (0..samples_written).for_each(|sample| buffer.extend_from_slice(&(sample as u16).to_le_bytes()));
+        buffer
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reads_waveform() {
+        let mut file = CompassFile::from_buffer(make_wave_file(4, 4), &None).unwrap();
+        file.set_keep_waves(true);
+        let hit = file.get_top_hit().unwrap();
+        assert_eq!(hit.waveform.as_ref().unwrap().samples, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn skips_waveforms_which_are_not_kept() {
+        //Two hits, so that the second one shows the first waveform was skipped in full
+        let mut buffer = make_wave_file(4, 4);
+        let hit = buffer[2..].to_vec();
+        buffer.extend_from_slice(&hit);
+        let mut file = CompassFile::from_buffer(buffer, &None).unwrap();
+        for _ in 0..2 {
+            let hit = file.get_top_hit().unwrap();
+            assert!(hit.waveform.is_none());
+            assert_eq!(hit.timestamp, 1.0);
+            file.set_hit_used();
+        }
+        file.get_top_hit().unwrap();
+        assert!(file.is_eof());
+    }
+
+    #[test]
+    fn truncated_skipped_waveform_ends_file() {
+        let mut file = CompassFile::from_buffer(make_wave_file(4, 3), &None).unwrap();
+        file.get_top_hit().unwrap();
+        assert!(file.is_eof());
+    }
+
+    #[test]
+    fn rejects_oversized_waveform() {
+        let mut file = CompassFile::from_buffer(make_wave_file(u32::MAX, 4), &None).unwrap();
+        assert!(matches!(file.get_top_hit(), Err(EVBError::CompassDataError(CompassDataError::WaveformTooLong(_)))));
+    }
+
+    #[test]
+    fn rejects_waveform_larger_than_file() {
+        let mut file = CompassFile::from_buffer(make_wave_file(MAX_WAVE_SAMPLES, 4), &None).unwrap();
+        assert!(matches!(file.get_top_hit(), Err(EVBError::CompassDataError(CompassDataError::WaveformPastEndOfFile(_)))));
+    }
+}
@@ -1,41 +1,76 @@
 use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
 use std::path::{PathBuf, Path};
 
 use flate2::read::GzDecoder;
 use polars::prelude::*;
 use std::sync::{Mutex, Arc};
+use std::sync::atomic::{AtomicI32, Ordering};
 use tar::Archive;
-use log::info;
+use log::{info, warn, error};
+use serde::{Serialize, Deserialize};
 
 use super::used_size::UsedSize;
 use super::channel_map::ChannelMap;
 use super::scaler_list::ScalerList;
 use super::shift_map::ShiftMap;
+use super::calibration_map::CalibrationMap;
 use super::compass_file::CompassFile;
+use super::hit_merger::HitMerger;
 use super::event_builder::EventBuilder;
-use super::sps_data::SPSData;
+use super::sps_data::{SPSData, EventContext};
 use super::error::EVBError;
 use super::nuclear_data::MassMap;
 use super::kinematics::{KineParameters, calculate_weights};
-
-//Maximum allowed size for a single dataframe: 8GB
-const MAX_USED_SIZE: usize = 8_000_000_000;
+use super::progress::{Progress, RunStatus};
+use super::waveform_data::{WaveformData, WaveformMode};
+use super::flag_policy::FlagPolicies;
+use super::geometry::DetectorGeometry;
+use super::hit_policy::HitPolicy;
+use super::excitation::{ExReconstructor, FocalPlaneCalibration};
+use super::job_control::JobControl;
+use super::output_writer::{BatchWriter, OutputFormat, OutputSettings, write_file};
+
+//Number of hits between progress updates
+const PROGRESS_UPDATE_HITS: u64 = 10_000;
+
+/// How the CoMPASS data is read out of the run archives
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ArchiveMode {
+    /// Unpack the archive to the unpack directory on disk, then read the files
+    #[default]
+    Unpack,
+    /// Read the files straight out of the archive into memory, no temporary files are written.
+    /// The archive is decompressed once; runs larger than the stream memory limit are unpacked instead.
+    Stream
+}
 
 #[derive(Debug)]
 struct RunParams<'a> {
     pub run_archive_path: PathBuf,
     pub unpack_dir_path: PathBuf,
-    pub output_file_path: PathBuf,
+    pub output_dir_path: PathBuf,
     pub scalerlist_file_path: Option<PathBuf>,
     pub scalerout_file_path: PathBuf,
+    pub geometry_file_path: PathBuf,
     pub nuc_map: &'a MassMap,
     pub channel_map: &'a ChannelMap,
     pub shift_map: &'a Option<ShiftMap>,
+    pub calibration_map: &'a Option<CalibrationMap>,
     pub coincidence_window: f64,
+    pub archive_mode: ArchiveMode,
+    pub stream_memory_mb: usize,
+    pub waveform_mode: WaveformMode,
+    pub flag_policies: &'a FlagPolicies,
+    pub geometry: &'a DetectorGeometry,
+    pub hit_policy: &'a HitPolicy,
+    pub ex_reconstructor: &'a Option<ExReconstructor>,
+    pub output: &'a OutputSettings,
     pub run_number: i32
 }
 
-fn clean_up_unpack_dir(unpack_dir: &Path) -> Result<(), EVBError> {
+/// Remove all files from the unpack directory
+pub fn clean_up_unpack_dir(unpack_dir: &Path) -> Result<(), EVBError> {
 
     for item in unpack_dir.read_dir()? {
         if let Ok(entry) = item {
@@ -48,142 +83,363 @@ fn clean_up_unpack_dir(unpack_dir: &Path) -> Result<(), EVBError> {
     Ok(())
 }
 
-fn write_dataframe(data: SPSData, filepath: &Path) -> Result<(), PolarsError> {
+//The format is picked from the extension of the file (parquet if it is not one of the output formats)
+fn write_series(columns: Vec<Series>, filepath: &Path) -> Result<(), PolarsError> {
     info!("Writing dataframe to disk at {}", filepath.display());
-    let columns : Vec<Series> = data.convert_to_series();
-    let mut df = DataFrame::new(columns)?;
-    let mut output_file = File::create(filepath)?;
-    ParquetWriter::new(&mut output_file).finish(&mut df)?;
-    Ok(())
+    let df = DataFrame::new(columns)?;
+    let settings = OutputSettings { format: OutputFormat::from_path(filepath).unwrap_or_default(), ..Default::default() };
+    write_file(&df, filepath, &settings)
+}
+
+/// Convert event built data to a dataframe and write it to a file. The format (parquet, Arrow IPC or CSV)
+/// is picked from the extension of the file, i.e. `.parquet`, `.arrow` or `.csv`.
+pub fn write_dataframe(data: SPSData, filepath: &Path) -> Result<(), PolarsError> {
+    write_series(data.convert_to_series(), filepath)
 }
 
-fn write_dataframe_fragment(data: SPSData, out_dir: &Path, run_number: &i32, frag_number: &i32) -> Result<(), PolarsError> {
-    let frag_file_path = out_dir.join(format!("run_{}_{}.parquet", run_number, frag_number));
-    write_dataframe(data, &frag_file_path)?;
+/// Write stored waveforms to a file, in the format given by the extension of the file (as in [`write_dataframe`])
+pub fn write_waveforms(data: WaveformData, filepath: &Path) -> Result<(), PolarsError> {
+    write_series(data.convert_to_series(), filepath)
+}
+
+//Write out a batch of events, along with their waveforms (if any were stored)
+fn write_batch(data_writer: &mut BatchWriter, waves_writer: &mut BatchWriter, data: SPSData, waves: WaveformData) -> Result<(), PolarsError> {
+    data_writer.write_batch(data.convert_to_series())?;
+    if !waves.is_empty() {
+        waves_writer.write_batch(waves.convert_to_series())?;
+    }
     Ok(())
 }
 
-//Main function which processes a single run archive and writes the resulting event built data to parquet file
-fn process_run(params: RunParams, k_params: &KineParameters, progress: Arc<Mutex<f32>>) -> Result<(), EVBError> {
-    //Protective, ensure no loose files
-    clean_up_unpack_dir(&params.unpack_dir_path)?;
+//Remove the partial output of a cancelled run (the batches written so far) and the unpacked files
+fn clean_up_cancelled_run(params: &RunParams, is_unpacked: bool, data_writer: BatchWriter, waves_writer: BatchWriter) -> Result<(), EVBError> {
+    data_writer.discard()?;
+    waves_writer.discard()?;
+    if is_unpacked {
+        clean_up_unpack_dir(&params.unpack_dir_path)?;
+    }
+    Ok(())
+}
 
-    let archive_file = File::open(&params.run_archive_path)?;
+/// Unpack a CoMPASS run archive (`run_<number>.tar.gz`) into the given directory
+pub fn unpack_run_archive(archive_path: &Path, unpack_dir: &Path) -> Result<(), EVBError> {
+    let archive_file = File::open(archive_path)?;
     let mut decompressed_archive = Archive::new(GzDecoder::new(archive_file));
-    decompressed_archive.unpack(&params.unpack_dir_path)?;
-
-    let mut scaler_list = match &params.scalerlist_file_path {
-        Some(path) => Some(ScalerList::new(path)?),
-        None => None
-    };
+    decompressed_archive.unpack(unpack_dir)?;
+    Ok(())
+}
 
-    //Collect all files from unpack, separate scalers from normal files
+/// Open all of the CoMPASS binary files in a directory. Use a HitMerger to read the hits of all of the files in time order.
+/// If a scaler list is given, scaler files are counted by the list and not returned.
+/// If keep_waves is true, waveforms are stored in the hits (for files which have them).
+pub fn open_compass_files<'a>(dir: &Path, shift_map: &'a Option<ShiftMap>, keep_waves: bool, scaler_list: &mut Option<ScalerList>) -> Result<Vec<CompassFile<'a>>, EVBError> {
     let mut files: Vec<CompassFile> = vec![];
-    let mut total_count: u64 = 0;
-    for item in params.unpack_dir_path.read_dir()? {
+    for item in dir.read_dir()? {
         let filepath = &item?.path();
-        match &mut scaler_list {
-            Some(list) => {
-                if list.read_scaler(filepath) {
-                    continue
-                }
+        if let Some(list) = scaler_list {
+            if list.read_scaler(filepath) {
+                continue
             }
-            None => ()
+        }
+
+        files.push(CompassFile::new(filepath, shift_map)?);
+        files.last_mut().unwrap().set_keep_waves(keep_waves);
+    }
+    Ok(files)
+}
+
+/// Same as open_compass_files, but reading the files directly out of the run archive rather than from unpacked files.
+/// A `.tar.gz` can only be decompressed front to back, so the archive is decompressed once and each file is read into
+/// memory. If the files add up to more than max_memory bytes, reading stops and None is returned, before anything
+/// is counted by the scaler list, so that the run can be unpacked instead.
+pub fn open_compass_archive<'a>(archive_path: &Path, shift_map: &'a Option<ShiftMap>, keep_waves: bool, scaler_list: &mut Option<ScalerList>, max_memory: u64) -> Result<Option<Vec<CompassFile<'a>>>, EVBError> {
+    let archive_file = File::open(archive_path)?;
+    let mut decompressed_archive = Archive::new(GzDecoder::new(BufReader::new(archive_file)));
+    let mut buffers: Vec<(String, Vec<u8>)> = vec![];
+    let mut total_size: u64 = 0;
+    for item in decompressed_archive.entries()? {
+        let mut entry = item?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = match entry.path()?.file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => continue
         };
-        
-        files.push(CompassFile::new(filepath, params.shift_map)?);
-        files.last_mut().unwrap().set_hit_used();
-        files.last_mut().unwrap().get_top_hit()?;
-        total_count += files.last().unwrap().get_number_of_hits();
+        total_size += entry.size();
+        if total_size > max_memory {
+            return Ok(None);
+        }
+        let mut buffer: Vec<u8> = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut buffer)?;
+        buffers.push((name, buffer));
     }
 
-    let mut evb = EventBuilder::new(&params.coincidence_window);
-    let mut analyzed_data = SPSData::default();
-    let x_weights = calculate_weights(&k_params, params.nuc_map);
+    let mut files: Vec<CompassFile> = vec![];
+    for (name, buffer) in buffers {
+        if let Some(list) = scaler_list {
+            if list.is_scaler(&name) {
+                let size = buffer.len() as u64;
+                list.read_scaler_reader(&name, Box::new(Cursor::new(buffer)), size);
+                continue
+            }
+        }
 
-    let mut earliest_file_index: Option<usize>;
+        files.push(CompassFile::from_buffer(buffer, shift_map)?);
+        files.last_mut().unwrap().set_keep_waves(keep_waves);
+    }
+    Ok(Some(files))
+}
 
-    let mut count: u64 = 0;
-    let mut flush_count: u64 = 0;
-    let flush_percent = 0.1;
-    let flush_val: u64 = ((total_count as f64) * flush_percent) as u64;
+/// Open the files of a run archive as set by the archive mode. A streamed run which is larger than max_memory (bytes)
+/// once decompressed is unpacked instead. Also returns whether the run was unpacked, in which case the unpack directory
+/// should be cleaned up once the files are dropped.
+pub fn open_run_archive<'a>(archive_path: &Path, unpack_dir: &Path, mode: ArchiveMode, max_memory: u64, shift_map: &'a Option<ShiftMap>,
+                            keep_waves: bool, scaler_list: &mut Option<ScalerList>) -> Result<(Vec<CompassFile<'a>>, bool), EVBError> {
+    if mode == ArchiveMode::Stream {
+        if let Some(files) = open_compass_archive(archive_path, shift_map, keep_waves, scaler_list, max_memory)? {
+            return Ok((files, false));
+        }
+        warn!("{} is larger than the stream memory limit of {} MB, so it is unpacked to disk instead", archive_path.display(), max_memory / 1_000_000);
+    }
 
-    let mut frag_number = 0;
+    //Protective, ensure no loose files
+    std::fs::create_dir_all(unpack_dir)?;
+    clean_up_unpack_dir(unpack_dir)?;
+    unpack_run_archive(archive_path, unpack_dir)?;
+    Ok((open_compass_files(unpack_dir, shift_map, keep_waves, scaler_list)?, true))
+}
 
-    loop {
-        //Bulk of the work ... look for the earliest hit in the file collection
-        earliest_file_index = Option::None;
-        for i in 0..files.len() {
-            if !files[i].is_eof() {
-                let hit = files[i].get_top_hit()?;
-                if hit.is_default() {
-                    continue;
-                }
+//Main function which processes a single run archive and writes the resulting event built data to parquet file.
+//Checks the job control between hits; on cancel the partial output is removed and EVBError::Cancelled returned.
+//If the run fails, the output writers are dropped unfinished, which removes the output written so far.
+fn process_run(params: RunParams, k_params: &KineParameters, progress: &Mutex<Progress>, control: &JobControl) -> Result<(), EVBError> {
+    let mut scaler_list = match &params.scalerlist_file_path {
+        Some(path) => Some(ScalerList::new(path)?),
+        None => None
+    };
 
-                earliest_file_index = match earliest_file_index {
-                    None => Some(i),
-                    Some(index) => {
-                        if hit.timestamp < files[index].get_top_hit()?.timestamp {
-                            Some(i)
-                        } else {
-                            Some(index)
-                        }
-                    }
-                };
-            }
-        }
+    //Collect all files from the archive, separate scalers from normal files
+    let keep_waves = params.waveform_mode == WaveformMode::Store;
+    let (files, is_unpacked) = open_run_archive(&params.run_archive_path, &params.unpack_dir_path, params.archive_mode,
+                                                params.stream_memory_mb as u64 * 1_000_000, params.shift_map, keep_waves, &mut scaler_list)?;
+    let mut hits = HitMerger::new(files)?;
+    let total_count: u64 = hits.get_number_of_hits();
+    match progress.lock() {
+        Ok(mut prog) => prog.set_total_hits(params.run_number, total_count),
+        Err(_) => return Err(EVBError::SyncError)
+    };
 
-        match earliest_file_index {
-            None => break, //This is how we exit, no more hits to be found
-            Some(i) => { //else we pop the earliest hit off to the event builder
-                let hit = files[i].get_top_hit()?;
-                evb.push_hit(hit);
-                files[i].set_hit_used();
-            }
-        }
+    let mut evb = EventBuilder::new(&params.coincidence_window);
+    let mut analyzed_data = SPSData::new(params.hit_policy.clone());
+    let mut waves = WaveformData::default();
+    let mut event_count: u64 = 0;
+    let flag_filter = params.flag_policies.get_filter();
+    let mut dropped_hits: u64 = 0;
+    let mut dropped_events: u64 = 0;
+    let context = EventContext {
+        channel_map: params.channel_map,
+        weights: calculate_weights(&k_params, params.nuc_map, params.geometry),
+        calibration: params.calibration_map.as_ref(),
+        geometry: params.geometry,
+        reconstructor: params.ex_reconstructor.as_ref()
+    };
 
-        if evb.is_event_ready() {
-            analyzed_data.append_event(evb.get_ready_event(), params.channel_map, x_weights);
-            //Check to see if we need to fragment
-            if analyzed_data.get_used_size() >  MAX_USED_SIZE {
-                write_dataframe_fragment(analyzed_data, params.output_file_path.parent().unwrap(), &params.run_number, &frag_number)?;
-                //allocate new vector
-                analyzed_data = SPSData::default();
-                frag_number += 1;
-            }
+    let mut count: u64 = 0;
+
+    let prefix = format!("run_{}", params.run_number);
+    let mut data_writer = BatchWriter::new(&params.output_dir_path, &prefix, "", params.output);
+    let mut waves_writer = BatchWriter::new(&params.output_dir_path, &prefix, "_waves", params.output);
+    let mut is_cancelled = false;
+
+    //Bulk of the work ... pop the earliest hit in the file collection off to the event builder
+    while let Some(hit) = hits.pop_earliest_hit()? {
+        if control.wait_if_paused() {
+            is_cancelled = true;
+            break;
         }
 
         //Progress report
         count += 1;
-        if count == flush_val {
-            flush_count += 1;
-            count = 0;
-
+        if count % PROGRESS_UPDATE_HITS == 0 {
             match progress.lock() {
-                Ok(mut prog) => *prog  = (flush_count as f64 * flush_percent) as f32,
+                Ok(mut prog) => prog.set_counts(params.run_number, count, event_count),
                 Err(_) => return Err(EVBError::SyncError)
             };
         }
+
+        if flag_filter.should_drop_hit(&hit) {
+            dropped_hits += 1;
+            continue;
+        }
+        evb.push_hit(&hit);
+
+        if evb.is_event_ready() {
+            let event = evb.get_ready_event();
+            if flag_filter.should_drop_event(&event) {
+                dropped_events += 1;
+                continue;
+            }
+            if keep_waves {
+                waves.append_event(event_count, &event, params.channel_map);
+            }
+            analyzed_data.append_event(event, &context);
+            event_count += 1;
+            //Write out the batch once it is full, and start a new one
+            if params.output.is_batch_full(analyzed_data.rows, analyzed_data.get_used_size() + waves.get_used_size()) {
+                let batch = std::mem::replace(&mut analyzed_data, SPSData::new(params.hit_policy.clone()));
+                write_batch(&mut data_writer, &mut waves_writer, batch, std::mem::take(&mut waves))?;
+            }
+        }
     }
 
-    if frag_number == 0 {
-        write_dataframe(analyzed_data, &params.output_file_path)?;
-    } else {
-        write_dataframe_fragment(analyzed_data, params.output_file_path.parent().unwrap(), &params.run_number, &frag_number)?;
+    //The last event is never made ready by a following hit
+    if !is_cancelled {
+        if let Some(event) = evb.take_open_event() {
+            if flag_filter.should_drop_event(&event) {
+                dropped_events += 1;
+            } else {
+                if keep_waves {
+                    waves.append_event(event_count, &event, params.channel_map);
+                }
+                analyzed_data.append_event(event, &context);
+                event_count += 1;
+            }
+        }
     }
+
+    match progress.lock() {
+        Ok(mut prog) => prog.set_counts(params.run_number, count, event_count),
+        Err(_) => return Err(EVBError::SyncError)
+    };
+
+    if is_cancelled {
+        drop(hits);
+        clean_up_cancelled_run(&params, is_unpacked, data_writer, waves_writer)?;
+        return Err(EVBError::Cancelled);
+    }
+
+    if dropped_hits != 0 || dropped_events != 0 {
+        info!("Run {} dropped {} hits and {} events due to hit flags", params.run_number, dropped_hits, dropped_events);
+    }
+
+    write_batch(&mut data_writer, &mut waves_writer, analyzed_data, waves)?;
+    data_writer.finish()?;
+    waves_writer.finish()?;
     match scaler_list {
         Some(list) => list.write_scalers(&params.scalerout_file_path)?,
         None => ()
     };
+    params.geometry.write_to_file(&params.geometry_file_path)?;
 
     //To be safe, manually drop all files in unpack dir before deleting all the files
-    drop(files);
+    drop(hits);
 
-    clean_up_unpack_dir(&params.unpack_dir_path)?;
+    if is_unpacked {
+        clean_up_unpack_dir(&params.unpack_dir_path)?;
+    }
 
     return Ok(());
 }
 
+//Read-only resources shared by all of the workers
+struct SharedResources {
+    channel_map: ChannelMap,
+    mass_map: MassMap,
+    shift_map: Option<ShiftMap>,
+    calibration_map: Option<CalibrationMap>,
+    ex_reconstructor: Option<ExReconstructor>
+}
+
+//Each worker gets its own subdirectory of the unpack directory so that runs don't collide
+fn get_worker_unpack_dir(unpack_dir: &Path, worker: usize) -> PathBuf {
+    unpack_dir.join(format!("worker_{}", worker))
+}
+
+//Worker loop: take the next run from the shared queue until there are none left or the job is cancelled.
+//Errors (and panics) are contained to the run in which they occur; the failed runs are returned.
+fn run_worker(worker: usize, params: &ProcessParams, k_params: &KineParameters, resources: &SharedResources,
+              next_run: &AtomicI32, progress: &Mutex<Progress>, control: &JobControl) -> Vec<i32> {
+    let mut failed_runs: Vec<i32> = vec![];
+    let unpack_dir = get_worker_unpack_dir(&params.unpack_dir, worker);
+    if let Err(e) = std::fs::create_dir_all(&unpack_dir) {
+        error!("Worker {} could not create unpack directory {}: {}", worker, unpack_dir.display(), e);
+        return failed_runs;
+    }
+
+    loop {
+        if control.wait_if_paused() {
+            break;
+        }
+        let run = next_run.fetch_add(1, Ordering::SeqCst);
+        if run >= params.run_max {
+            break;
+        }
+
+        let local_params =  RunParams {
+            run_archive_path: params.archive_dir.join(format!("run_{}.tar.gz", run)),
+            unpack_dir_path: unpack_dir.clone(),
+            output_dir_path: params.output_dir.clone(),
+            scalerlist_file_path: params.scaler_list_filepath.clone(),
+            scalerout_file_path: params.output_dir.join(format!("run_{}_scalers.txt", run)),
+            geometry_file_path: params.output_dir.join(format!("run_{}_geometry.yaml", run)),
+            nuc_map: &resources.mass_map,
+            channel_map: &resources.channel_map,
+            shift_map: &resources.shift_map,
+            calibration_map: &resources.calibration_map,
+            coincidence_window: params.coincidence_window,
+            archive_mode: params.archive_mode,
+            stream_memory_mb: params.stream_memory_mb,
+            waveform_mode: params.waveform_mode,
+            flag_policies: &params.flag_policies,
+            geometry: &params.geometry,
+            hit_policy: &params.hit_policy,
+            ex_reconstructor: &resources.ex_reconstructor,
+            output: &params.output,
+            run_number: run
+        };
+
+        //Skip over run if it doesnt exist
+        let status = if !local_params.run_archive_path.exists() {
+            RunStatus::Missing
+        } else {
+            if let Ok(mut prog) = progress.lock() {
+                prog.set_status(run, RunStatus::Running);
+            }
+            info!("Worker {} processing run {}", worker, run);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| process_run(local_params, k_params, progress, control)));
+            match result {
+                Ok(Ok(_)) => RunStatus::Done,
+                Ok(Err(EVBError::Cancelled)) => {
+                    info!("Run {} was cancelled", run);
+                    RunStatus::Cancelled
+                }
+                Ok(Err(e)) => {
+                    error!("Run {} failed with error: {}", run, e);
+                    failed_runs.push(run);
+                    RunStatus::Failed(e.to_string())
+                }
+                Err(_) => {
+                    error!("Run {} failed, the processor panicked", run);
+                    failed_runs.push(run);
+                    RunStatus::Failed(String::from("Processor panicked"))
+                }
+            }
+        };
+
+        match progress.lock() {
+            Ok(mut prog) => prog.set_status(run, status),
+            Err(_) => error!("Run {} could not update progress", run)
+        };
+    }
+
+    if let Err(e) = std::fs::remove_dir_all(&unpack_dir) {
+        error!("Worker {} could not remove unpack directory {}: {}", worker, unpack_dir.display(), e);
+    }
+    failed_runs
+}
+
+/// Parameters for event building a range of runs
 pub struct ProcessParams {
     pub archive_dir: PathBuf,
     pub unpack_dir: PathBuf,
@@ -191,43 +447,170 @@ pub struct ProcessParams {
     pub channel_map_filepath: PathBuf,
     pub scaler_list_filepath: Option<PathBuf>,
     pub shift_map_filepath: Option<PathBuf>,
+    pub calibration_map_filepath: Option<PathBuf>,
+    pub fp_calibration_filepath: Option<PathBuf>,
+    pub mass_filepath: Option<PathBuf>,
     pub coincidence_window: f64,
     pub run_min: i32,
-    pub run_max: i32
+    pub run_max: i32,
+    pub n_workers: usize,
+    pub archive_mode: ArchiveMode,
+    /// Maximum memory (MB) a streamed run may use once decompressed; larger runs are unpacked instead
+    pub stream_memory_mb: usize,
+    pub waveform_mode: WaveformMode,
+    pub flag_policies: FlagPolicies,
+    pub geometry: DetectorGeometry,
+    pub hit_policy: HitPolicy,
+    pub output: OutputSettings
 }
 
-//Function which handles processing multiple runs, this is what the UI actually calls
-pub fn process_runs(params: ProcessParams, k_params: KineParameters, progress: Arc<Mutex<f32>>) -> Result<(), EVBError> {
-    let channel_map = ChannelMap::new(&params.channel_map_filepath)?;
-    let mass_map = MassMap::new()?;
-    let shift_map = match params.shift_map_filepath {
-        Some(path) => Some(ShiftMap::new(&path)?),
+/// Event build all runs in [run_min, run_max), writing a parquet file (and scaler file) for each.
+/// This is what the UI actually calls. Runs are distributed over n_workers threads; a failure in one
+/// run does not stop the others. Progress is reported per run. The job can be paused or cancelled through
+/// the control; on cancel the runs in progress are cleaned up and EVBError::JobCancelled gives the completed runs.
+pub fn process_runs(params: ProcessParams, mut k_params: KineParameters, progress: Arc<Mutex<Progress>>, control: Arc<JobControl>) -> Result<(), EVBError> {
+    let mass_map = MassMap::load(&params.mass_filepath)?;
+    info!("Using nuclear masses from {}", mass_map.get_evaluation());
+    k_params.resolve_reaction(&mass_map)?;
+    k_params.target.validate(k_params.sps_angle)?;
+    let ex_reconstructor = match &params.fp_calibration_filepath {
+        Some(path) => Some(ExReconstructor::new(FocalPlaneCalibration::new(path)?, &k_params, &mass_map)?),
         None => None
     };
-    for run in params.run_min..params.run_max {
-        let local_params =  RunParams {
-            run_archive_path: params.archive_dir.join(format!("run_{}.tar.gz", run)),
-            unpack_dir_path: params.unpack_dir.clone(),
-            output_file_path: params.output_dir.join(format!("run_{}.parquet", run)),
-            scalerlist_file_path: params.scaler_list_filepath.clone(),
-            scalerout_file_path: params.output_dir.join(format!("run_{}_scalers.txt", run)),
-            nuc_map: &mass_map,
-            channel_map: &channel_map,
-            shift_map: &shift_map,
-            coincidence_window: params.coincidence_window.clone(),
-            run_number: run.clone()
-        };
+    let resources = SharedResources {
+        channel_map: ChannelMap::new(&params.channel_map_filepath)?,
+        mass_map,
+        shift_map: match &params.shift_map_filepath {
+            Some(path) => Some(ShiftMap::new(path)?),
+            None => None
+        },
+        calibration_map: match &params.calibration_map_filepath {
+            Some(path) => Some(CalibrationMap::new(path)?),
+            None => None
+        },
+        ex_reconstructor
+    };
 
-        match progress.lock() {
-            Ok(mut prog) => *prog  = 0.0,
-            Err(_) => return Err(EVBError::SyncError)
+    match progress.lock() {
+        Ok(mut prog) => prog.reset(params.run_min, params.run_max),
+        Err(_) => return Err(EVBError::SyncError)
+    };
+
+    let next_run = AtomicI32::new(params.run_min);
+    let n_workers = params.n_workers.max(1);
+    let mut failed_runs: Vec<i32> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_workers).map(|worker| {
+            let (params, k_params, resources, next_run, progress, control) = (&params, &k_params, &resources, &next_run, progress.as_ref(), control.as_ref());
+            scope.spawn(move || run_worker(worker, params, k_params, resources, next_run, progress, control))
+        }).collect();
+
+        handles.into_iter()
+               .flat_map(|handle| handle.join().unwrap_or_default())
+               .collect()
+    });
+
+    if control.is_cancelled() {
+        return match progress.lock() {
+            Ok(mut prog) => {
+                prog.cancel_queued();
+                Err(EVBError::JobCancelled(prog.get_completed_runs()))
+            }
+            Err(_) => Err(EVBError::SyncError)
         };
+    }
 
-        //Skip over run if it doesnt exist
-        if local_params.run_archive_path.exists() {
-            process_run(local_params, &k_params, progress.clone())?;
+    if failed_runs.is_empty() {
+        Ok(())
+    } else {
+        failed_runs.sort();
+        Err(EVBError::RunError(failed_runs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+
+    const HEADER_ENERGY: u16 = 0x0001;
+
+    //Contents of a file of energy-only hits on board 0 of the given channel
+    fn make_file_data(channel: u16, n_hits: u64) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend_from_slice(&HEADER_ENERGY.to_le_bytes());
+        for hit in 0..n_hits {
+            buffer.extend_from_slice(&0u16.to_le_bytes());
+            buffer.extend_from_slice(&channel.to_le_bytes());
+            buffer.extend_from_slice(&(hit * 1000).to_le_bytes());
+            buffer.extend_from_slice(&100u16.to_le_bytes());
+            buffer.extend_from_slice(&0u32.to_le_bytes());
         }
+        buffer
     }
 
-    Ok(())
-}
\ No newline at end of file
+    //Write a run archive with a file per channel
+    fn make_archive(dir: &Path, files: &[(u16, u64)]) -> PathBuf {
+        let path = dir.join("run_1.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::fast()));
+        for (channel, n_hits) in files {
+            let data = make_file_data(*channel, *n_hits);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("DataR_CH{}@V1730_1_run_1.BIN", channel), data.as_slice()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    fn get_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spsevb_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn count_hits(files: &[CompassFile]) -> Vec<u64> {
+        files.iter().map(|file| file.get_number_of_hits()).collect()
+    }
+
+    #[test]
+    fn streams_every_file_of_the_archive() {
+        let dir = get_test_dir("stream_archive");
+        let archive = make_archive(&dir, &[(0, 3), (1, 5), (2, 1)]);
+        let files = open_compass_archive(&archive, &None, false, &mut None, u64::MAX).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(count_hits(&files), [3, 5, 1]);
+    }
+
+    #[test]
+    fn stops_streaming_past_the_memory_limit() {
+        let dir = get_test_dir("stream_limit");
+        let archive = make_archive(&dir, &[(0, 3), (1, 5)]);
+        //Each hit is 18 bytes (16, plus the energy), after the 2 byte header
+        let total_size = (2 + 3 * 18) + (2 + 5 * 18);
+        let fits = open_compass_archive(&archive, &None, false, &mut None, total_size).unwrap();
+        let too_large = open_compass_archive(&archive, &None, false, &mut None, total_size - 1).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(fits.is_some());
+        assert!(too_large.is_none());
+    }
+
+    #[test]
+    fn unpacks_runs_too_large_to_stream() {
+        let dir = get_test_dir("stream_fallback");
+        let archive = make_archive(&dir, &[(0, 3), (1, 5)]);
+        let unpack_dir = dir.join("unpack");
+        let (streamed, streamed_unpacked) = open_run_archive(&archive, &unpack_dir, ArchiveMode::Stream, u64::MAX, &None, false, &mut None).unwrap();
+        let (fallback, fallback_unpacked) = open_run_archive(&archive, &unpack_dir, ArchiveMode::Stream, 10, &None, false, &mut None).unwrap();
+        let mut fallback_hits = count_hits(&fallback);
+        drop(fallback);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!streamed_unpacked);
+        assert_eq!(count_hits(&streamed), [3, 5]);
+        assert!(fallback_unpacked);
+        fallback_hits.sort(); //The unpacked files are read in directory order
+        assert_eq!(fallback_hits, [3, 5]);
+    }
+}
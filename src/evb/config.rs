@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use super::compass_run::{ProcessParams, ArchiveMode};
+use super::kinematics::KineParameters;
+use super::energy_loss::TargetError;
+use super::waveform_data::WaveformMode;
+use super::flag_policy::FlagPolicies;
+use super::geometry::DetectorGeometry;
+use super::hit_policy::HitPolicy;
+use super::output_writer::OutputSettings;
+use super::ws::{Workspace, WorkspaceError};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    FileError(std::io::Error),
+    SerializerError(serde_yaml::Error),
+    WorkspaceError(WorkspaceError),
+    TargetError(TargetError),
+    MissingWorkspace,
+    MissingChannelMap,
+    MissingScalerList,
+    BadRunRange(i32, i32)
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(value: std::io::Error) -> Self {
+        ConfigError::FileError(value)
+    }
+}
+
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(value: serde_yaml::Error) -> Self {
+        ConfigError::SerializerError(value)
+    }
+}
+
+impl From<WorkspaceError> for ConfigError {
+    fn from(value: WorkspaceError) -> Self {
+        ConfigError::WorkspaceError(value)
+    }
+}
+
+impl From<TargetError> for ConfigError {
+    fn from(value: TargetError) -> Self {
+        ConfigError::TargetError(value)
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::FileError(x) => write!(f, "Config had a file I/O error: {}", x),
+            ConfigError::SerializerError(x) => write!(f, "Config had a serializer error: {}", x),
+            ConfigError::WorkspaceError(x) => write!(f, "Config had a workspace error: {}", x),
+            ConfigError::TargetError(x) => write!(f, "Config has an invalid target: {}", x),
+            ConfigError::MissingWorkspace => write!(f, "Config does not specify a workspace"),
+            ConfigError::MissingChannelMap => write!(f, "Config does not specify a channel map"),
+            ConfigError::MissingScalerList => write!(f, "Config does not specify a scaler list"),
+            ConfigError::BadRunRange(min, max) => write!(f, "Config has an invalid run range: min {} is greater than max {}", min, max)
+        }
+    }
+}
+
+impl Error for ConfigError {
+
+}
+
+//The full set of user inputs for an event building job. This is what gets saved to/loaded from YAML,
+//by both the GUI and the command line interface.
+//Missing fields take their default values, so that older configs can still be loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppParams {
+    pub workspace: Option<Workspace>,
+    pub channel_map: Option<PathBuf>,
+    pub scaler_list: Option<PathBuf>,
+    pub shift_map: Option<PathBuf>,
+    pub calibration_map: Option<PathBuf>,
+    pub fp_calibration: Option<PathBuf>,
+    pub mass_file: Option<PathBuf>,
+    pub kinematics: KineParameters,
+    pub coincidence_window: f64,
+    pub run_min: i32,
+    pub run_max: i32,
+    pub n_workers: usize,
+    pub archive_mode: ArchiveMode,
+    /// Maximum memory (MB) a streamed run may use once decompressed; larger runs are unpacked instead
+    pub stream_memory_mb: usize,
+    pub waveform_mode: WaveformMode,
+    pub flag_policies: FlagPolicies,
+    pub geometry: DetectorGeometry,
+    pub hit_policy: HitPolicy,
+    pub output: OutputSettings
+}
+
+impl Default for AppParams {
+    fn default() -> Self {
+        AppParams {
+            workspace: None,
+            channel_map: None,
+            scaler_list: None,
+            shift_map: None,
+            calibration_map: None,
+            fp_calibration: None,
+            mass_file: None,
+            kinematics: KineParameters::default(),
+            coincidence_window: 3.0e3,
+            run_min: 0,
+            run_max: 0,
+            n_workers: 1,
+            archive_mode: ArchiveMode::default(),
+            stream_memory_mb: 4_000,
+            waveform_mode: WaveformMode::default(),
+            flag_policies: FlagPolicies::default(),
+            geometry: DetectorGeometry::default(),
+            hit_policy: HitPolicy::default(),
+            output: OutputSettings::default()
+        }
+    }
+}
+
+impl AppParams {
+    pub fn read_from_file(path: &Path) -> Result<Self, ConfigError> {
+        let yaml_str = std::fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str::<AppParams>(&yaml_str)?)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<(), ConfigError> {
+        let mut config = File::create(path)?;
+        let yaml_str = serde_yaml::to_string(self)?;
+        config.write_all(yaml_str.as_bytes())?;
+        Ok(())
+    }
+
+    //Check that everything needed to run is specified and convert to the parameters used by process_runs
+    pub fn get_process_params(&self) -> Result<ProcessParams, ConfigError> {
+        let workspace = match &self.workspace {
+            Some(ws) => ws,
+            None => return Err(ConfigError::MissingWorkspace)
+        };
+        let channel_map = match &self.channel_map {
+            Some(path) => path,
+            None => return Err(ConfigError::MissingChannelMap)
+        };
+        if self.scaler_list.is_none() {
+            return Err(ConfigError::MissingScalerList);
+        }
+        if self.run_min > self.run_max {
+            return Err(ConfigError::BadRunRange(self.run_min, self.run_max));
+        }
+        self.kinematics.target.validate(self.kinematics.sps_angle)?;
+
+        Ok(ProcessParams {
+            archive_dir: workspace.get_archive_dir()?,
+            unpack_dir: workspace.get_unpack_dir()?,
+            output_dir: workspace.get_output_dir()?,
+            channel_map_filepath: channel_map.clone(),
+            scaler_list_filepath: self.scaler_list.clone(),
+            shift_map_filepath: self.shift_map.clone(),
+            calibration_map_filepath: self.calibration_map.clone(),
+            fp_calibration_filepath: self.fp_calibration.clone(),
+            mass_filepath: self.mass_file.clone(),
+            coincidence_window: self.coincidence_window,
+            run_min: self.run_min,
+            run_max: self.run_max + 1, //Make it [run_min, run_max]
+            n_workers: self.n_workers,
+            archive_mode: self.archive_mode,
+            stream_memory_mb: self.stream_memory_mb,
+            waveform_mode: self.waveform_mode,
+            flag_policies: self.flag_policies.clone(),
+            geometry: self.geometry.clone(),
+            hit_policy: self.hit_policy.clone(),
+            output: self.output.clone()
+        })
+    }
+}
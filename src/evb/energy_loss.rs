@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::sync::OnceLock;
+use serde::{Serialize, Deserialize};
+
+//Mean excitation energies of the elements, shipped in etc/ and built in to the executable
+const EXCITATION_TABLE: &str = include_str!("../../etc/MeanExcitationEnergies.txt");
+static EXCITATION_MAP: OnceLock<HashMap<u32, f64>> = OnceLock::new();
+
+const BETHE_K: f64 = 0.307075; //MeV cm^2/mol
+const ELECTRON_MASS: f64 = 0.51099895000; //MeV
+const FINE_STRUCTURE: f64 = 1.0 / 137.035999;
+const UG2G: f64 = 1.0e-6;
+const STEPS_PER_LAYER: usize = 100;
+//Smallest cosine of the angle between a path and the target normal, below which a layer is crossed (nearly) edge-on
+const MIN_PATH_COSINE: f64 = 1.0e-3;
+
+#[derive(Debug, Clone)]
+pub enum TargetError {
+    BadReactionLayer(usize, usize),
+    EdgeOnBeam(f64),
+    EdgeOnEjectile(f64, f64)
+}
+
+impl Display for TargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetError::BadReactionLayer(layer, n_layers) => write!(f, "Target reaction layer {} does not exist; the target has {} layers", layer, n_layers),
+            TargetError::EdgeOnBeam(angle) => write!(f, "Target angle {} deg has the beam crossing the target edge-on", angle),
+            TargetError::EdgeOnEjectile(angle, sps_angle) => write!(f, "Target angle {} deg with SPS angle {} deg has the ejectile leaving the target edge-on", angle, sps_angle)
+        }
+    }
+}
+
+impl Error for TargetError {
+
+}
+
+fn get_excitation_map() -> &'static HashMap<u32, f64> {
+    EXCITATION_MAP.get_or_init(|| {
+        EXCITATION_TABLE.lines()
+                        .skip(1)
+                        .filter_map(|line| {
+                            let entries: Vec<&str> = line.split_whitespace().collect();
+                            if entries.len() < 3 {
+                                return None;
+                            }
+                            Some((entries[0].parse().ok()?, entries[2].parse().ok()?))
+                        })
+                        .collect()
+    })
+}
+
+//Mean excitation energy in MeV. Elements missing from the table use the Bloch approximation.
+fn get_mean_excitation(z: u32) -> f64 {
+    let ev = match get_excitation_map().get(&z) {
+        Some(value) => *value,
+        None => {
+            let zf = z as f64;
+            if z < 13 { 12.0 * zf + 7.0 } else { 9.76 * zf + 58.8 * zf.powf(-0.19) }
+        }
+    };
+    ev * 1.0e-6
+}
+
+/// An element in a target compound, given by Z, A and the number of atoms in the compound
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TargetElement {
+    pub z: u32,
+    pub a: u32,
+    pub count: u32
+}
+
+/// A layer of the target, a compound with a thickness in ug/cm^2
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TargetLayer {
+    pub compound: Vec<TargetElement>,
+    pub thickness: f64
+}
+
+impl TargetLayer {
+    //Electronic stopping power in MeV cm^2/g of an ion (charge z, mass in MeV) with the given kinetic energy (MeV).
+    //Bethe-Bloch with the Pierce-Blann effective charge, using Bragg additivity for compounds. Below the energy where the
+    //Bethe logarithm becomes small the formula is not valid, and the stopping power is taken to scale as sqrt(E) instead.
+    //This limit is ~0.1 MeV/u in carbon up to ~1 MeV/u in gold, and the approximation below it is only rough (see the README).
+    fn get_stopping_power(&self, ke: f64, z: u32, mass: f64) -> f64 {
+        let total_mass: f64 = self.compound.iter().map(|e| (e.a * e.count) as f64).sum();
+        if total_mass == 0.0 || ke <= 0.0 {
+            return 0.0;
+        }
+
+        self.compound.iter()
+            .map(|element| {
+                let fraction = (element.a * element.count) as f64 / total_mass;
+                fraction * bethe_bloch(ke, z, mass, element.z, element.a)
+            })
+            .sum()
+    }
+}
+
+fn bethe_bloch(ke: f64, z: u32, mass: f64, target_z: u32, target_a: u32) -> f64 {
+    let excitation = get_mean_excitation(target_z);
+    let log_term = |t: f64| {
+        let gamma = 1.0 + t / mass;
+        let beta2 = 1.0 - 1.0 / (gamma * gamma);
+        ((2.0 * ELECTRON_MASS * beta2 * gamma * gamma / excitation).ln() - beta2, beta2)
+    };
+
+    //Lowest energy where the formula is used, where the logarithm is ~1
+    let beta2_min = std::f64::consts::E * excitation / (2.0 * ELECTRON_MASS);
+    let ke_min = mass * (1.0 / (1.0 - beta2_min).sqrt() - 1.0);
+    let (eval_ke, scale) = if ke < ke_min { (ke_min, (ke / ke_min).sqrt()) } else { (ke, 1.0) };
+
+    let (log_value, beta2) = log_term(eval_ke);
+    let zf = z as f64;
+    let z_eff = zf * (1.0 - (-0.95 * beta2.sqrt() / (FINE_STRUCTURE * zf.powf(2.0 / 3.0))).exp());
+    scale * BETHE_K * z_eff * z_eff * (target_z as f64) / (target_a as f64) / beta2 * log_value
+}
+
+//Integrate the energy of an ion through a thickness (g/cm^2) of a layer. Positive direction loses energy,
+//negative recovers the energy lost (for working backwards from a measured energy). Uses the midpoint method.
+fn integrate_layer(layer: &TargetLayer, mut ke: f64, z: u32, mass: f64, thickness: f64, sign: f64) -> f64 {
+    let dx = thickness / (STEPS_PER_LAYER as f64);
+    for _ in 0..STEPS_PER_LAYER {
+        let half = ke - sign * 0.5 * dx * layer.get_stopping_power(ke, z, mass);
+        ke -= sign * dx * layer.get_stopping_power(half.max(0.0), z, mass);
+        if ke <= 0.0 {
+            return 0.0;
+        }
+    }
+    ke
+}
+
+/// Target made of layers, with the reaction taking place in the middle of the reaction layer. The angle (deg) is between
+/// the beam and the normal of the target. Missing fields take their default values (no target, so no energy loss).
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetDescription {
+    pub layers: Vec<TargetLayer>,
+    pub reaction_layer: usize,
+    pub angle: f64
+}
+
+impl TargetDescription {
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Check that the reaction layer exists and that neither the beam nor the ejectile (at sps_angle, deg) would
+    /// cross the layers edge-on, where the path length through the target diverges. An empty target is always valid.
+    pub fn validate(&self, sps_angle: f64) -> Result<(), TargetError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if self.reaction_layer >= self.layers.len() {
+            return Err(TargetError::BadReactionLayer(self.reaction_layer, self.layers.len()));
+        }
+        if self.angle.to_radians().cos().abs() < MIN_PATH_COSINE {
+            return Err(TargetError::EdgeOnBeam(self.angle));
+        }
+        if (sps_angle - self.angle).to_radians().cos().abs() < MIN_PATH_COSINE {
+            return Err(TargetError::EdgeOnEjectile(self.angle, sps_angle));
+        }
+        Ok(())
+    }
+
+    //Segments (layer, thickness in g/cm^2 along the normal) crossed from the reaction point going either upstream
+    //(out of the front of the target) or downstream (out of the back), in the order they are crossed
+    fn get_exit_segments(&self, downstream: bool) -> Vec<(&TargetLayer, f64)> {
+        let mut segments: Vec<(&TargetLayer, f64)> = vec![];
+        let reaction_layer = match self.layers.get(self.reaction_layer) {
+            Some(layer) => layer,
+            None => return segments
+        };
+        segments.push((reaction_layer, 0.5 * reaction_layer.thickness * UG2G));
+        if downstream {
+            self.layers.iter().skip(self.reaction_layer + 1).for_each(|l| segments.push((l, l.thickness * UG2G)));
+        } else {
+            self.layers.iter().take(self.reaction_layer).rev().for_each(|l| segments.push((l, l.thickness * UG2G)));
+        }
+        segments
+    }
+
+    /// Kinetic energy (MeV) of the beam (charge z, mass in MeV) at the reaction point
+    pub fn get_beam_energy_at_reaction(&self, ke: f64, z: u32, mass: f64) -> f64 {
+        let path_factor = 1.0 / self.angle.to_radians().cos().abs();
+        let mut segments = self.get_exit_segments(false);
+        segments.reverse();
+        segments.iter().fold(ke, |e, (layer, thickness)| integrate_layer(layer, e, z, mass, thickness * path_factor, 1.0))
+    }
+
+    //Ejectile leaves at sps_angle to the beam; it goes out the back of the target if it moves forward relative to the target normal
+    fn get_ejectile_path(&self, sps_angle: f64) -> (Vec<(&TargetLayer, f64)>, f64) {
+        let cos_exit = (sps_angle - self.angle).to_radians().cos();
+        (self.get_exit_segments(cos_exit >= 0.0), 1.0 / cos_exit.abs())
+    }
+
+    /// Kinetic energy (MeV) of the ejectile (charge z, mass in MeV) after leaving the target, from its energy at the reaction point
+    pub fn get_ejectile_exit_energy(&self, ke: f64, z: u32, mass: f64, sps_angle: f64) -> f64 {
+        let (segments, path_factor) = self.get_ejectile_path(sps_angle);
+        segments.iter().fold(ke, |e, (layer, thickness)| integrate_layer(layer, e, z, mass, thickness * path_factor, 1.0))
+    }
+
+    /// Kinetic energy (MeV) of the ejectile (charge z, mass in MeV) at the reaction point, from its energy after leaving the target
+    pub fn get_ejectile_reaction_energy(&self, ke: f64, z: u32, mass: f64, sps_angle: f64) -> f64 {
+        let (segments, path_factor) = self.get_ejectile_path(sps_angle);
+        segments.iter().rev().fold(ke, |e, (layer, thickness)| integrate_layer(layer, e, z, mass, thickness * path_factor, -1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn carbon_target(reaction_layer: usize, angle: f64) -> TargetDescription {
+        let layer = TargetLayer { compound: vec![TargetElement { z: 6, a: 12, count: 1 }], thickness: 50.0 };
+        TargetDescription { layers: vec![layer.clone(), layer], reaction_layer, angle }
+    }
+
+    #[test]
+    fn empty_target_is_valid() {
+        let target = TargetDescription { reaction_layer: 3, angle: 90.0, ..Default::default() };
+        assert!(target.validate(90.0).is_ok());
+    }
+
+    #[test]
+    fn accepts_tilted_target() {
+        assert!(carbon_target(1, 30.0).validate(20.0).is_ok());
+        assert!(carbon_target(0, -45.0).validate(130.0).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_reaction_layer() {
+        assert!(matches!(carbon_target(2, 0.0).validate(20.0), Err(TargetError::BadReactionLayer(2, 2))));
+    }
+
+    #[test]
+    fn rejects_edge_on_beam() {
+        assert!(matches!(carbon_target(0, 90.0).validate(20.0), Err(TargetError::EdgeOnBeam(_))));
+        assert!(matches!(carbon_target(0, -90.0).validate(20.0), Err(TargetError::EdgeOnBeam(_))));
+    }
+
+    const PROTON_MASS: f64 = 938.272;
+    const DEUTERON_MASS: f64 = 1875.613;
+    const ALPHA_MASS: f64 = 3727.379;
+
+    #[test]
+    fn proton_stopping_power_matches_pstar() {
+        //PSTAR (NIST) electronic stopping powers of liquid water, MeV cm^2/g. Bethe-Bloch without shell corrections
+        //and with Bragg additivity of H and O is expected to agree to within 3% at these energies.
+        let water = TargetLayer { compound: vec![TargetElement { z: 1, a: 1, count: 2 }, TargetElement { z: 8, a: 16, count: 1 }], thickness: 0.0 };
+        for (ke, pstar) in [(10.0, 45.67), (100.0, 7.289)] {
+            let stopping = water.get_stopping_power(ke, 1, PROTON_MASS);
+            assert!((stopping / pstar - 1.0).abs() < 0.03, "{} MeV: {} vs PSTAR {}", ke, stopping, pstar);
+        }
+    }
+
+    #[test]
+    fn deuteron_loss_in_carbon() {
+        //A 16 MeV deuteron has the velocity of an 8 MeV proton, so it has the same stopping power (~49 MeV cm^2/g in carbon,
+        //PSTAR graphite agrees to a few percent), and loses ~2.5 keV in 50 ug/cm^2
+        let carbon = &carbon_target(0, 0.0).layers[0];
+        let deuteron = carbon.get_stopping_power(16.0, 1, DEUTERON_MASS);
+        let proton = carbon.get_stopping_power(16.0 * PROTON_MASS / DEUTERON_MASS, 1, PROTON_MASS);
+        assert!((deuteron / proton - 1.0).abs() < 1.0e-9);
+        assert!((deuteron / 49.0 - 1.0).abs() < 0.03, "{}", deuteron);
+
+        let loss = 16.0 - integrate_layer(carbon, 16.0, 1, DEUTERON_MASS, carbon.thickness * UG2G, 1.0);
+        assert!((loss / (deuteron * carbon.thickness * UG2G) - 1.0).abs() < 0.01, "{} MeV", loss);
+        assert!((loss / 2.45e-3 - 1.0).abs() < 0.03, "{} MeV", loss);
+    }
+
+    #[test]
+    fn ejectile_energy_round_trips() {
+        let mut target = carbon_target(0, 30.0);
+        target.layers[1] = TargetLayer { compound: vec![TargetElement { z: 79, a: 197, count: 1 }], thickness: 100.0 };
+        //Includes energies below the Bethe-Bloch limit in gold, where the sqrt(E) approximation is used
+        for (ke, z, mass) in [(0.3, 1, PROTON_MASS), (16.0, 1, DEUTERON_MASS), (1.0, 2, ALPHA_MASS), (20.0, 2, ALPHA_MASS)] {
+            for sps_angle in [20.0, 150.0] {
+                let exit = target.get_ejectile_exit_energy(ke, z, mass, sps_angle);
+                assert!(exit < ke);
+                let reaction = target.get_ejectile_reaction_energy(exit, z, mass, sps_angle);
+                assert!((reaction - ke).abs() < 1.0e-9, "{} MeV came back as {} MeV", ke, reaction);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_edge_on_ejectile() {
+        assert!(matches!(carbon_target(0, 30.0).validate(120.0), Err(TargetError::EdgeOnEjectile(_, _))));
+        assert!(matches!(carbon_target(0, 0.0).validate(-90.0), Err(TargetError::EdgeOnEjectile(_, _))));
+    }
+}
@@ -4,19 +4,32 @@ use std::error::Error;
 use super::channel_map::{ChannelMapError};
 use super::nuclear_data::MassError;
 use super::shift_map::ShiftError;
+use super::calibration_map::CalibrationError;
+use super::excitation::ExcitationError;
+use super::reaction::ReactionError;
+use super::energy_loss::TargetError;
+use super::compass_data::CompassDataError;
 use std::fmt::Display;
 
 #[derive(Debug)]
 pub enum EVBError {
     CompressorError(DecompressError),
-    WavesError,
     FileError(std::io::Error),
     ParserError,
+    CompassDataError(CompassDataError),
     ChannelError(ChannelMapError),
     DataFrameError(PolarsError),
     MassMapError(MassError),
     ShiftMapError(ShiftError),
-    SyncError
+    CalibrationMapError(CalibrationError),
+    ExcitationError(ExcitationError),
+    ReactionError(ReactionError),
+    TargetError(TargetError),
+    SerializerError(serde_yaml::Error),
+    SyncError,
+    Cancelled,
+    JobCancelled(Vec<i32>),
+    RunError(Vec<i32>)
 }
 
 impl From<std::io::Error> for EVBError {
@@ -31,6 +44,12 @@ impl From<DecompressError> for EVBError {
     }
 }
 
+impl From<CompassDataError> for EVBError {
+    fn from(value: CompassDataError) -> Self {
+        EVBError::CompassDataError(value)
+    }
+}
+
 impl From<ChannelMapError> for EVBError {
     fn from(err: ChannelMapError) -> EVBError {
         EVBError::ChannelError(err)
@@ -55,18 +74,56 @@ impl From<ShiftError> for EVBError {
     }
 }
 
+impl From<CalibrationError> for EVBError {
+    fn from(value: CalibrationError) -> Self {
+        EVBError::CalibrationMapError(value)
+    }
+}
+
+impl From<ExcitationError> for EVBError {
+    fn from(value: ExcitationError) -> Self {
+        EVBError::ExcitationError(value)
+    }
+}
+
+impl From<ReactionError> for EVBError {
+    fn from(value: ReactionError) -> Self {
+        EVBError::ReactionError(value)
+    }
+}
+
+impl From<TargetError> for EVBError {
+    fn from(value: TargetError) -> Self {
+        EVBError::TargetError(value)
+    }
+}
+
+impl From<serde_yaml::Error> for EVBError {
+    fn from(value: serde_yaml::Error) -> Self {
+        EVBError::SerializerError(value)
+    }
+}
+
 impl Display for EVBError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             EVBError::CompressorError(x) => write!(f, "Run had a decompression error: {}", x),
-            EVBError::WavesError => write!(f, "Run found a file with waveform data, which is not supported!"),
             EVBError::FileError(x) => write!(f, "Run had a file I/O error: {}", x),
             EVBError::ParserError => write!(f, "Run had an error parsing the data from files"),
+            EVBError::CompassDataError(x) => write!(f, "Run had an error in the CoMPASS data: {}", x),
             EVBError::ChannelError(x) => write!(f, "Run had an error occur with the channel map: {}", x),
             EVBError::DataFrameError(x) => write!(f, "Run had an error using polars: {}", x),
             EVBError::MassMapError(x) => write!(f, "Run had an error with the mass data: {}", x),
             EVBError::ShiftMapError(x) => write!(f, "Run had an error with the shift map: {}", x),
-            EVBError::SyncError => write!(f, "Run was unable to access shared progress resource")
+            EVBError::CalibrationMapError(x) => write!(f, "Run had an error with the calibration map: {}", x),
+            EVBError::ExcitationError(x) => write!(f, "Run had an error with the focal plane calibration: {}", x),
+            EVBError::ReactionError(x) => write!(f, "Run had an error with the reaction: {}", x),
+            EVBError::TargetError(x) => write!(f, "Run had an error with the target: {}", x),
+            EVBError::SerializerError(x) => write!(f, "Run had an error serializing to yaml: {}", x),
+            EVBError::SyncError => write!(f, "Run was unable to access shared progress resource"),
+            EVBError::Cancelled => write!(f, "Run was cancelled"),
+            EVBError::JobCancelled(x) => write!(f, "Job was cancelled, runs {:?} were completed", x),
+            EVBError::RunError(x) => write!(f, "Runs {:?} failed, see the log for details", x)
         }
     }
 }
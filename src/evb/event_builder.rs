@@ -1,5 +1,6 @@
 use super::compass_data::CompassData;
 
+/// Groups time ordered hits into events using a fixed coincidence window (ns) which starts at the first hit of each event
 #[derive(Debug)]
 pub struct EventBuilder {
     coincidence_window: f64,
@@ -23,6 +24,8 @@ impl EventBuilder {
         return self.is_event_ready;
     }
 
+    /// Add the next hit (in time). If the hit falls outside the window, the current event is made ready
+    /// and the hit starts a new event.
     pub fn push_hit(&mut self, hit: &CompassData) {
         if self.event.is_empty() {
             self.event.push(hit.clone());
@@ -39,8 +42,44 @@ impl EventBuilder {
         }
     }
 
+    /// Take the most recently completed event
     pub fn get_ready_event(&mut self) -> Vec<CompassData> {
         self.is_event_ready = false;
         return self.ready_event.clone();
     }
-}
\ No newline at end of file
+
+    /// Take the event which is still open, if any. Once the hits run out the last event is never made ready by a
+    /// following hit, so it has to be taken with this to not be lost.
+    pub fn take_open_event(&mut self) -> Option<Vec<CompassData>> {
+        if self.event.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.event))
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_hit(timestamp: f64) -> CompassData {
+        CompassData { timestamp, ..Default::default() }
+    }
+
+    #[test]
+    fn last_event_is_taken_after_the_hits_run_out() {
+        let mut evb = EventBuilder::new(&100.0);
+        let mut events: Vec<Vec<f64>> = vec![];
+        for time in [10.0, 50.0, 300.0, 350.0, 1000.0] {
+            evb.push_hit(&make_hit(time));
+            if evb.is_event_ready() {
+                events.push(evb.get_ready_event().iter().map(|hit| hit.timestamp).collect());
+            }
+        }
+        assert_eq!(events, [vec![10.0, 50.0], vec![300.0, 350.0]]);
+
+        let last = evb.take_open_event().expect("The last hit starts an event");
+        assert_eq!(last.iter().map(|hit| hit.timestamp).collect::<Vec<f64>>(), [1000.0]);
+        assert!(evb.take_open_event().is_none());
+    }
+}
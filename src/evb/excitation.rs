@@ -0,0 +1,131 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::kinematics::{KineParameters, ReactionData, calculate_ejectile_ke_from_rho, calculate_excitation, remove_ejectile_loss};
+use super::nuclear_data::MassMap;
+
+const MAX_INVERSION_ITERATIONS: usize = 50;
+const INVERSION_TOLERANCE: f64 = 1.0e-6; //mm
+
+#[derive(Debug)]
+pub enum ExcitationError {
+    FileError(std::io::Error),
+    SerializerError(serde_yaml::Error),
+    NoCoefficients,
+    InvalidReaction(String)
+}
+
+impl From<std::io::Error> for ExcitationError {
+    fn from(value: std::io::Error) -> Self {
+        ExcitationError::FileError(value)
+    }
+}
+
+impl From<serde_yaml::Error> for ExcitationError {
+    fn from(value: serde_yaml::Error) -> Self {
+        ExcitationError::SerializerError(value)
+    }
+}
+
+impl Display for ExcitationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExcitationError::FileError(x) => write!(f, "Focal plane calibration had an IO error: {}", x),
+            ExcitationError::SerializerError(x) => write!(f, "Focal plane calibration could not parse yaml: {}", x),
+            ExcitationError::NoCoefficients => write!(f, "Focal plane calibration has no coefficients"),
+            ExcitationError::InvalidReaction(x) => write!(f, "Focal plane calibration cannot be used with the reaction {}", x)
+        }
+    }
+}
+
+impl std::error::Error for ExcitationError {
+
+}
+
+/// Polynomial calibration of the focal plane position Xavg (mm) to the radius of curvature rho (cm) in the SPS,
+/// rho = c0 + c1*Xavg + c2*Xavg^2 + ... Stored as YAML. The uncertainties of the coefficients are optional.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FocalPlaneCalibration {
+    pub coefficients: Vec<f64>,
+    #[serde(default)]
+    pub uncertainties: Vec<f64>
+}
+
+impl FocalPlaneCalibration {
+    pub fn new(path: &Path) -> Result<Self, ExcitationError> {
+        let yaml_str = std::fs::read_to_string(path)?;
+        let calibration = serde_yaml::from_str::<FocalPlaneCalibration>(&yaml_str)?;
+        if calibration.coefficients.is_empty() {
+            return Err(ExcitationError::NoCoefficients);
+        }
+        Ok(calibration)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<(), ExcitationError> {
+        let mut file = File::create(path)?;
+        let yaml_str = serde_yaml::to_string(self)?;
+        file.write_all(yaml_str.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn get_rho(&self, xavg: f64) -> f64 {
+        //Horner's method
+        self.coefficients.iter().rev().fold(0.0, |acc, c| acc * xavg + c)
+    }
+
+    /// Invert the calibration, finding the Xavg (mm) for a rho (cm) with Newton's method starting from the center of the
+    /// focal plane. Returns None if the solution is not found.
+    pub fn get_xavg(&self, rho: f64) -> Option<f64> {
+        let mut xavg: f64 = 0.0;
+        for _ in 0..MAX_INVERSION_ITERATIONS {
+            let derivative: f64 = self.coefficients.iter().enumerate().skip(1).map(|(p, c)| (p as f64) * c * xavg.powi(p as i32 - 1)).sum();
+            if derivative == 0.0 {
+                return None;
+            }
+            let step = (self.get_rho(xavg) - rho) / derivative;
+            xavg -= step;
+            if step.abs() < INVERSION_TOLERANCE {
+                return Some(xavg);
+            }
+        }
+        None
+    }
+}
+
+/// Values reconstructed from the focal plane position of an event. The ejectile energy is at the reaction point,
+/// corrected for the energy lost leaving the target.
+#[derive(Debug, Clone, Copy)]
+pub struct Reconstruction {
+    pub rho: f64,
+    pub ejectile_ke: f64,
+    pub ex: f64
+}
+
+/// Reconstructs the excitation energy of the residual from Xavg, using a focal plane calibration and the reaction
+#[derive(Debug, Clone)]
+pub struct ExReconstructor {
+    calibration: FocalPlaneCalibration,
+    kinematics: KineParameters,
+    reaction: ReactionData
+}
+
+impl ExReconstructor {
+    pub fn new(calibration: FocalPlaneCalibration, kinematics: &KineParameters, nuc_map: &MassMap) -> Result<Self, ExcitationError> {
+        let reaction = match ReactionData::new(kinematics, nuc_map) {
+            Some(rxn) => rxn,
+            None => return Err(ExcitationError::InvalidReaction(kinematics.generate_rxn_eqn(nuc_map)))
+        };
+        Ok(ExReconstructor { calibration, kinematics: kinematics.clone(), reaction })
+    }
+
+    pub fn reconstruct(&self, xavg: f64) -> Reconstruction {
+        let rho = self.calibration.get_rho(xavg);
+        let exit_ke = calculate_ejectile_ke_from_rho(&self.kinematics, &self.reaction, rho);
+        let ejectile_ke = remove_ejectile_loss(&self.kinematics, &self.reaction, exit_ke);
+        let ex = calculate_excitation(&self.kinematics, &self.reaction, ejectile_ke);
+        Reconstruction { rho, ejectile_ke, ex }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt::Display;
+use serde::{Serialize, Deserialize};
+
+use super::compass_data::{CompassData, CompassFlags};
+
+/// What to do with a hit which has a given flag set
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum FlagAction {
+    #[default]
+    Keep,
+    DropHit,
+    DropEvent
+}
+
+impl Display for FlagAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlagAction::Keep => write!(f, "Keep"),
+            FlagAction::DropHit => write!(f, "Drop Hit"),
+            FlagAction::DropEvent => write!(f, "Drop Event")
+        }
+    }
+}
+
+/// Policies for the CoMPASS hit flag conditions which mark bad hits
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlagPolicies {
+    pub pile_up: FlagAction,
+    pub saturation: FlagAction,
+    pub lost_trigger: FlagAction,
+    pub fake_event: FlagAction
+}
+
+impl FlagPolicies {
+    //Mask of all flags whose policy is the given action
+    fn get_mask(&self, action: FlagAction) -> CompassFlags {
+        let mut mask = CompassFlags::empty();
+        if self.pile_up == action {
+            mask |= CompassFlags::PILE_UP;
+        }
+        if self.saturation == action {
+            mask |= CompassFlags::SATURATION_IN_GATE | CompassFlags::INPUT_SATURATING;
+        }
+        if self.lost_trigger == action {
+            mask |= CompassFlags::TRIGGER_LOST | CompassFlags::N_TRIGGERS_LOST;
+        }
+        if self.fake_event == action {
+            mask |= CompassFlags::FAKE_EVENT;
+        }
+        mask
+    }
+
+    pub fn get_filter(&self) -> FlagFilter {
+        FlagFilter { drop_hit_mask: self.get_mask(FlagAction::DropHit), drop_event_mask: self.get_mask(FlagAction::DropEvent) }
+    }
+}
+
+/// Precomputed masks from a set of FlagPolicies, used to filter hits and events
+#[derive(Debug, Clone, Copy)]
+pub struct FlagFilter {
+    drop_hit_mask: CompassFlags,
+    drop_event_mask: CompassFlags
+}
+
+impl FlagFilter {
+    pub fn should_drop_hit(&self, hit: &CompassData) -> bool {
+        hit.flags.intersects(self.drop_hit_mask)
+    }
+
+    pub fn should_drop_event(&self, event: &[CompassData]) -> bool {
+        event.iter().any(|hit| hit.flags.intersects(self.drop_event_mask))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::compass_data::RawCompassData;
+
+    fn make_hit(flags: u32) -> CompassData {
+        CompassData::new(&RawCompassData { board: 0, channel: 0, timestamp: 0, energy: 0, energy_calibrated: None, energy_short: 0, flags }, &None)
+    }
+
+    #[test]
+    fn decodes_flag_word() {
+        let hit = make_hit(0x8000 | 0x0080 | 0x0008);
+        assert!(hit.flags.contains(CompassFlags::PILE_UP | CompassFlags::SATURATION_IN_GATE | CompassFlags::FAKE_EVENT));
+        assert!(!hit.flags.contains(CompassFlags::TRIGGER_LOST));
+    }
+
+    #[test]
+    fn drop_decisions_per_policy() {
+        //(policy, flag word, drop hit, drop event)
+        let cases = [
+            (FlagPolicies::default(), 0x8000, false, false),
+            (FlagPolicies { pile_up: FlagAction::DropHit, ..Default::default() }, 0x8000, true, false),
+            (FlagPolicies { pile_up: FlagAction::DropEvent, ..Default::default() }, 0x8000, false, true),
+            (FlagPolicies { pile_up: FlagAction::DropHit, ..Default::default() }, 0x0000, false, false),
+            (FlagPolicies { saturation: FlagAction::DropHit, ..Default::default() }, 0x0080, true, false),
+            (FlagPolicies { saturation: FlagAction::DropHit, ..Default::default() }, 0x0400, true, false),
+            (FlagPolicies { lost_trigger: FlagAction::DropEvent, ..Default::default() }, 0x0020, false, true),
+            (FlagPolicies { lost_trigger: FlagAction::DropEvent, ..Default::default() }, 0x0040, false, true),
+            (FlagPolicies { fake_event: FlagAction::DropHit, ..Default::default() }, 0x0008, true, false),
+            (FlagPolicies { fake_event: FlagAction::DropHit, ..Default::default() }, 0x8000, false, false),
+            (FlagPolicies { pile_up: FlagAction::DropHit, fake_event: FlagAction::DropEvent, ..Default::default() }, 0x8008, true, true)
+        ];
+        for (policies, flags, drop_hit, drop_event) in cases {
+            let filter = policies.get_filter();
+            let hit = make_hit(flags);
+            assert_eq!(filter.should_drop_hit(&hit), drop_hit, "{:?} flags {:#x}", policies, flags);
+            assert_eq!(filter.should_drop_event(&[make_hit(0), hit]), drop_event, "{:?} flags {:#x}", policies, flags);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::num::ParseFloatError;
+use std::path::Path;
+
+use super::excitation::FocalPlaneCalibration;
+use super::energy_loss::TargetError;
+use super::kinematics::{KineParameters, ReactionData, calculate_ejectile_ke, calculate_ejectile_ke_from_rho, calculate_excitation, calculate_rho};
+use super::kinematics::{apply_ejectile_loss, remove_ejectile_loss};
+use super::nuclear_data::MassMap;
+
+#[derive(Debug)]
+pub enum FitError {
+    FileError(std::io::Error),
+    ParseError(ParseFloatError),
+    MissingColumn(usize),
+    InvalidReaction(String),
+    ForbiddenState(f64),
+    NotEnoughPoints(usize, usize),
+    SingularFit,
+    TargetError(TargetError)
+}
+
+impl From<std::io::Error> for FitError {
+    fn from(value: std::io::Error) -> Self {
+        FitError::FileError(value)
+    }
+}
+
+impl From<ParseFloatError> for FitError {
+    fn from(value: ParseFloatError) -> Self {
+        FitError::ParseError(value)
+    }
+}
+
+impl From<TargetError> for FitError {
+    fn from(value: TargetError) -> Self {
+        FitError::TargetError(value)
+    }
+}
+
+impl Display for FitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FitError::FileError(x) => write!(f, "Focal plane fit had an IO error: {}", x),
+            FitError::ParseError(x) => write!(f, "Focal plane fit could not parse peak list: {}", x),
+            FitError::MissingColumn(x) => write!(f, "Focal plane fit peak list line {} needs at least Xavg and Ex", x),
+            FitError::InvalidReaction(x) => write!(f, "Focal plane fit cannot be done for the reaction {}", x),
+            FitError::ForbiddenState(x) => write!(f, "Focal plane fit state at {} MeV is not kinematically allowed", x),
+            FitError::NotEnoughPoints(n, order) => write!(f, "Focal plane fit has {} points, which is not enough for a polynomial of order {}", n, order),
+            FitError::SingularFit => write!(f, "Focal plane fit could not be solved (points may be degenerate)"),
+            FitError::TargetError(x) => write!(f, "Focal plane fit cannot be done for the target: {}", x)
+        }
+    }
+}
+
+impl std::error::Error for FitError {
+
+}
+
+/// A peak in the focal plane spectrum identified with a known state
+#[derive(Debug, Clone)]
+pub struct CalibrationPoint {
+    pub xavg: f64, //mm
+    pub xavg_uncertainty: f64, //mm, 0 if not known
+    pub ex: f64 //MeV
+}
+
+/// Read a list of calibration points. The first line is a header; each following line is the Xavg centroid (mm),
+/// the excitation energy (MeV) of the state, and optionally the uncertainty of the centroid (mm).
+pub fn read_calibration_points(path: &Path) -> Result<Vec<CalibrationPoint>, FitError> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut junk = String::new();
+    let mut points: Vec<CalibrationPoint> = vec![];
+
+    reader.read_line(&mut junk)?;
+    for (number, line) in reader.lines().enumerate() {
+        let line_str = line?;
+        let entries: Vec<&str> = line_str.split_whitespace().collect();
+        if entries.is_empty() {
+            continue;
+        } else if entries.len() < 2 {
+            return Err(FitError::MissingColumn(number + 2));
+        }
+        points.push(CalibrationPoint {
+            xavg: entries[0].parse()?,
+            ex: entries[1].parse()?,
+            xavg_uncertainty: match entries.get(2) {
+                Some(value) => value.parse()?,
+                None => 0.0
+            }
+        });
+    }
+    Ok(points)
+}
+
+/// Result for a single calibration point
+#[derive(Debug, Clone)]
+pub struct FitResidual {
+    pub point: CalibrationPoint,
+    pub rho_expected: f64,
+    pub rho_fit: f64,
+    pub ex_fit: f64,
+    pub residual_kev: f64
+}
+
+/// Result of a focal plane fit
+#[derive(Debug, Clone)]
+pub struct FocalPlaneFit {
+    pub reaction: String,
+    pub mass_evaluation: String,
+    pub calibration: FocalPlaneCalibration,
+    pub is_weighted: bool,
+    pub chi_square: f64, //Residual sum of squares (cm^2) if the fit is unweighted
+    pub dof: usize,
+    pub residuals: Vec<FitResidual>
+}
+
+impl FocalPlaneFit {
+    /// Write a report of the fit, with the residual of each point in keV
+    pub fn write_report(&self, path: &Path) -> Result<(), FitError> {
+        let mut file = File::create(path)?;
+        writeln!(file, "Reaction: {}", self.reaction)?;
+        writeln!(file, "Mass evaluation: {}", self.mass_evaluation)?;
+        writeln!(file, "Polynomial order: {}", self.calibration.coefficients.len() - 1)?;
+        for (i, (c, u)) in self.calibration.coefficients.iter().zip(self.calibration.uncertainties.iter()).enumerate() {
+            writeln!(file, "c{}: {:e} +/- {:e}", i, c, u)?;
+        }
+        if self.dof == 0 {
+            writeln!(file, "No degrees of freedom, uncertainties are not meaningful")?;
+        } else if self.is_weighted {
+            writeln!(file, "Chi-square/dof: {:.4} / {} = {:.4}", self.chi_square, self.dof, self.chi_square / self.dof as f64)?;
+        } else {
+            writeln!(file, "Unweighted fit, residual sum of squares: {:e} cm^2 ({} dof)", self.chi_square, self.dof)?;
+        }
+        writeln!(file, "{:>12} {:>12} {:>12} {:>14} {:>14} {:>12} {:>14}",
+                 "Xavg(mm)", "dXavg(mm)", "Ex(MeV)", "RhoExp(cm)", "RhoFit(cm)", "ExFit(MeV)", "Residual(keV)")?;
+        for res in self.residuals.iter() {
+            writeln!(file, "{:>12.4} {:>12.4} {:>12.4} {:>14.5} {:>14.5} {:>12.4} {:>14.2}",
+                     res.point.xavg, res.point.xavg_uncertainty, res.point.ex, res.rho_expected, res.rho_fit, res.ex_fit, res.residual_kev)?;
+        }
+        Ok(())
+    }
+}
+
+//A pivot smaller than this, relative to the norm of the matrix, means the matrix is (numerically) singular
+const RELATIVE_PIVOT_TOLERANCE: f64 = 1.0e-12;
+
+//Invert a small square matrix with Gauss-Jordan elimination (partial pivoting)
+fn invert_matrix(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    //Infinity norm (largest absolute row sum), so that the singularity test does not depend on the scale of the matrix
+    let norm = matrix.iter().map(|row| row.iter().map(|v| v.abs()).sum::<f64>()).fold(0.0, f64::max);
+    let mut inverse: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))?;
+        if matrix[pivot][col].is_nan() || matrix[pivot][col].abs() <= RELATIVE_PIVOT_TOLERANCE * norm {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+
+        let scale = matrix[col][col];
+        for j in 0..n {
+            matrix[col][j] /= scale;
+            inverse[col][j] /= scale;
+        }
+        for row in 0..n {
+            if row == col {
+                continue;
+            }
+            let factor = matrix[row][col];
+            for j in 0..n {
+                matrix[row][j] -= factor * matrix[col][j];
+                inverse[row][j] -= factor * inverse[col][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+//Binomial coefficient, for the small orders of the fit
+fn choose(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+//Weighted linear least squares for a polynomial. Returns the coefficients and their covariance matrix.
+//Powers of Xavg (up to ~300 mm) span many orders of magnitude, which makes the normal equations badly conditioned,
+//so the fit is done in u = (x - center) / scale, which lies in [-1, 1], and the result is transformed back to powers of x.
+fn fit_polynomial(x: &[f64], y: &[f64], weights: &[f64], order: usize) -> Option<(Vec<f64>, Vec<Vec<f64>>)> {
+    let n_par = order + 1;
+    let center = x.iter().sum::<f64>() / x.len() as f64;
+    let scale = match x.iter().map(|xi| (xi - center).abs()).fold(0.0, f64::max) {
+        spread if spread > 0.0 => spread,
+        _ => 1.0
+    };
+
+    let mut normal = vec![vec![0.0; n_par]; n_par];
+    let mut rhs = vec![0.0; n_par];
+    for ((xi, yi), wi) in x.iter().zip(y.iter()).zip(weights.iter()) {
+        let u = (xi - center) / scale;
+        let powers: Vec<f64> = (0..n_par).map(|p| u.powi(p as i32)).collect();
+        for i in 0..n_par {
+            rhs[i] += wi * powers[i] * yi;
+            for j in 0..n_par {
+                normal[i][j] += wi * powers[i] * powers[j];
+            }
+        }
+    }
+    let scaled_covariance = invert_matrix(normal)?;
+    let scaled_coefficients: Vec<f64> = (0..n_par).map(|i| (0..n_par).map(|j| scaled_covariance[i][j] * rhs[j]).sum()).collect();
+
+    //((x - center) / scale)^k = sum over j of choose(k, j) x^j (-center)^(k - j) / scale^k
+    let transform: Vec<Vec<f64>> = (0..n_par).map(|j| (0..n_par).map(|k| {
+        if j > k { 0.0 } else { choose(k, j) * (-center).powi((k - j) as i32) / scale.powi(k as i32) }
+    }).collect()).collect();
+    let coefficients = (0..n_par).map(|j| (0..n_par).map(|k| transform[j][k] * scaled_coefficients[k]).sum()).collect();
+    let covariance = (0..n_par).map(|i| (0..n_par).map(|j| {
+        (0..n_par).map(|k| (0..n_par).map(|l| transform[i][k] * scaled_covariance[k][l] * transform[j][l]).sum::<f64>()).sum()
+    }).collect()).collect();
+    Some((coefficients, covariance))
+}
+
+fn evaluate_derivative(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().enumerate().skip(1).map(|(p, c)| (p as f64) * c * x.powi(p as i32 - 1)).sum()
+}
+
+/// Fit a polynomial of the given order for Xavg -> rho from peaks with known excitation energies.
+/// The expected rho of each state is calculated from the reaction. If every point has an Xavg uncertainty, the fit is
+/// weighted using the effective variance (the uncertainty propagated through the slope of a first unweighted fit);
+/// otherwise the fit is unweighted and the uncertainties are scaled by the residual variance.
+pub fn fit_focal_plane(points: &[CalibrationPoint], order: usize, kinematics: &KineParameters, nuc_map: &MassMap) -> Result<FocalPlaneFit, FitError> {
+    let reaction_str = kinematics.generate_rxn_eqn(nuc_map);
+    let reaction = match ReactionData::new(kinematics, nuc_map) {
+        Some(rxn) => rxn,
+        None => return Err(FitError::InvalidReaction(reaction_str))
+    };
+    kinematics.target.validate(kinematics.sps_angle)?;
+    if points.len() < order + 1 {
+        return Err(FitError::NotEnoughPoints(points.len(), order));
+    }
+
+    let mut rho_expected: Vec<f64> = vec![];
+    for point in points.iter() {
+        match calculate_ejectile_ke(kinematics, &reaction, point.ex) {
+            Some(ke) => rho_expected.push(calculate_rho(kinematics, &reaction, apply_ejectile_loss(kinematics, &reaction, ke))),
+            None => return Err(FitError::ForbiddenState(point.ex))
+        }
+    }
+    let x: Vec<f64> = points.iter().map(|p| p.xavg).collect();
+
+    let unit_weights = vec![1.0; points.len()];
+    let (mut coefficients, mut covariance) = match fit_polynomial(&x, &rho_expected, &unit_weights, order) {
+        Some(result) => result,
+        None => return Err(FitError::SingularFit)
+    };
+
+    let is_weighted = points.iter().all(|p| p.xavg_uncertainty > 0.0);
+    let mut weights = unit_weights;
+    if is_weighted {
+        weights = points.iter()
+                        .map(|p| {
+                            let sigma = evaluate_derivative(&coefficients, p.xavg) * p.xavg_uncertainty;
+                            1.0 / (sigma * sigma)
+                        })
+                        .collect();
+        (coefficients, covariance) = match fit_polynomial(&x, &rho_expected, &weights, order) {
+            Some(result) => result,
+            None => return Err(FitError::SingularFit)
+        };
+    }
+
+    let calibration = FocalPlaneCalibration { coefficients, uncertainties: vec![] };
+    let mut chi_square = 0.0;
+    let mut residuals: Vec<FitResidual> = vec![];
+    for ((point, rho), weight) in points.iter().zip(rho_expected.iter()).zip(weights.iter()) {
+        let rho_fit = calibration.get_rho(point.xavg);
+        let exit_ke = calculate_ejectile_ke_from_rho(kinematics, &reaction, rho_fit);
+        let ex_fit = calculate_excitation(kinematics, &reaction, remove_ejectile_loss(kinematics, &reaction, exit_ke));
+        chi_square += weight * (rho - rho_fit).powi(2);
+        residuals.push(FitResidual { point: point.clone(), rho_expected: *rho, rho_fit, ex_fit, residual_kev: (ex_fit - point.ex) * 1.0e3 });
+    }
+
+    //Without known uncertainties, estimate the scale of the errors from the scatter of the points
+    let dof = points.len() - (order + 1);
+    let scale = if is_weighted || dof == 0 { 1.0 } else { chi_square / dof as f64 };
+    let uncertainties = (0..=order).map(|i| (covariance[i][i] * scale).sqrt()).collect();
+
+    Ok(FocalPlaneFit {
+        reaction: reaction_str,
+        mass_evaluation: nuc_map.get_evaluation().to_string(),
+        calibration: FocalPlaneCalibration { uncertainties, ..calibration },
+        is_weighted,
+        chi_square,
+        dof,
+        residuals
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+        coefficients.iter().enumerate().map(|(p, c)| c * x.powi(p as i32)).sum()
+    }
+
+    #[test]
+    fn recovers_polynomial_coefficients() {
+        let cases: [&[f64]; 3] = [&[75.0, 0.04], &[75.0, 0.04, -2.0e-5], &[75.0, 0.04, -2.0e-5, 3.0e-8]];
+        let x: Vec<f64> = (0..9).map(|i| -200.0 + 50.0 * i as f64).collect();
+        for coefficients in cases {
+            let y: Vec<f64> = x.iter().map(|xi| evaluate(coefficients, *xi)).collect();
+            let order = coefficients.len() - 1;
+            //Weights do not change the solution for exact points
+            for weights in [vec![1.0; x.len()], (1..=x.len()).map(|i| i as f64).collect()] {
+                let (fit, _) = fit_polynomial(&x, &y, &weights, order).unwrap();
+                for (fitted, expected) in fit.iter().zip(coefficients.iter()) {
+                    assert!((fitted - expected).abs() <= 1.0e-9 * expected.abs(), "order {}: {:?} != {:?}", order, fit, coefficients);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_points_are_singular() {
+        assert!(invert_matrix(vec![vec![1.0, 2.0], vec![2.0, 4.0]]).is_none());
+        assert!(fit_polynomial(&[1.0, 1.0, 1.0], &[2.0, 3.0, 4.0], &[1.0; 3], 1).is_none());
+    }
+
+    #[test]
+    fn singularity_does_not_depend_on_scale() {
+        for scale in [1.0e-20, 1.0, 1.0e20] {
+            let regular = vec![vec![2.0 * scale, 1.0 * scale], vec![1.0 * scale, 3.0 * scale]];
+            let inverse = invert_matrix(regular).unwrap();
+            assert!((inverse[0][0] * scale - 0.6).abs() < 1.0e-12);
+            let nearly_singular = vec![vec![scale, scale], vec![scale, (1.0 + 1.0e-15) * scale]];
+            assert!(invert_matrix(nearly_singular).is_none(), "scale {}", scale);
+        }
+    }
+
+    #[test]
+    fn recovers_coefficients_far_from_the_center() {
+        //Peaks all on one side of the focal plane, where raw powers of Xavg are nearly collinear
+        let coefficients: [&[f64]; 3] = [&[75.0, 0.04, -2.0e-5], &[75.0, 0.04, -2.0e-5, 3.0e-8], &[75.0, 0.04, -2.0e-5, 3.0e-8, -1.0e-11]];
+        let x: Vec<f64> = (0..12).map(|i| 150.0 + 12.5 * i as f64).collect();
+        for expected in coefficients {
+            let y: Vec<f64> = x.iter().map(|xi| evaluate(expected, *xi)).collect();
+            let (fit, covariance) = fit_polynomial(&x, &y, &vec![1.0; x.len()], expected.len() - 1).unwrap();
+            for xi in x.iter() {
+                assert!((evaluate(&fit, *xi) - evaluate(expected, *xi)).abs() < 1.0e-9, "order {}: {:?}", expected.len() - 1, fit);
+            }
+            assert!(covariance.iter().enumerate().all(|(i, row)| row[i] > 0.0));
+        }
+    }
+
+    #[test]
+    fn recovers_focal_plane_calibration() {
+        let nuc_map = MassMap::new().unwrap();
+        let kinematics = KineParameters { target_z: 6, target_a: 12, projectile_z: 1, projectile_a: 2, ejectile_z: 1, ejectile_a: 1,
+                                          b_field: 8.0, sps_angle: 20.0, projectile_ke: 16.0, ..Default::default() };
+        let reaction = ReactionData::new(&kinematics, &nuc_map).unwrap();
+        //Place known 13C states on the focal plane with rho = c0 + c1 * Xavg
+        let coefficients = [80.0, 0.045];
+        let points: Vec<CalibrationPoint> = [0.0, 3.089, 3.685, 3.854, 6.864].iter().map(|ex| {
+            let ke = calculate_ejectile_ke(&kinematics, &reaction, *ex).unwrap();
+            let rho = calculate_rho(&kinematics, &reaction, apply_ejectile_loss(&kinematics, &reaction, ke));
+            CalibrationPoint { xavg: (rho - coefficients[0]) / coefficients[1], xavg_uncertainty: 0.0, ex: *ex }
+        }).collect();
+
+        let fit = fit_focal_plane(&points, 1, &kinematics, &nuc_map).unwrap();
+        assert!(!fit.is_weighted);
+        assert_eq!(fit.dof, 3);
+        for (fitted, expected) in fit.calibration.coefficients.iter().zip(coefficients.iter()) {
+            assert!((fitted - expected).abs() <= 1.0e-9 * expected.abs(), "{:?} != {:?}", fit.calibration.coefficients, coefficients);
+        }
+        for residual in fit.residuals.iter() {
+            assert!(residual.residual_kev.abs() < 1.0e-3, "{:?}", residual);
+        }
+
+        assert!(matches!(fit_focal_plane(&points[..2], 2, &kinematics, &nuc_map), Err(FitError::NotEnoughPoints(2, 2))));
+    }
+
+    #[test]
+    fn reads_calibration_points() {
+        let path = std::env::temp_dir().join(format!("spsevb_fp_points_{}.txt", std::process::id()));
+        std::fs::write(&path, "Xavg Ex dXavg\n-100.5 0.0 0.2\n\n50.0 3.089\n").unwrap();
+        let points = read_calibration_points(&path).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[0].xavg, points[0].ex, points[0].xavg_uncertainty), (-100.5, 0.0, 0.2));
+        assert_eq!((points[1].xavg, points[1].ex, points[1].xavg_uncertainty), (50.0, 3.089, 0.0));
+
+        std::fs::write(&path, "Xavg Ex dXavg\n-100.5 0.0\n12.0\n").unwrap();
+        let result = read_calibration_points(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(FitError::MissingColumn(3))));
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::error::EVBError;
+
+/// Constants of the focal plane detector and spectrograph optics used to calculate the focal plane physics fields.
+/// These change whenever the detector is rebuilt, so they are part of the config and are saved with each run.
+/// Missing fields take their default values (the current SPS setup).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectorGeometry {
+    pub x1_delay_ns_per_mm: f64, //Propagation of the front delay line
+    pub x2_delay_ns_per_mm: f64, //Propagation of the back delay line
+    pub delay_line_spacing: f64, //Distance between the front and back delay lines in mm, used for theta
+    pub anode_wire_distance: f64, //Distance between the anode wires in cm, used for the kinematic correction
+    pub dispersion: f64, // x-position/rho
+    pub magnification: f64 // in x-position
+}
+
+impl Default for DetectorGeometry {
+    fn default() -> Self {
+        DetectorGeometry {
+            x1_delay_ns_per_mm: 2.1,
+            x2_delay_ns_per_mm: 1.98,
+            delay_line_spacing: 36.0,
+            anode_wire_distance: 4.28625,
+            dispersion: 1.96,
+            magnification: 0.39
+        }
+    }
+}
+
+impl DetectorGeometry {
+    /// Write the geometry as YAML, so that the constants used for a run are kept with its output
+    pub fn write_to_file(&self, path: &Path) -> Result<(), EVBError> {
+        let mut file = File::create(path)?;
+        let yaml_str = serde_yaml::to_string(self)?;
+        file.write_all(yaml_str.as_bytes())?;
+        Ok(())
+    }
+}
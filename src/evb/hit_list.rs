@@ -0,0 +1,78 @@
+use strum::IntoEnumIterator;
+use strum_macros::{EnumIter, AsRefStr};
+use super::compass_data::CompassData;
+use super::used_size::UsedSize;
+
+//List columns of every hit in an event for each focal plane detector component. The variants are the column names.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Hash, Eq, PartialOrd, Ord, PartialEq, EnumIter, AsRefStr)]
+pub enum HitListField {
+    AnodeFrontHits,
+    AnodeBackHits,
+    ScintLeftHits,
+    ScintRightHits,
+    CathodeHits,
+    DelayFrontLeftHits,
+    DelayFrontRightHits,
+    DelayBackLeftHits,
+    DelayBackRightHits
+}
+
+impl HitListField {
+    //Returns a list of fields for iterating over
+    pub fn get_field_vec() -> Vec<HitListField> {
+        HitListField::iter().collect()
+    }
+}
+
+impl UsedSize for HitListField {
+    fn get_used_size(&self) -> usize {
+        std::mem::size_of::<HitListField>()
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialOrd, Ord, PartialEq, EnumIter, AsRefStr)]
+pub enum HitListSubField {
+    Energy,
+    EnergyCal,
+    Short,
+    Time,
+    Flags
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HitListData {
+    pub energies: Vec<f64>,
+    pub energies_calibrated: Vec<f64>,
+    pub energies_short: Vec<f64>,
+    pub times: Vec<f64>,
+    pub flags: Vec<u32>
+}
+
+impl UsedSize for HitListData {
+    fn get_used_size(&self) -> usize {
+        self.energies.get_used_size() +
+        self.energies_calibrated.get_used_size() +
+        self.energies_short.get_used_size() +
+        self.times.get_used_size() +
+        self.flags.get_used_size()
+    }
+}
+
+impl HitListData {
+    pub fn push(&mut self, hit: &CompassData) {
+        self.energies.push(hit.energy);
+        self.energies_calibrated.push(hit.energy_calibrated);
+        self.energies_short.push(hit.energy_short);
+        self.times.push(hit.timestamp);
+        self.flags.push(hit.flags.bits());
+    }
+
+    pub fn len(&self) -> usize {
+        self.energies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.energies.is_empty()
+    }
+}
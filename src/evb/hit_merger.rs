@@ -0,0 +1,161 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::binary_heap::PeekMut;
+
+use super::compass_data::CompassData;
+use super::compass_file::CompassFile;
+use super::error::EVBError;
+
+//The next hit from one of the files. Ordered so that the BinaryHeap (a max-heap) pops the earliest hit first.
+//Ties in time go to the file opened first.
+struct HeapEntry {
+    hit: CompassData,
+    file_index: usize
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.hit.timestamp.total_cmp(&self.hit.timestamp)
+             .then_with(|| other.file_index.cmp(&self.file_index))
+    }
+}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {
+
+}
+
+/// Merges the hits of a collection of CoMPASS files into a single stream in time order.
+///
+/// Each file is already in time order, so only the next hit of each file needs to be compared. These are kept in a
+/// binary heap keyed on timestamp, making each hit O(log(files)) rather than the O(files) of scanning every file.
+/// Iterating the merger gives the hits from earliest to latest.
+pub struct HitMerger<'a> {
+    files: Vec<CompassFile<'a>>,
+    heap: BinaryHeap<HeapEntry>
+}
+
+impl<'a> HitMerger<'a> {
+    /// Take the files and read the first hit of each
+    pub fn new(mut files: Vec<CompassFile<'a>>) -> Result<Self, EVBError> {
+        let mut heap = BinaryHeap::with_capacity(files.len());
+        for (file_index, file) in files.iter_mut().enumerate() {
+            if let Some(hit) = file.next() {
+                heap.push(HeapEntry { hit: hit?, file_index });
+            }
+        }
+        Ok(HitMerger { files, heap })
+    }
+
+    /// Total number of hits in the files, estimated from the file sizes
+    pub fn get_number_of_hits(&self) -> u64 {
+        self.files.iter().map(|f| f.get_number_of_hits()).sum()
+    }
+
+    /// Take the earliest remaining hit, replacing it with the next hit from the same file.
+    /// Returns None once all of the files are exhausted.
+    pub fn pop_earliest_hit(&mut self) -> Result<Option<CompassData>, EVBError> {
+        let mut top = match self.heap.peek_mut() {
+            Some(top) => top,
+            None => return Ok(None)
+        };
+        //Swapping in the next hit of the same file and letting the heap sift it down is cheaper than a pop and a push
+        match self.files[top.file_index].next() {
+            Some(hit) => Ok(Some(std::mem::replace(&mut top.hit, hit?))),
+            None => Ok(Some(PeekMut::pop(top).hit))
+        }
+    }
+}
+
+impl<'a> Iterator for HitMerger<'a> {
+    type Item = Result<CompassData, EVBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pop_earliest_hit().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evb::compass_data::generate_board_channel_uuid;
+
+    const HEADER_ENERGY: u16 = 0x0001;
+
+    //File of energy-only hits on board 0 of the given channel, one hit per timestamp (ps)
+    fn make_file(channel: u16, timestamps: &[u64]) -> CompassFile<'static> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend_from_slice(&HEADER_ENERGY.to_le_bytes());
+        for timestamp in timestamps {
+            buffer.extend_from_slice(&0u16.to_le_bytes());
+            buffer.extend_from_slice(&channel.to_le_bytes());
+            buffer.extend_from_slice(&timestamp.to_le_bytes());
+            buffer.extend_from_slice(&100u16.to_le_bytes());
+            buffer.extend_from_slice(&0u32.to_le_bytes());
+        }
+        CompassFile::from_buffer(buffer, &None).unwrap()
+    }
+
+    //Channel of each merged hit, with its timestamp in ps
+    fn merge(files: Vec<CompassFile<'static>>) -> Vec<(u32, u64)> {
+        HitMerger::new(files).unwrap()
+            .map(|hit| {
+                let hit = hit.unwrap();
+                let channel = (0..16).find(|c| generate_board_channel_uuid(&0, c) == hit.uuid).unwrap();
+                (channel, (hit.timestamp * 1000.0).round() as u64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_interleaved_files_in_time_order() {
+        let files = vec![
+            make_file(0, &[1000, 4000, 7000]),
+            make_file(1, &[2000, 5000, 8000]),
+            make_file(2, &[3000, 6000, 9000])
+        ];
+        let hits = merge(files);
+        let expected: Vec<(u32, u64)> = (1..10).map(|i| ((i as u32 - 1) % 3, i * 1000)).collect();
+        assert_eq!(hits, expected);
+    }
+
+    #[test]
+    fn equal_timestamps_go_to_first_file() {
+        let files = vec![
+            make_file(3, &[1000, 2000]),
+            make_file(1, &[1000, 2000]),
+            make_file(2, &[2000])
+        ];
+        assert_eq!(merge(files), vec![(3, 1000), (1, 1000), (3, 2000), (1, 2000), (2, 2000)]);
+    }
+
+    #[test]
+    fn exhausted_files_are_dropped() {
+        let files = vec![
+            make_file(0, &[1000]),
+            make_file(1, &[]),
+            make_file(2, &[2000, 3000, 4000])
+        ];
+        let mut merger = HitMerger::new(files).unwrap();
+        assert_eq!(merger.get_number_of_hits(), 4);
+        assert_eq!(merger.heap.len(), 2);
+
+        assert!(merger.pop_earliest_hit().unwrap().is_some());
+        assert_eq!(merger.heap.len(), 1);
+        let rest: Vec<f64> = merger.by_ref().map(|hit| hit.unwrap().timestamp).collect();
+        assert_eq!(rest.len(), 3);
+        assert!(merger.heap.is_empty());
+        assert!(merger.pop_earliest_hit().unwrap().is_none());
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::Display;
+use serde::{Serialize, Deserialize};
+
+use super::compass_data::CompassData;
+
+/// Which hit fills the single value columns when a focal plane channel has more than one hit in an event
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum HitSelection {
+    First,
+    #[default]
+    Last,
+    LargestEnergy,
+    ClosestToScint
+}
+
+impl Display for HitSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HitSelection::First => write!(f, "First"),
+            HitSelection::Last => write!(f, "Last"),
+            HitSelection::LargestEnergy => write!(f, "Largest Energy"),
+            HitSelection::ClosestToScint => write!(f, "Closest to Scint")
+        }
+    }
+}
+
+impl HitSelection {
+    /// Select a hit from the (time ordered) hits of one channel. scint_time is the reference for ClosestToScint;
+    /// if there is no reference the first hit is selected.
+    pub fn select<'a>(&self, hits: &[&'a CompassData], scint_time: Option<f64>) -> Option<&'a CompassData> {
+        match self {
+            HitSelection::First => hits.first().copied(),
+            HitSelection::Last => hits.last().copied(),
+            HitSelection::LargestEnergy => hits.iter().copied().max_by(|a, b| a.energy.total_cmp(&b.energy)),
+            HitSelection::ClosestToScint => match scint_time {
+                Some(time) => hits.iter().copied().min_by(|a, b| (a.timestamp - time).abs().total_cmp(&(b.timestamp - time).abs())),
+                None => hits.first().copied()
+            }
+        }
+    }
+}
+
+/// How multiple hits of a focal plane channel within an event are handled
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HitPolicy {
+    pub selection: HitSelection,
+    pub keep_all_hits: bool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_hit(timestamp: f64, energy: f64) -> CompassData {
+        CompassData { timestamp, energy, ..Default::default() }
+    }
+
+    #[test]
+    fn each_selection_chooses_expected_hit() {
+        //Time ordered hits: the largest energy is in the middle, and the hit at 200 is closest to the scint at 190
+        let hits = [make_hit(100.0, 50.0), make_hit(150.0, 300.0), make_hit(200.0, 20.0), make_hit(400.0, 80.0)];
+        let refs: Vec<&CompassData> = hits.iter().collect();
+        //(selection, scint time, timestamp of the expected hit)
+        let cases = [
+            (HitSelection::First, Some(190.0), 100.0),
+            (HitSelection::Last, Some(190.0), 400.0),
+            (HitSelection::LargestEnergy, Some(190.0), 150.0),
+            (HitSelection::ClosestToScint, Some(190.0), 200.0),
+            (HitSelection::ClosestToScint, Some(390.0), 400.0),
+            (HitSelection::ClosestToScint, None, 100.0)
+        ];
+        for (selection, scint_time, expected) in cases {
+            let selected = selection.select(&refs, scint_time).unwrap();
+            assert_eq!(selected.timestamp, expected, "{} with scint time {:?}", selection, scint_time);
+        }
+    }
+
+    #[test]
+    fn single_and_empty_channels() {
+        let hit = make_hit(100.0, 50.0);
+        for selection in [HitSelection::First, HitSelection::Last, HitSelection::LargestEnergy, HitSelection::ClosestToScint] {
+            assert_eq!(selection.select(&[&hit], Some(0.0)).unwrap().timestamp, 100.0);
+            assert!(selection.select(&[], Some(0.0)).is_none());
+        }
+    }
+}
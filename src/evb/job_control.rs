@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const PAUSE_POLL_MS: u64 = 100;
+
+/// Cooperative cancel and pause of a running job, shared between the caller (UI or CLI) and the processing threads.
+/// The processing threads check the state between hits.
+#[derive(Debug, Default)]
+pub struct JobControl {
+    cancelled: Arc<AtomicBool>,
+    paused: AtomicBool
+}
+
+impl JobControl {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// The flag set on cancel, for use with signal handlers (i.e. cancel on Ctrl-C)
+    pub fn get_cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Block while the job is paused. Returns true if the job has been cancelled.
+    pub fn wait_if_paused(&self) -> bool {
+        while self.is_paused() && !self.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(PAUSE_POLL_MS));
+        }
+        self.is_cancelled()
+    }
+}
@@ -1,12 +1,13 @@
 use super::nuclear_data::MassMap;
+use super::geometry::DetectorGeometry;
+use super::energy_loss::TargetDescription;
+use super::reaction::{ReactionError, parse_reaction};
 use serde::{Serialize, Deserialize};
 
 const C: f64 = 2.99792458e8; //speed of light in m/s
 const QBRHO2P: f64 = C * 1.0e-9; //convert charge (in units of e) * B (kG (tesla)) * rho (cm) to momentum in MeV
-const SPS_DISPERSION: f64 = 1.96; // x-position/rho
-const SPS_MAGNIFICATION: f64 = 0.39; // in x-position
-const SPS_DETECTOR_WIRE_DIST: f64 = 4.28625; //Distance between anode wires in SPS focal plane detector cm
 
+/// Reaction and spectrograph settings used for the kinematic correction
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KineParameters {
     pub target_z: u32,
@@ -18,6 +19,10 @@ pub struct KineParameters {
     pub b_field: f64, //kG
     pub sps_angle: f64, //deg
     pub projectile_ke: f64, //MeV
+    #[serde(default)]
+    pub target: TargetDescription,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<String> //Reaction equation (i.e. 12C(d,p)13C), overrides the Z, A values when given
 }
 
 impl Default for KineParameters {
@@ -32,6 +37,8 @@ impl Default for KineParameters {
             b_field: 7.9,
             sps_angle: 0.0,
             projectile_ke: 16.0,
+            target: TargetDescription::default(),
+            reaction: None
         }
     }
 }
@@ -45,6 +52,19 @@ impl KineParameters {
         self.target_a + self.projectile_a - self.ejectile_a
     }
 
+    /// If a reaction equation is given, parse it and set the Z, A values of the reaction from it
+    pub fn resolve_reaction(&mut self, nuc_map: &MassMap) -> Result<(), ReactionError> {
+        let eqn = match &self.reaction {
+            Some(eqn) => eqn,
+            None => return Ok(())
+        };
+        let rxn = parse_reaction(eqn, nuc_map)?;
+        (self.target_z, self.target_a) = rxn.target;
+        (self.projectile_z, self.projectile_a) = rxn.projectile;
+        (self.ejectile_z, self.ejectile_a) = rxn.ejectile;
+        Ok(())
+    }
+
     pub fn generate_rxn_eqn(&self, nuc_map: &MassMap) -> String {
         let targ_str = match nuc_map.get_data(&self.target_z, &self.target_a) {
             Some(data) => &data.isotope,
@@ -70,8 +90,8 @@ impl KineParameters {
     }
 }
 
-//Returns z-offset of focal plane in cm
-fn calculate_z_offset(params: &KineParameters, nuc_map: &MassMap) -> Option<f64> {
+/// Returns z-offset (cm) of the focal plane for the kinematics of the residual populated at excitation energy ex (MeV)
+pub fn calculate_z_offset(params: &KineParameters, nuc_map: &MassMap, geometry: &DetectorGeometry, ex: f64) -> Option<f64> {
     let target = match nuc_map.get_data(&params.target_z, &params.target_a){
         Some(data) => data,
         None => return None
@@ -89,12 +109,16 @@ fn calculate_z_offset(params: &KineParameters, nuc_map: &MassMap) -> Option<f64>
         None => return None
     };
 
+    //Energy loss in the target: the reaction happens with the beam energy at the reaction point
+    let beam_ke = params.target.get_beam_energy_at_reaction(params.projectile_ke, projectile.z, projectile.mass);
+
     let angle_rads = params.sps_angle.to_radians();
-    let q_val = target.mass + projectile.mass - ejectile.mass - residual.mass;
-    let term1 = (projectile.mass * ejectile.mass * params.projectile_ke).sqrt() / 
-                     (ejectile.mass + residual.mass) * angle_rads.cos();
-    let term2 = (params.projectile_ke * (residual.mass - projectile.mass) + residual.mass * q_val) /
-                     (ejectile.mass + residual.mass);
+    let residual_mass = residual.mass + ex;
+    let q_val = target.mass + projectile.mass - ejectile.mass - residual_mass;
+    let term1 = (projectile.mass * ejectile.mass * beam_ke).sqrt() / 
+                     (ejectile.mass + residual_mass) * angle_rads.cos();
+    let term2 = (beam_ke * (residual_mass - projectile.mass) + residual_mass * q_val) /
+                     (ejectile.mass + residual_mass);
 
     let mut ejectile_ke = term1 + (term1 * term1 + term2).sqrt();
     if ejectile_ke.is_nan() {
@@ -102,21 +126,115 @@ fn calculate_z_offset(params: &KineParameters, nuc_map: &MassMap) -> Option<f64>
     }
     ejectile_ke *= ejectile_ke;
 
-    let ejectile_p = (ejectile_ke * (ejectile_ke + 2.0 * ejectile.mass)).sqrt();
+    //and the ejectile loses energy on the way out of the target
+    let exit_ke = params.target.get_ejectile_exit_energy(ejectile_ke, ejectile.z, ejectile.mass, params.sps_angle);
+    let ejectile_p = (exit_ke * (exit_ke + 2.0 * ejectile.mass)).sqrt();
     let rho = ejectile_p /((ejectile.z as f64) * params.b_field * QBRHO2P);
-    let val = (projectile.mass * ejectile.mass * params.projectile_ke / ejectile_ke).sqrt();
-    let k = val * angle_rads.sin() / (ejectile.mass + residual.mass - val * angle_rads.cos());
-    return Some(-1.0 * rho * SPS_DISPERSION * SPS_MAGNIFICATION * k);
+    let val = (projectile.mass * ejectile.mass * beam_ke / ejectile_ke).sqrt();
+    let k = val * angle_rads.sin() / (ejectile.mass + residual_mass - val * angle_rads.cos());
+    return Some(-1.0 * rho * geometry.dispersion * geometry.magnification * k);
 }
 
-//Calculate weights for correcting focal plane position for kinematic shift
-//Returns tuple of weights where should be used like xavg = x1 * result.0 + x2 * result.1
-pub fn calculate_weights(params: &KineParameters, nuc_map: &MassMap) -> Option<(f64, f64)> {
-    let z_offset = match calculate_z_offset(params, nuc_map) {
+/// Calculate weights for correcting focal plane position for kinematic shift
+/// Returns tuple of weights where should be used like xavg = x1 * result.0 + x2 * result.1
+pub fn calculate_weights(params: &KineParameters, nuc_map: &MassMap, geometry: &DetectorGeometry) -> Option<(f64, f64)> {
+    let z_offset = match calculate_z_offset(params, nuc_map, geometry, 0.0) {
         Some(z) => z,
         None => return None
     };
-    let w1 = 0.5 - z_offset/SPS_DETECTOR_WIRE_DIST;
+    let w1 = 0.5 - z_offset/geometry.anode_wire_distance;
     let w2 = 1.0 - w1;
     Some((w1, w2))
 }
+
+/// Masses (MeV) of the nuclei in the reaction, looked up once from the MassMap, and the beam energy (MeV) at the
+/// reaction point (after energy loss in the target)
+#[derive(Debug, Clone)]
+pub struct ReactionData {
+    pub target_mass: f64,
+    pub projectile_mass: f64,
+    pub ejectile_mass: f64,
+    pub residual_mass: f64,
+    pub ejectile_z: u32,
+    pub beam_ke: f64
+}
+
+impl ReactionData {
+    /// Returns None if any of the nuclei are not in the mass table
+    pub fn new(params: &KineParameters, nuc_map: &MassMap) -> Option<Self> {
+        let target = nuc_map.get_data(&params.target_z, &params.target_a)?;
+        let projectile = nuc_map.get_data(&params.projectile_z, &params.projectile_a)?;
+        let ejectile = nuc_map.get_data(&params.ejectile_z, &params.ejectile_a)?;
+        let residual = nuc_map.get_data(&params.get_residual_z(), &params.get_residual_a())?;
+        Some(ReactionData {
+            target_mass: target.mass,
+            projectile_mass: projectile.mass,
+            ejectile_mass: ejectile.mass,
+            residual_mass: residual.mass,
+            ejectile_z: ejectile.z,
+            beam_ke: params.target.get_beam_energy_at_reaction(params.projectile_ke, projectile.z, projectile.mass)
+        })
+    }
+}
+
+/// Kinetic energy (MeV) of the ejectile at the SPS angle at the reaction point, populating the residual at excitation
+/// energy ex (MeV). Relativistic two-body kinematics; returns None if the state is not kinematically allowed.
+pub fn calculate_ejectile_ke(params: &KineParameters, rxn: &ReactionData, ex: f64) -> Option<f64> {
+    let angle_rads = params.sps_angle.to_radians();
+    let e_total = rxn.beam_ke + rxn.projectile_mass + rxn.target_mass;
+    let p_projectile = (rxn.beam_ke * (rxn.beam_ke + 2.0 * rxn.projectile_mass)).sqrt();
+    let residual_mass = rxn.residual_mass + ex;
+
+    //Conservation of four-momentum gives e_total * E3 - p_proj * cos(theta) * p3 = a/2, solved for E3
+    let s = e_total * e_total - p_projectile * p_projectile;
+    let a = s + rxn.ejectile_mass * rxn.ejectile_mass - residual_mass * residual_mass;
+    let b = p_projectile * angle_rads.cos();
+    let denom = e_total * e_total - b * b;
+    let discriminant = a * a - 4.0 * rxn.ejectile_mass * rxn.ejectile_mass * denom;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let ejectile_e = (a * e_total + b * discriminant.sqrt()) / (2.0 * denom);
+    let ejectile_ke = ejectile_e - rxn.ejectile_mass;
+    if ejectile_ke.is_nan() || ejectile_ke < 0.0 {
+        return None;
+    }
+    Some(ejectile_ke)
+}
+
+/// Excitation energy (MeV) of the residual from the kinetic energy (MeV) of the ejectile at the SPS angle at the
+/// reaction point (missing mass)
+pub fn calculate_excitation(params: &KineParameters, rxn: &ReactionData, ejectile_ke: f64) -> f64 {
+    let angle_rads = params.sps_angle.to_radians();
+    let e_total = rxn.beam_ke + rxn.projectile_mass + rxn.target_mass;
+    let p_projectile = (rxn.beam_ke * (rxn.beam_ke + 2.0 * rxn.projectile_mass)).sqrt();
+    let ejectile_e = ejectile_ke + rxn.ejectile_mass;
+    let p_ejectile = (ejectile_ke * (ejectile_ke + 2.0 * rxn.ejectile_mass)).sqrt();
+
+    let residual_e = e_total - ejectile_e;
+    let residual_p2 = p_projectile * p_projectile + p_ejectile * p_ejectile - 2.0 * p_projectile * p_ejectile * angle_rads.cos();
+    (residual_e * residual_e - residual_p2).sqrt() - rxn.residual_mass
+}
+
+/// Radius of curvature (cm) in the SPS of an ejectile with the given kinetic energy (MeV)
+pub fn calculate_rho(params: &KineParameters, rxn: &ReactionData, ejectile_ke: f64) -> f64 {
+    let ejectile_p = (ejectile_ke * (ejectile_ke + 2.0 * rxn.ejectile_mass)).sqrt();
+    ejectile_p / ((rxn.ejectile_z as f64) * params.b_field * QBRHO2P)
+}
+
+/// Kinetic energy (MeV) of an ejectile with the given radius of curvature (cm) in the SPS
+pub fn calculate_ejectile_ke_from_rho(params: &KineParameters, rxn: &ReactionData, rho: f64) -> f64 {
+    let ejectile_p = rho * (rxn.ejectile_z as f64) * params.b_field * QBRHO2P;
+    (ejectile_p * ejectile_p + rxn.ejectile_mass * rxn.ejectile_mass).sqrt() - rxn.ejectile_mass
+}
+
+/// Kinetic energy (MeV) of the ejectile after leaving the target, from its energy at the reaction point
+pub fn apply_ejectile_loss(params: &KineParameters, rxn: &ReactionData, ejectile_ke: f64) -> f64 {
+    params.target.get_ejectile_exit_energy(ejectile_ke, rxn.ejectile_z, rxn.ejectile_mass, params.sps_angle)
+}
+
+/// Kinetic energy (MeV) of the ejectile at the reaction point, from its energy after leaving the target
+pub fn remove_ejectile_loss(params: &KineParameters, rxn: &ReactionData, ejectile_ke: f64) -> f64 {
+    params.target.get_ejectile_reaction_energy(ejectile_ke, rxn.ejectile_z, rxn.ejectile_mass, params.sps_angle)
+}
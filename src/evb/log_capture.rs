@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// A single captured log message
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub time: String,
+    pub level: Level,
+    pub target: String,
+    pub message: String
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}] {}", self.time, self.level, self.message)
+    }
+}
+
+/// Log records captured by a LogCapture, shared with whatever displays them. Keeps the most recent records
+/// (up to the capacity) in memory, and copies every record to the job log file while one is open.
+#[derive(Debug, Default)]
+pub struct LogStore {
+    records: Mutex<VecDeque<LogRecord>>,
+    capacity: usize,
+    error_count: Mutex<usize>,
+    job_file: Mutex<Option<File>>
+}
+
+impl LogStore {
+    pub fn new(capacity: usize) -> Self {
+        LogStore { capacity, ..Default::default() }
+    }
+
+    fn push(&self, record: LogRecord) {
+        if let Ok(mut file) = self.job_file.lock() {
+            if let Some(f) = file.as_mut() {
+                //Nowhere to report a failure to write a log
+                let _ = writeln!(f, "{}", record);
+            }
+        }
+        if record.level == Level::Error {
+            if let Ok(mut count) = self.error_count.lock() {
+                *count += 1;
+            }
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut records) = self.records.lock() {
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+
+    /// Copies of the records at or above the given level, optionally only those containing the search text (case insensitive)
+    pub fn get_records(&self, level: LevelFilter, search: &str) -> Vec<LogRecord> {
+        let search = search.to_lowercase();
+        match self.records.lock() {
+            Ok(records) => records.iter()
+                                  .filter(|r| r.level <= level)
+                                  .filter(|r| search.is_empty() || r.message.to_lowercase().contains(&search))
+                                  .cloned()
+                                  .collect(),
+            Err(_) => vec![]
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut records) = self.records.lock() {
+            records.clear();
+        }
+    }
+
+    /// Number of error records seen so far
+    pub fn get_error_count(&self) -> usize {
+        match self.error_count.lock() {
+            Ok(count) => *count,
+            Err(_) => 0
+        }
+    }
+
+    /// Copy all following records to a file, until stop_job_log is called
+    pub fn start_job_log(&self, path: &Path) -> Result<(), std::io::Error> {
+        let file = File::create(path)?;
+        if let Ok(mut job_file) = self.job_file.lock() {
+            *job_file = Some(file);
+        }
+        Ok(())
+    }
+
+    pub fn stop_job_log(&self) {
+        if let Ok(mut job_file) = self.job_file.lock() {
+            *job_file = None;
+        }
+    }
+}
+
+/// Write records to a file, one per line
+pub fn write_records(records: &[LogRecord], path: &Path) -> Result<(), std::io::Error> {
+    let mut file = File::create(path)?;
+    for record in records.iter() {
+        writeln!(file, "{}", record)?;
+    }
+    Ok(())
+}
+
+/// Name of a job log file, stamped with the current local time
+pub fn get_job_log_name() -> String {
+    format!("job_{}.log", chrono::Local::now().format("%Y%m%d_%H%M%S"))
+}
+
+/// Logger which sends records to a LogStore. Meant to be combined with the terminal logger using simplelog::CombinedLogger.
+pub struct LogCapture {
+    level: LevelFilter,
+    store: Arc<LogStore>
+}
+
+impl LogCapture {
+    pub fn new(level: LevelFilter, store: Arc<LogStore>) -> Box<Self> {
+        Box::new(LogCapture { level, store })
+    }
+}
+
+impl Log for LogCapture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.store.push(LogRecord {
+            time: chrono::Local::now().format(TIME_FORMAT).to_string(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string()
+        });
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.store.job_file.lock() {
+            if let Some(f) = file.as_mut() {
+                let _ = f.flush();
+            }
+        }
+    }
+}
+
+impl SharedLogger for LogCapture {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
@@ -2,6 +2,7 @@ pub mod channel_map;
 pub mod sps_data;
 pub mod compass_data;
 pub mod compass_file;
+pub mod hit_merger;
 pub mod event_builder;
 pub mod compass_run;
 pub mod error;
@@ -9,5 +10,25 @@ pub mod nuclear_data;
 pub mod kinematics;
 pub mod scaler_list;
 pub mod shift_map;
+pub mod calibration_map;
+pub mod shift_calibration;
+pub mod geometry;
+pub mod hit_list;
+pub mod hit_policy;
+pub mod excitation;
+pub mod fp_fit;
+pub mod energy_loss;
+pub mod state_prediction;
+pub mod reaction;
 pub mod sabre_fields;
-pub mod used_size;
\ No newline at end of file
+pub mod used_size;
+pub mod ws;
+pub mod config;
+pub mod progress;
+pub mod job_control;
+pub mod log_capture;
+pub mod output_writer;
+#[cfg(feature = "root-experimental")]
+pub mod root_writer;
+pub mod waveform_data;
+pub mod flag_policy;
\ No newline at end of file
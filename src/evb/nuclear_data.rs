@@ -1,6 +1,5 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::io::BufRead;
+use std::path::{Path, PathBuf};
 use std::fmt::Display;
 use std::error::Error;
 
@@ -33,10 +32,10 @@ impl From<std::num::ParseFloatError> for MassError {
 impl Display for MassError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MassError::MassFileNotFoundError => write!(f, "Could not find and open amdc mass file!"),
-            MassError::MassFileParseError => write!(f, "Unable to parse amdc mass file!"),
-            MassError::MassFileParseIntError(e) => write!(f, "Unable to parse amdc mass file with error {}", e),
-            MassError::MassFileParseFloatError(e) => write!(f, "Unable to parse amdc mass file with error {}", e)
+            MassError::MassFileNotFoundError => write!(f, "Could not find and open mass file!"),
+            MassError::MassFileParseError => write!(f, "Unable to parse mass file!"),
+            MassError::MassFileParseIntError(e) => write!(f, "Unable to parse mass file with error {}", e),
+            MassError::MassFileParseFloatError(e) => write!(f, "Unable to parse mass file with error {}", e)
         }
     }
 }
@@ -67,47 +66,216 @@ fn generate_nucleus_id(z: &u32, a: &u32) -> u32 {
 const U2MEV: f64 = 931.49410242;
 const ELECTRON_MASS: f64 = 0.51099895000; //MeV
 
+//Mass table built in to the executable, used unless a mass file is given
+const BUILTIN_TABLE: &str = include_str!("../../etc/amdc_2016.txt");
+const BUILTIN_EVALUATION: &str = "AME2016 (built-in)";
+
+//Columns (start, end) of the AME mass file format (i.e. mass_1.mas20), which is fixed width
+const AME_N_COLUMNS: (usize, usize) = (4, 9);
+const AME_Z_COLUMNS: (usize, usize) = (9, 14);
+const AME_A_COLUMNS: (usize, usize) = (14, 19);
+const AME_ELEMENT_COLUMNS: (usize, usize) = (20, 23);
+const AME_MASS_U_COLUMNS: (usize, usize) = (106, 109);
+const AME_MASS_MICRO_U_COLUMNS: (usize, usize) = (110, 123);
+const AME_HEADER_SEARCH_LINES: usize = 40;
+
+//Estimated (non-experimental) values are marked by a # in place of the decimal point
+fn parse_column<T: std::str::FromStr>(line: &str, columns: (usize, usize)) -> Option<T> {
+    line.get(columns.0..columns.1.min(line.len()))?.trim().replace('#', ".").parse().ok()
+}
+
+fn make_nuclear_data(z: u32, a: u32, element: &str, mass_u: f64) -> NuclearData {
+    NuclearData {
+        z,
+        a,
+        mass: mass_u * U2MEV - (z as f64) * ELECTRON_MASS,
+        isotope: format!("{}{}", a, element),
+        element: String::from(element)
+    }
+}
+
+/// Table of nuclear masses (MeV) keyed by Z, A, along with the name of the mass evaluation it came from
 #[derive(Debug, Clone, Default)]
 pub struct MassMap {
     map: HashMap<u32, NuclearData>,
-    file: PathBuf
+    symbols: HashMap<String, u32>,
+    evaluation: String
 }
 
 impl MassMap {
+    /// Mass table built in to the executable
     pub fn new() -> Result<Self, MassError> {
-        let mut map = MassMap { map: HashMap::new(), file: PathBuf::from(std::env::current_dir()?.join("etc").join("amdc_2016.txt")) };
-        map.init()?;
+        let mut map = MassMap { map: HashMap::new(), symbols: HashMap::new(), evaluation: String::from(BUILTIN_EVALUATION) };
+        map.init(BUILTIN_TABLE)?;
         return Ok(map);
     }
 
-    fn init(&mut self) -> Result<(), MassError> {
-        let file = std::fs::File::open(&self.file)?;
-        let mut reader = std::io::BufReader::new(file);
-        let mut junk = String::new();
-        reader.read_line(&mut junk)?;
-        reader.read_line(&mut junk)?;
-        let lines = reader.lines();
-        
-        for line in lines {
-            match line {
-                Ok(line_str) => {
-                    let entries: Vec<&str> = line_str.split_whitespace().collect();
-                    let mut data = NuclearData::default();
-                    data.z = entries[1].parse()?;
-                    data.a = entries[2].parse()?;
-                    data.element = String::from(entries[3]);
-                    data.isotope = format!("{}{}", data.a, data.element);
-                    data.mass = (entries[4].parse::<f64>()? + 1.0e-6 * entries[5].parse::<f64>()?) * U2MEV - (data.z as f64) * ELECTRON_MASS;
-                    self.map.insert(generate_nucleus_id(&data.z, &data.a), data);
-                },
-                Err(_) => return Err(MassError::MassFileParseError)
-            };
+    /// Mass table read from a file. Either an AME mass file (i.e. mass_1.mas20 from the AMDC) or the simple
+    /// N Z A EL Mass(u) Mass(micro-u) format of the built-in table can be used.
+    pub fn from_file(path: &Path) -> Result<Self, MassError> {
+        let text = std::fs::read_to_string(path)?;
+        let evaluation = match Self::find_evaluation_name(&text) {
+            Some(name) => format!("{} ({})", name, path.display()),
+            None => path.display().to_string()
+        };
+        let mut map = MassMap { map: HashMap::new(), symbols: HashMap::new(), evaluation };
+        map.init(&text)?;
+        return Ok(map);
+    }
+
+    /// Use the mass file if one is given, otherwise the built-in table
+    pub fn load(file: &Option<PathBuf>) -> Result<Self, MassError> {
+        match file {
+            Some(path) => Self::from_file(path),
+            None => Self::new()
+        }
+    }
+
+    /// Name of the mass evaluation used for this table
+    pub fn get_evaluation(&self) -> &str {
+        &self.evaluation
+    }
+
+    //AME files name the evaluation (i.e. AME2020) in the header
+    fn find_evaluation_name(text: &str) -> Option<String> {
+        for line in text.lines().take(AME_HEADER_SEARCH_LINES) {
+            if let Some(position) = line.find("AME") {
+                let year: String = line[position + 3..].trim_start().chars().take_while(|c| c.is_ascii_digit()).collect();
+                if year.len() == 4 {
+                    return Some(format!("AME{}", year));
+                }
+            }
+        }
+        None
+    }
+
+    fn init(&mut self, text: &str) -> Result<(), MassError> {
+        let is_simple_format = match text.lines().next() {
+            Some(header) => header.split_whitespace().take(4).eq(["N", "Z", "A", "EL"]),
+            None => return Err(MassError::MassFileParseError)
+        };
+
+        if is_simple_format {
+            self.init_simple(text)?;
+        } else {
+            self.init_ame(text)?;
+        }
+
+        if self.map.is_empty() {
+            return Err(MassError::MassFileParseError);
+        }
+        Ok(())
+    }
+
+    fn init_simple(&mut self, text: &str) -> Result<(), MassError> {
+        for line in text.lines().skip(2) {
+            let entries: Vec<&str> = line.split_whitespace().collect();
+            if entries.is_empty() {
+                continue;
+            } else if entries.len() < 6 {
+                return Err(MassError::MassFileParseError);
+            }
+            let mass_u = entries[4].parse::<f64>()? + 1.0e-6 * entries[5].replace('#', ".").parse::<f64>()?;
+            self.insert(make_nuclear_data(entries[1].parse()?, entries[2].parse()?, entries[3], mass_u));
         }
+        Ok(())
+    }
 
+    //Header lines are anything without an N, Z, A in the expected columns
+    fn init_ame(&mut self, text: &str) -> Result<(), MassError> {
+        for line in text.lines() {
+            let (z, a) = match (parse_column::<u32>(line, AME_N_COLUMNS), parse_column::<u32>(line, AME_Z_COLUMNS), parse_column::<u32>(line, AME_A_COLUMNS)) {
+                (Some(_), Some(z), Some(a)) => (z, a),
+                _ => continue
+            };
+            let element = match line.get(AME_ELEMENT_COLUMNS.0..AME_ELEMENT_COLUMNS.1) {
+                Some(el) => el.trim(),
+                None => return Err(MassError::MassFileParseError)
+            };
+            let mass_u = match (parse_column::<f64>(line, AME_MASS_U_COLUMNS), parse_column::<f64>(line, AME_MASS_MICRO_U_COLUMNS)) {
+                (Some(u), Some(micro_u)) => u + 1.0e-6 * micro_u,
+                _ => return Err(MassError::MassFileParseError)
+            };
+            self.insert(make_nuclear_data(z, a, element, mass_u));
+        }
         Ok(())
     }
 
+    fn insert(&mut self, data: NuclearData) {
+        self.symbols.insert(data.element.clone(), data.z);
+        self.map.insert(generate_nucleus_id(&data.z, &data.a), data);
+    }
+
     pub fn get_data(&self, z: &u32, a: &u32) -> Option<&NuclearData> {
         self.map.get(&generate_nucleus_id(z, a))
     }
+
+    /// Z of an element symbol. Symbols are matched exactly first, then with standard capitalization (i.e. "he" -> "He")
+    pub fn get_z_from_symbol(&self, symbol: &str) -> Option<u32> {
+        if let Some(z) = self.symbols.get(symbol) {
+            return Some(*z);
+        }
+        let mut chars = symbol.chars();
+        let normalized: String = match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect(),
+            None => return None
+        };
+        self.symbols.get(&normalized).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Fixed width line of an AME mass file, with the fields in the columns read by the parser
+    fn make_ame_line(n: u32, z: u32, a: u32, element: &str, mass_u: &str, mass_micro_u: &str) -> String {
+        let mut line = vec![b' '; 135];
+        let mut place = |columns: (usize, usize), value: &str| {
+            line[columns.1 - value.len()..columns.1].copy_from_slice(value.as_bytes());
+        };
+        place(AME_N_COLUMNS, &n.to_string());
+        place(AME_Z_COLUMNS, &z.to_string());
+        place(AME_A_COLUMNS, &a.to_string());
+        place((AME_ELEMENT_COLUMNS.0, AME_ELEMENT_COLUMNS.0 + element.len()), element);
+        place(AME_MASS_U_COLUMNS, mass_u);
+        place(AME_MASS_MICRO_U_COLUMNS, mass_micro_u);
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn built_in_table_has_13c() {
+        let masses = MassMap::new().unwrap();
+        assert_eq!(masses.get_evaluation(), BUILTIN_EVALUATION);
+        //13C atomic mass 13.00335483 u (unchanged between AME2016 and AME2020 at this precision)
+        let carbon = masses.get_data(&6, &13).unwrap();
+        let expected = 13.00335483 * U2MEV - 6.0 * ELECTRON_MASS;
+        assert!((carbon.mass - expected).abs() < 1.0e-5, "{} MeV", carbon.mass);
+        assert_eq!(carbon.isotope, "13C");
+    }
+
+    #[test]
+    fn reads_ame_file_with_estimated_masses() {
+        let text = [
+            String::from("1    a0dsskgw"),
+            String::from("0                          ATOMIC MASS ADJUSTMENT"),
+            String::from("0           ****************     AME2020     ****************"),
+            String::from("0   N-Z    N    Z    A  EL    O     MASS EXCESS(keV)  ... ATOMIC MASS(micro-u)"),
+            make_ame_line(7, 6, 13, "C", "13", "003354.835"),
+            make_ame_line(16, 6, 22, "C", "22", "057553#"),
+        ].join("\n");
+        let path = std::env::temp_dir().join(format!("spsevb_ame_{}.mas20", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let masses = MassMap::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let masses = masses.unwrap();
+
+        assert_eq!(masses.get_evaluation(), format!("AME2020 ({})", path.display()));
+        let carbon = masses.get_data(&6, &13).unwrap();
+        assert!((carbon.mass - (13.003354835 * U2MEV - 6.0 * ELECTRON_MASS)).abs() < 1.0e-6);
+        //The # marks an estimate in place of the decimal point
+        let estimated = masses.get_data(&6, &22).unwrap();
+        assert!((estimated.mass - (22.057553 * U2MEV - 6.0 * ELECTRON_MASS)).abs() < 1.0e-6);
+        assert_eq!(masses.get_z_from_symbol("c"), Some(6));
+    }
 }
@@ -0,0 +1,493 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use polars::prelude::*;
+use polars::export::arrow::array::Array;
+use serde::{Serialize, Deserialize};
+use log::{info, warn};
+
+/// Compression codec used for the parquet output
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Compression {
+    Uncompressed,
+    Snappy,
+    Lz4,
+    #[default]
+    Zstd
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Uncompressed => write!(f, "Uncompressed"),
+            Compression::Snappy => write!(f, "Snappy"),
+            Compression::Lz4 => write!(f, "LZ4"),
+            Compression::Zstd => write!(f, "Zstd")
+        }
+    }
+}
+
+impl Compression {
+    /// Range of the compression level, for codecs which have one
+    pub fn get_level_range(&self) -> Option<std::ops::RangeInclusive<i32>> {
+        match self {
+            Compression::Zstd => Some(1..=22),
+            _ => None
+        }
+    }
+}
+
+/// File format of the output. Parquet is compressed and is the best choice for most analyses; Arrow IPC (Feather v2)
+/// is uncompressed, so it is larger but can be memory-mapped for fast interactive reads; CSV is for quick checks
+/// with tools which cannot read the other formats. ROOT (a TTree, built with the `root-experimental` feature) is for
+/// analyses which are still done in ROOT; it has not yet been checked against ROOT itself, so it is experimental.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum OutputFormat {
+    #[default]
+    Parquet,
+    Ipc,
+    Csv,
+    #[cfg(feature = "root-experimental")]
+    Root
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Parquet => write!(f, "Parquet"),
+            OutputFormat::Ipc => write!(f, "Arrow IPC"),
+            OutputFormat::Csv => write!(f, "CSV"),
+            #[cfg(feature = "root-experimental")]
+            OutputFormat::Root => write!(f, "ROOT (experimental)")
+        }
+    }
+}
+
+impl OutputFormat {
+    /// File extension (without the dot) of the format
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Ipc => "arrow",
+            OutputFormat::Csv => "csv",
+            #[cfg(feature = "root-experimental")]
+            OutputFormat::Root => "root"
+        }
+    }
+
+    /// Format matching the extension of a path, if any (`.feather` and `.ipc` are also taken as Arrow IPC)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "parquet" => Some(OutputFormat::Parquet),
+            "arrow" | "feather" | "ipc" => Some(OutputFormat::Ipc),
+            "csv" => Some(OutputFormat::Csv),
+            #[cfg(feature = "root-experimental")]
+            "root" => Some(OutputFormat::Root),
+            _ => None
+        }
+    }
+}
+
+/// How the event built data of a run is written. Events are collected in memory and written out in batches,
+/// so that the memory used per worker is bounded by the batch rather than by the size of the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputSettings {
+    /// Number of events in a batch
+    pub batch_size: usize,
+    /// Maximum memory (MB) used by a batch. A batch is written early if it grows past this (i.e. events with many hits).
+    pub max_batch_memory_mb: usize,
+    /// Write each batch to its own fragment file (`run_<number>_<fragment>.parquet`), rather than as a row group of a single file
+    pub fragment_files: bool,
+    pub format: OutputFormat,
+    /// Parquet only
+    pub compression: Compression,
+    /// Compression level, for codecs which have one (Zstd: 1-22, higher is smaller but slower to write)
+    pub compression_level: i32,
+    /// Maximum number of rows in a parquet row group. If not set, each batch is one row group.
+    pub row_group_size: Option<usize>,
+    /// Write the per-column statistics (min, max, null count) of each row group, which readers can use to skip row groups
+    pub statistics: bool
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            batch_size: 100_000,
+            max_batch_memory_mb: 1_000,
+            fragment_files: false,
+            format: OutputFormat::default(),
+            compression: Compression::default(),
+            compression_level: 3,
+            row_group_size: None,
+            statistics: true
+        }
+    }
+}
+
+impl OutputSettings {
+    pub fn get_max_batch_bytes(&self) -> usize {
+        self.max_batch_memory_mb * 1_000_000
+    }
+
+    /// Create a parquet writer with the compression and statistics settings
+    pub fn get_parquet_writer<W: Write>(&self, writer: W) -> Result<ParquetWriter<W>, PolarsError> {
+        let compression = match self.compression {
+            Compression::Uncompressed => ParquetCompression::Uncompressed,
+            Compression::Snappy => ParquetCompression::Snappy,
+            Compression::Lz4 => ParquetCompression::Lz4Raw,
+            Compression::Zstd => ParquetCompression::Zstd(Some(ZstdLevel::try_new(self.compression_level)?))
+        };
+        Ok(ParquetWriter::new(writer).with_compression(compression).with_statistics(self.statistics))
+    }
+
+    /// Check whether a batch with the given number of events and memory use should be written out
+    pub fn is_batch_full(&self, events: usize, used_size: usize) -> bool {
+        events >= self.batch_size.max(1) || used_size > self.get_max_batch_bytes()
+    }
+}
+
+//Write a batch as row groups of at most row_group_size rows (the whole batch if not set).
+//The columns of a batch are single chunks, and each chunk given to the writer becomes a row group.
+fn write_row_groups(writer: &mut polars::io::parquet::BatchedWriter<File>, df: &DataFrame, row_group_size: Option<usize>) -> Result<(), PolarsError> {
+    match row_group_size {
+        Some(size) if size > 0 => {
+            let mut offset = 0;
+            while offset < df.height() {
+                writer.write_batch(&df.slice(offset as i64, size))?;
+                offset += size;
+            }
+        }
+        _ => writer.write_batch(df)?
+    };
+    Ok(())
+}
+
+//Join the values of each row of a list column into one string (separated by ';'), null if the row is null.
+//get_values picks the values to join out of the inner values of the list (i.e. one field of a struct).
+fn join_list_rows(list: &ListChunked, name: &str, get_values: impl Fn(Series) -> Result<Series, PolarsError>) -> Result<Series, PolarsError> {
+    let mut rows: Vec<Option<String>> = Vec::with_capacity(list.len());
+    for array in list.downcast_iter() {
+        let values = get_values(Series::try_from((name, array.values().clone()))?)?.cast(&DataType::Utf8)?;
+        let values: Vec<&str> = values.utf8()?.into_iter().map(|value| value.unwrap_or("")).collect();
+        for row in 0..array.len() {
+            if array.is_valid(row) {
+                let (start, end) = array.offsets().start_end(row);
+                rows.push(Some(values[start..end].join(";")));
+            } else {
+                rows.push(None);
+            }
+        }
+    }
+    Ok(Series::new(name, rows))
+}
+
+//CSV has no nested types, so list columns are flattened. A list of structs (i.e. the SABRE ring and wedge hits)
+//becomes one column per field, <Column>_<Field>, and any other list (i.e. waveform samples) one column. Each row
+//holds the values of the list joined by ';', in the same order in each of the field columns.
+fn flatten_for_csv(df: &DataFrame) -> Result<DataFrame, PolarsError> {
+    let mut columns: Vec<Series> = vec![];
+    for column in df.get_columns() {
+        match column.dtype() {
+            DataType::List(inner) => {
+                let list = column.list()?;
+                match inner.as_ref() {
+                    DataType::Struct(fields) => {
+                        for field in fields.iter() {
+                            let name = format!("{}_{}", column.name(), field.name());
+                            columns.push(join_list_rows(list, &name, |values| values.struct_()?.field_by_name(field.name()))?);
+                        }
+                    }
+                    _ => columns.push(join_list_rows(list, column.name(), Ok)?)
+                }
+            }
+            _ => columns.push(column.clone())
+        }
+    }
+    DataFrame::new(columns)
+}
+
+//A file of one of the output formats, written a batch at a time
+trait FormatWriter {
+    fn write_batch(&mut self, df: &DataFrame) -> Result<(), PolarsError>;
+    fn finish(&mut self) -> Result<(), PolarsError>;
+}
+
+struct ParquetFormatWriter {
+    writer: polars::io::parquet::BatchedWriter<File>,
+    row_group_size: Option<usize>
+}
+
+impl FormatWriter for ParquetFormatWriter {
+    fn write_batch(&mut self, df: &DataFrame) -> Result<(), PolarsError> {
+        write_row_groups(&mut self.writer, df, self.row_group_size)
+    }
+
+    fn finish(&mut self) -> Result<(), PolarsError> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+//Each batch is a record batch of the IPC file. The file is not compressed, so that it can be memory-mapped.
+struct IpcFormatWriter {
+    writer: polars::io::ipc::BatchedWriter<File>
+}
+
+impl FormatWriter for IpcFormatWriter {
+    fn write_batch(&mut self, df: &DataFrame) -> Result<(), PolarsError> {
+        self.writer.write_batch(df)
+    }
+
+    fn finish(&mut self) -> Result<(), PolarsError> {
+        self.writer.finish()
+    }
+}
+
+//The header is written with the first batch only
+struct CsvFormatWriter {
+    file: File,
+    has_header: bool
+}
+
+impl FormatWriter for CsvFormatWriter {
+    fn write_batch(&mut self, df: &DataFrame) -> Result<(), PolarsError> {
+        let mut flat_df = flatten_for_csv(df)?;
+        CsvWriter::new(&mut self.file).has_header(!self.has_header).finish(&mut flat_df)?;
+        self.has_header = true;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), PolarsError> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+//Each batch is one basket of each branch of the tree, which is written when finished
+#[cfg(feature = "root-experimental")]
+struct RootFormatWriter {
+    writer: crate::evb::root_writer::TreeWriter
+}
+
+#[cfg(feature = "root-experimental")]
+impl FormatWriter for RootFormatWriter {
+    fn write_batch(&mut self, df: &DataFrame) -> Result<(), PolarsError> {
+        self.writer.write_batch(df)
+    }
+
+    fn finish(&mut self) -> Result<(), PolarsError> {
+        self.writer.finish()
+    }
+}
+
+//Create the file at path and a writer of the output format for dataframes with the given schema
+fn create_format_writer(path: &Path, schema: &Schema, settings: &OutputSettings) -> Result<Box<dyn FormatWriter>, PolarsError> {
+    let file = File::create(path)?;
+    let writer: Box<dyn FormatWriter> = match settings.format {
+        OutputFormat::Parquet => Box::new(ParquetFormatWriter {
+            writer: settings.get_parquet_writer(file)?.batched(schema)?,
+            row_group_size: settings.row_group_size
+        }),
+        OutputFormat::Ipc => Box::new(IpcFormatWriter {
+            writer: IpcWriter::new(file).with_compression(None).batched(schema)?
+        }),
+        OutputFormat::Csv => Box::new(CsvFormatWriter { file, has_header: false }),
+        #[cfg(feature = "root-experimental")]
+        OutputFormat::Root => {
+            static EXPERIMENTAL_WARNING: std::sync::Once = std::sync::Once::new();
+            EXPERIMENTAL_WARNING.call_once(|| warn!("ROOT output is experimental: the files have not been validated against ROOT or uproot, check them before relying on them"));
+            //The file records its own name, which is the name it has once complete
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().trim_end_matches(".partial");
+            Box::new(RootFormatWriter {
+                writer: crate::evb::root_writer::TreeWriter::new(file, name, crate::evb::root_writer::TREE_NAME,
+                                                                 crate::evb::root_writer::TREE_TITLE, schema)?
+            })
+        }
+    };
+    Ok(writer)
+}
+
+/// Write a whole dataframe to a single file in the output format of the settings
+pub fn write_file(df: &DataFrame, path: &Path, settings: &OutputSettings) -> Result<(), PolarsError> {
+    let mut writer = create_format_writer(path, &df.schema(), settings)?;
+    writer.write_batch(df)?;
+    writer.finish()
+}
+
+//Files are written under a temporary name until they are complete, so that an interrupted write is never mistaken for output
+fn get_partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+/// Writes one output of a run (i.e. the event built data or the waveforms) in batches. Normally each batch is
+/// appended to a single file, `<prefix><suffix>.<extension>` (a row group of a parquet file, a record batch of an
+/// Arrow IPC file, or rows of a CSV file); with fragment files each batch is written to
+/// `<prefix>_<fragment><suffix>.<extension>`. The file is only created once the first batch is written.
+///
+/// A file is written as `<name>.partial` and only renamed once it is complete (when finished, for the single file).
+/// If the writer is dropped without being finished (i.e. the run failed), everything written so far is removed.
+pub struct BatchWriter {
+    dir: PathBuf,
+    prefix: String,
+    suffix: String,
+    settings: OutputSettings,
+    writer: Option<Box<dyn FormatWriter>>,
+    partial_path: Option<PathBuf>,
+    paths: Vec<PathBuf>
+}
+
+impl BatchWriter {
+    pub fn new(dir: &Path, prefix: &str, suffix: &str, settings: &OutputSettings) -> Self {
+        BatchWriter {
+            dir: dir.to_path_buf(),
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            settings: settings.clone(),
+            writer: None,
+            partial_path: None,
+            paths: vec![]
+        }
+    }
+
+    /// Write a batch of columns. Empty batches are skipped, unless nothing has been written yet, so that the
+    /// output exists even if there was no data.
+    pub fn write_batch(&mut self, columns: Vec<Series>) -> Result<(), PolarsError> {
+        let df = DataFrame::new(columns)?;
+        if df.height() == 0 && !self.paths.is_empty() {
+            return Ok(());
+        }
+        let extension = self.settings.format.get_extension();
+
+        if self.settings.fragment_files {
+            let path = self.dir.join(format!("{}_{}{}.{}", self.prefix, self.paths.len(), self.suffix, extension));
+            info!("Writing dataframe fragment to disk at {}", path.display());
+            let partial_path = self.partial_path.insert(get_partial_path(&path));
+            write_file(&df, partial_path, &self.settings)?;
+            std::fs::rename(partial_path, &path)?;
+            self.partial_path = None;
+            self.paths.push(path);
+            return Ok(());
+        }
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let path = self.dir.join(format!("{}{}.{}", self.prefix, self.suffix, extension));
+                info!("Writing dataframe to disk at {}", path.display());
+                let partial_path = self.partial_path.insert(get_partial_path(&path));
+                let writer = create_format_writer(partial_path, &df.schema(), &self.settings)?;
+                self.paths.push(path);
+                self.writer.insert(writer)
+            }
+        };
+        writer.write_batch(&df)
+    }
+
+    /// Finish the file (i.e. writing the parquet footer) and give it its final name
+    pub fn finish(mut self) -> Result<(), PolarsError> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.finish()?;
+        }
+        self.writer = None;
+        if let (Some(partial_path), Some(path)) = (self.partial_path.take(), self.paths.last()) {
+            std::fs::rename(partial_path, path)?;
+        }
+        //The output is complete, so it is kept
+        self.paths.clear();
+        Ok(())
+    }
+
+    /// Stop writing and remove everything written so far
+    pub fn discard(mut self) -> Result<(), std::io::Error> {
+        self.remove_output()
+    }
+
+    fn remove_output(&mut self) -> Result<(), std::io::Error> {
+        self.writer = None;
+        for path in self.partial_path.take().into_iter().chain(self.paths.drain(..)) {
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BatchWriter {
+    fn drop(&mut self) {
+        if let Err(x) = self.remove_output() {
+            warn!("Could not remove unfinished output in {}: {}", self.dir.display(), x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spsevb_output_writer_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn list_dir(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn make_batch() -> Vec<Series> {
+        vec![Series::new("x", &[1.0, 2.0, 3.0])]
+    }
+
+    #[test]
+    fn finished_file_is_renamed() {
+        let dir = make_dir("finished");
+        let mut writer = BatchWriter::new(&dir, "run_1", "", &OutputSettings::default());
+        writer.write_batch(make_batch()).unwrap();
+        writer.write_batch(make_batch()).unwrap();
+        assert_eq!(list_dir(&dir), vec!["run_1.parquet.partial"]);
+
+        writer.finish().unwrap();
+        assert_eq!(list_dir(&dir), vec!["run_1.parquet"]);
+        let df = ParquetReader::new(File::open(dir.join("run_1.parquet")).unwrap()).finish().unwrap();
+        assert_eq!(df.height(), 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unfinished_output_is_removed() {
+        let dir = make_dir("unfinished");
+        let mut writer = BatchWriter::new(&dir, "run_1", "", &OutputSettings::default());
+        writer.write_batch(make_batch()).unwrap();
+        drop(writer);
+        assert!(list_dir(&dir).is_empty());
+
+        let settings = OutputSettings { fragment_files: true, ..Default::default() };
+        let mut writer = BatchWriter::new(&dir, "run_1", "", &settings);
+        writer.write_batch(make_batch()).unwrap();
+        writer.write_batch(make_batch()).unwrap();
+        assert_eq!(list_dir(&dir), vec!["run_1_0.parquet", "run_1_1.parquet"]);
+        drop(writer);
+        assert!(list_dir(&dir).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn discard_removes_output() {
+        let dir = make_dir("discard");
+        let mut writer = BatchWriter::new(&dir, "run_1", "_waves", &OutputSettings::default());
+        writer.write_batch(make_batch()).unwrap();
+        writer.discard().unwrap();
+        assert!(list_dir(&dir).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunStatus {
+    Queued,
+    Running,
+    Done,
+    Missing,
+    Cancelled,
+    Failed(String)
+}
+
+impl Display for RunStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunStatus::Queued => write!(f, "Queued"),
+            RunStatus::Running => write!(f, "Running"),
+            RunStatus::Done => write!(f, "Done"),
+            RunStatus::Missing => write!(f, "Missing"),
+            RunStatus::Cancelled => write!(f, "Cancelled"),
+            RunStatus::Failed(x) => write!(f, "Failed: {}", x)
+        }
+    }
+}
+
+/// Progress of a single run. The total number of hits is known once the run's files are opened.
+#[derive(Debug, Clone)]
+pub struct RunProgress {
+    pub run_number: i32,
+    pub status: RunStatus,
+    pub hits_processed: u64,
+    pub total_hits: u64,
+    pub events: u64,
+    pub start_time: Option<Instant>,
+    pub elapsed: Duration //Time spent processing, fixed once the run is finished
+}
+
+impl RunProgress {
+    fn new(run_number: i32) -> Self {
+        RunProgress { run_number, status: RunStatus::Queued, hits_processed: 0, total_hits: 0, events: 0, start_time: None, elapsed: Duration::ZERO }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.status, RunStatus::Queued | RunStatus::Running)
+    }
+
+    /// Fraction of the run completed, from 0 to 1
+    pub fn get_fraction(&self) -> f32 {
+        if self.status == RunStatus::Done {
+            1.0
+        } else if self.total_hits == 0 {
+            0.0
+        } else {
+            (self.hits_processed as f64 / self.total_hits as f64) as f32
+        }
+    }
+
+    pub fn get_elapsed(&self) -> Duration {
+        match (&self.status, self.start_time) {
+            (RunStatus::Running, Some(start)) => start.elapsed(),
+            _ => self.elapsed
+        }
+    }
+
+    /// Hits processed per second
+    pub fn get_hit_rate(&self) -> f64 {
+        let seconds = self.get_elapsed().as_secs_f64();
+        if seconds == 0.0 { 0.0 } else { self.hits_processed as f64 / seconds }
+    }
+
+    /// Estimated time remaining for a running run, from the current hit rate
+    pub fn get_eta(&self) -> Option<Duration> {
+        let rate = self.get_hit_rate();
+        if self.status != RunStatus::Running || rate == 0.0 || self.total_hits == 0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(self.total_hits.saturating_sub(self.hits_processed) as f64 / rate))
+    }
+}
+
+/// Progress of all of the runs in a job, shared between the processing threads and the UI
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub runs: Vec<RunProgress>,
+    pub start_time: Option<Instant>
+}
+
+impl Progress {
+    //Reset for a job over the runs in [run_min, run_max)
+    pub fn reset(&mut self, run_min: i32, run_max: i32) {
+        self.runs = (run_min..run_max).map(RunProgress::new).collect();
+        self.start_time = Some(Instant::now());
+    }
+
+    pub fn get_run_mut(&mut self, run_number: i32) -> Option<&mut RunProgress> {
+        self.runs.iter_mut().find(|r| r.run_number == run_number)
+    }
+
+    pub fn set_status(&mut self, run_number: i32, status: RunStatus) {
+        if let Some(run) = self.get_run_mut(run_number) {
+            if status == RunStatus::Running {
+                run.start_time = Some(Instant::now());
+            } else if run.status == RunStatus::Running {
+                run.elapsed = run.get_elapsed();
+            }
+            run.status = status;
+        }
+    }
+
+    pub fn set_total_hits(&mut self, run_number: i32, total_hits: u64) {
+        if let Some(run) = self.get_run_mut(run_number) {
+            run.total_hits = total_hits;
+        }
+    }
+
+    pub fn set_counts(&mut self, run_number: i32, hits_processed: u64, events: u64) {
+        if let Some(run) = self.get_run_mut(run_number) {
+            run.hits_processed = hits_processed;
+            run.events = events;
+        }
+    }
+
+    pub fn get_active_runs(&self) -> impl Iterator<Item = &RunProgress> {
+        self.runs.iter().filter(|r| r.status == RunStatus::Running)
+    }
+
+    //Mark the runs which were never started as cancelled
+    pub fn cancel_queued(&mut self) {
+        self.runs.iter_mut().filter(|r| r.status == RunStatus::Queued).for_each(|r| r.status = RunStatus::Cancelled);
+    }
+
+    pub fn get_completed_runs(&self) -> Vec<i32> {
+        self.runs.iter().filter(|r| r.status == RunStatus::Done).map(|r| r.run_number).collect()
+    }
+
+    pub fn get_number_finished(&self) -> usize {
+        self.runs.iter().filter(|r| r.is_finished()).count()
+    }
+
+    //Overall fraction of the job completed, counting finished runs as complete
+    pub fn get_total_fraction(&self) -> f32 {
+        if self.runs.is_empty() {
+            return 0.0;
+        }
+        let total: f32 = self.runs.iter()
+                             .map(|r| if r.is_finished() { 1.0 } else { r.get_fraction() })
+                             .sum();
+        total / (self.runs.len() as f32)
+    }
+
+    pub fn get_elapsed(&self) -> Duration {
+        match self.start_time {
+            Some(start) => start.elapsed(),
+            None => Duration::ZERO
+        }
+    }
+
+    pub fn get_hits_processed(&self) -> u64 {
+        self.runs.iter().map(|r| r.hits_processed).sum()
+    }
+
+    pub fn get_events(&self) -> u64 {
+        self.runs.iter().map(|r| r.events).sum()
+    }
+
+    /// Hits processed per second over the whole job
+    pub fn get_hit_rate(&self) -> f64 {
+        let seconds = self.get_elapsed().as_secs_f64();
+        if seconds == 0.0 { 0.0 } else { self.get_hits_processed() as f64 / seconds }
+    }
+
+    /// Estimated time remaining for the job, assuming the remaining runs take as long as those done so far
+    pub fn get_eta(&self) -> Option<Duration> {
+        let fraction = self.get_total_fraction() as f64;
+        if fraction <= 0.0 || fraction >= 1.0 {
+            return None;
+        }
+        Some(self.get_elapsed().mul_f64((1.0 - fraction) / fraction))
+    }
+}
+
+/// Format a duration as hh:mm:ss
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
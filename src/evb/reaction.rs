@@ -0,0 +1,159 @@
+use std::fmt::Display;
+
+use super::nuclear_data::MassMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReactionError {
+    InvalidFormat(String),
+    UnknownElement(String),
+    UnknownNuclide(String),
+    ChargeNotConserved,
+    BaryonNotConserved
+}
+
+impl Display for ReactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReactionError::InvalidFormat(x) => write!(f, "Reaction {} is not of the form Target(Projectile,Ejectile)Residual", x),
+            ReactionError::UnknownElement(x) => write!(f, "Reaction has an unknown element in nuclide {}", x),
+            ReactionError::UnknownNuclide(x) => write!(f, "Reaction has nuclide {} which is not in the mass table", x),
+            ReactionError::ChargeNotConserved => write!(f, "Reaction does not conserve charge"),
+            ReactionError::BaryonNotConserved => write!(f, "Reaction does not conserve baryon number")
+        }
+    }
+}
+
+impl std::error::Error for ReactionError {
+
+}
+
+/// Z, A of the nuclei in a reaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParsedReaction {
+    pub target: (u32, u32),
+    pub projectile: (u32, u32),
+    pub ejectile: (u32, u32),
+    pub residual: (u32, u32)
+}
+
+//Common shorthand for light ions
+fn parse_shorthand(token: &str) -> Option<(u32, u32)> {
+    match token {
+        "n" => Some((0, 1)),
+        "p" => Some((1, 1)),
+        "d" => Some((1, 2)),
+        "t" => Some((1, 3)),
+        "a" | "alpha" => Some((2, 4)),
+        _ => None
+    }
+}
+
+//A nuclide is either shorthand or a mass number and element symbol, in either order (i.e. 12C or C12)
+fn parse_nuclide(token: &str, nuc_map: &MassMap) -> Result<(u32, u32), ReactionError> {
+    if let Some(za) = parse_shorthand(token) {
+        return Ok(za);
+    }
+
+    let (symbol, mass_number): (String, String) = if token.starts_with(|c: char| c.is_ascii_digit()) {
+        let symbol_start = token.find(|c: char| !c.is_ascii_digit()).unwrap_or(token.len());
+        (token[symbol_start..].to_string(), token[..symbol_start].to_string())
+    } else {
+        let number_start = token.find(|c: char| c.is_ascii_digit()).unwrap_or(token.len());
+        (token[..number_start].to_string(), token[number_start..].to_string())
+    };
+
+    let a: u32 = match mass_number.parse() {
+        Ok(value) => value,
+        Err(_) => return Err(ReactionError::UnknownNuclide(token.to_string()))
+    };
+    let z = match nuc_map.get_z_from_symbol(&symbol) {
+        Some(value) => value,
+        None => return Err(ReactionError::UnknownElement(token.to_string()))
+    };
+    match nuc_map.get_data(&z, &a) {
+        Some(_) => Ok((z, a)),
+        None => Err(ReactionError::UnknownNuclide(token.to_string()))
+    }
+}
+
+/// Parse a reaction in standard notation, i.e. "12C(d,p)13C". The residual is optional (i.e. "12C(d,p)"); if given it is
+/// checked for conservation of charge and baryon number. Element symbols are taken from the mass table, and the shorthand
+/// n, p, d, t, and a (alpha) can be used for light ions.
+pub fn parse_reaction(eqn: &str, nuc_map: &MassMap) -> Result<ParsedReaction, ReactionError> {
+    let invalid = || ReactionError::InvalidFormat(eqn.to_string());
+    let compact: String = eqn.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let (target_str, rest) = compact.split_once('(').ok_or_else(invalid)?;
+    let (light_str, residual_str) = rest.split_once(')').ok_or_else(invalid)?;
+    let (projectile_str, ejectile_str) = light_str.split_once(',').ok_or_else(invalid)?;
+    if target_str.is_empty() || projectile_str.is_empty() || ejectile_str.is_empty() {
+        return Err(invalid());
+    }
+
+    let target = parse_nuclide(target_str, nuc_map)?;
+    let projectile = parse_nuclide(projectile_str, nuc_map)?;
+    let ejectile = parse_nuclide(ejectile_str, nuc_map)?;
+
+    let total_z = target.0 + projectile.0;
+    let total_a = target.1 + projectile.1;
+    if ejectile.0 > total_z {
+        return Err(ReactionError::ChargeNotConserved);
+    } else if ejectile.1 >= total_a {
+        return Err(ReactionError::BaryonNotConserved);
+    }
+    let residual = (total_z - ejectile.0, total_a - ejectile.1);
+
+    if !residual_str.is_empty() {
+        let given = parse_nuclide(residual_str, nuc_map)?;
+        if given.0 != residual.0 {
+            return Err(ReactionError::ChargeNotConserved);
+        } else if given.1 != residual.1 {
+            return Err(ReactionError::BaryonNotConserved);
+        }
+    } else if nuc_map.get_data(&residual.0, &residual.1).is_none() {
+        return Err(ReactionError::UnknownNuclide(format!("Z={} A={}", residual.0, residual.1)));
+    }
+
+    Ok(ParsedReaction { target, projectile, ejectile, residual })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_reactions() {
+        let nuc_map = MassMap::new().unwrap();
+        let cases = [
+            ("12C(d,p)13C", (6, 12), (1, 2), (1, 1), (6, 13)),
+            ("12C(d,p)", (6, 12), (1, 2), (1, 1), (6, 13)),
+            ("C12(d,p)C13", (6, 12), (1, 2), (1, 1), (6, 13)),
+            (" 12C ( d , p ) 13C ", (6, 12), (1, 2), (1, 1), (6, 13)),
+            ("27Al(p,n)27Si", (13, 27), (1, 1), (0, 1), (14, 27)),
+            ("16O(t,a)15N", (8, 16), (1, 3), (2, 4), (7, 15)),
+            ("24Mg(alpha,d)26Al", (12, 24), (2, 4), (1, 2), (13, 26))
+        ];
+        for (eqn, target, projectile, ejectile, residual) in cases {
+            let rxn = parse_reaction(eqn, &nuc_map).unwrap();
+            assert_eq!(rxn, ParsedReaction { target, projectile, ejectile, residual }, "{}", eqn);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_reactions() {
+        let nuc_map = MassMap::new().unwrap();
+        let cases = [
+            ("12C(d,p)14C", ReactionError::BaryonNotConserved),
+            ("12C(d,p)13N", ReactionError::ChargeNotConserved),
+            ("12C(d,a)11B", ReactionError::BaryonNotConserved),
+            ("d(p,12C)", ReactionError::ChargeNotConserved),
+            ("12C(d,14C)", ReactionError::BaryonNotConserved),
+            ("12C(d,p", ReactionError::InvalidFormat(String::from("12C(d,p"))),
+            ("12C(d)13C", ReactionError::InvalidFormat(String::from("12C(d)13C"))),
+            ("12Xx(d,p)", ReactionError::UnknownElement(String::from("12Xx")))
+        ];
+        for (eqn, error) in cases {
+            assert_eq!(parse_reaction(eqn, &nuc_map), Err(error), "{}", eqn);
+        }
+    }
+}
@@ -0,0 +1,1372 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use chrono::{Datelike, Local, Timelike};
+use flate2::Compression as ZlibLevel;
+use flate2::write::ZlibEncoder;
+use polars::prelude::*;
+use polars::export::arrow::array::Array;
+use rand::Rng;
+
+/// Name of the tree in the ROOT files written by spsevb, the same as the old SPS_SABRE_EventBuilder
+pub const TREE_NAME: &str = "SPSTree";
+pub const TREE_TITLE: &str = "spsevb event built data";
+
+//Layout of the file. Everything is written in the 64-bit (large file) format, so files can grow past 2 GB.
+const ROOT_VERSION: i32 = 1_062_400; //ROOT 6.24/00, +1000000 for the large file format
+const BEGIN: u64 = 100; //Position of the top directory; the file header is before it
+const KEY_VERSION: i16 = 1004;
+const DIRECTORY_VERSION: i16 = 1005;
+const DIRECTORY_SIZE: usize = 60;
+const FREE_VERSION: i16 = 1001;
+const UUID_VERSION: i16 = 1;
+const SEEK_UNITS: u8 = 8;
+const KEY_FIXED_SIZE: usize = 34; //Key header without the class name, name, and title
+const BASKET_HEADER_SIZE: usize = 19; //TBasket fields following its key header
+
+//Compression, zlib level 1. ROOT compresses in blocks with a 9 byte header each.
+const COMPRESSION_SETTING: i32 = 101;
+const ZLIB_LEVEL: u32 = 1;
+const MAX_BLOCK_SIZE: usize = 0xff_ffff;
+
+//Object serialization
+const BYTE_COUNT_MASK: u32 = 0x4000_0000;
+const CLASS_MASK: u32 = 0x8000_0000;
+const NEW_CLASS_TAG: u32 = 0xffff_ffff;
+const MAP_OFFSET: usize = 2;
+const OBJECT_BITS: u32 = 0x0300_0000;
+//ROOT::TIOFeatures, written with its checksum and no features set
+const IO_FEATURES: [u8; 11] = [0x40, 0x00, 0x00, 0x07, 0x00, 0x00, 0x1a, 0xa1, 0x2f, 0x10, 0x00];
+//Class version ROOT gives STL collections, used for the vector branches and each vector written to them
+const STL_VERSION: i16 = 9;
+
+//Class versions
+const TREE_VERSION: i16 = 20;
+const BRANCH_VERSION: i16 = 13;
+const BRANCH_ELEMENT_VERSION: i16 = 10;
+const LEAF_VERSION: i16 = 2;
+const LEAF_TYPE_VERSION: i16 = 1;
+const BASKET_VERSION: i16 = 3;
+const OBJECT_ARRAY_VERSION: i16 = 3;
+const LIST_VERSION: i16 = 5;
+
+//Defaults of the tree and branches, as ROOT sets them
+const BASKET_SIZE: i32 = 32_000;
+const ENTRY_OFFSET_LEN: i32 = 1_000;
+const MIN_MAX_BASKETS: usize = 10;
+
+//Type of the values of a branch (or the elements of a vector branch)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LeafType {
+    Bool,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double
+}
+
+impl LeafType {
+    fn from_dtype(dtype: &DataType) -> Option<Self> {
+        match dtype {
+            DataType::Boolean => Some(LeafType::Bool),
+            DataType::UInt16 => Some(LeafType::UShort),
+            DataType::Int32 => Some(LeafType::Int),
+            DataType::UInt32 => Some(LeafType::UInt),
+            DataType::Int64 => Some(LeafType::Long),
+            DataType::UInt64 => Some(LeafType::ULong),
+            DataType::Float32 => Some(LeafType::Float),
+            DataType::Float64 => Some(LeafType::Double),
+            _ => None
+        }
+    }
+
+    fn get_size(&self) -> usize {
+        match self {
+            LeafType::Bool => 1,
+            LeafType::UShort => 2,
+            LeafType::Int | LeafType::UInt | LeafType::Float => 4,
+            LeafType::Long | LeafType::ULong | LeafType::Double => 8
+        }
+    }
+
+    //Type code of the leaf list (i.e. Xavg/D)
+    fn get_code(&self) -> char {
+        match self {
+            LeafType::Bool => 'O',
+            LeafType::UShort => 's',
+            LeafType::Int => 'I',
+            LeafType::UInt => 'i',
+            LeafType::Long => 'L',
+            LeafType::ULong => 'l',
+            LeafType::Float => 'F',
+            LeafType::Double => 'D'
+        }
+    }
+
+    fn get_leaf_class(&self) -> &'static str {
+        match self {
+            LeafType::Bool => "TLeafO",
+            LeafType::UShort => "TLeafS",
+            LeafType::Int | LeafType::UInt => "TLeafI",
+            LeafType::Long | LeafType::ULong => "TLeafL",
+            LeafType::Float => "TLeafF",
+            LeafType::Double => "TLeafD"
+        }
+    }
+
+    fn is_unsigned(&self) -> bool {
+        matches!(self, LeafType::UShort | LeafType::UInt | LeafType::ULong)
+    }
+
+    //C++ type of the elements of a vector branch. There is no vector<bool> branch, as ROOT stores it as a bit field.
+    fn get_vector_element(&self) -> Option<&'static str> {
+        match self {
+            LeafType::Bool => None,
+            LeafType::UShort => Some("unsigned short"),
+            LeafType::Int => Some("int"),
+            LeafType::UInt => Some("unsigned int"),
+            LeafType::Long => Some("Long64_t"),
+            LeafType::ULong => Some("ULong64_t"),
+            LeafType::Float => Some("float"),
+            LeafType::Double => Some("double")
+        }
+    }
+
+    //Append the values of a series of this type, big endian. ROOT has no nulls, so they are written as 0.
+    fn put_values(&self, series: &Series, out: &mut Vec<u8>) -> Result<(), PolarsError> {
+        match self {
+            LeafType::Bool => series.bool()?.into_iter().for_each(|v| out.push(v.unwrap_or(false) as u8)),
+            LeafType::UShort => series.u16()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0).to_be_bytes())),
+            LeafType::Int => series.i32()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0).to_be_bytes())),
+            LeafType::UInt => series.u32()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0).to_be_bytes())),
+            LeafType::Long => series.i64()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0).to_be_bytes())),
+            LeafType::ULong => series.u64()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0).to_be_bytes())),
+            LeafType::Float => series.f32()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0.0).to_be_bytes())),
+            LeafType::Double => series.f64()?.into_iter().for_each(|v| out.extend_from_slice(&v.unwrap_or(0.0).to_be_bytes()))
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BranchKind {
+    //One value per entry, a TBranch with a single leaf (i.e. Xavg/D)
+    Scalar(LeafType),
+    //A string per entry, Name/C
+    Text,
+    //A std::vector per entry, a TBranchElement
+    Vector(LeafType)
+}
+
+//A basket of a branch which has been written to the file
+#[derive(Debug, Clone)]
+struct BasketRecord {
+    seek: u64,
+    bytes: i32,
+    first_entry: i64
+}
+
+#[derive(Debug, Clone)]
+struct Branch {
+    name: String,
+    column: String,
+    //Field of the struct in a list of structs (i.e. the Energy of the SABRE ring hits)
+    field: Option<String>,
+    kind: BranchKind,
+    baskets: Vec<BasketRecord>,
+    total_bytes: i64,
+    zipped_bytes: i64,
+    max_text_length: usize
+}
+
+impl Branch {
+    fn new(name: String, column: &str, field: Option<&str>, kind: BranchKind) -> Self {
+        Branch {
+            name,
+            column: column.to_string(),
+            field: field.map(|f| f.to_string()),
+            kind,
+            baskets: vec![],
+            total_bytes: 0,
+            zipped_bytes: 0,
+            max_text_length: 0
+        }
+    }
+
+    fn get_title(&self) -> String {
+        match self.kind {
+            BranchKind::Scalar(leaf) => format!("{}/{}", self.name, leaf.get_code()),
+            BranchKind::Text => format!("{}/C", self.name),
+            BranchKind::Vector(_) => self.name.clone()
+        }
+    }
+
+    fn has_entry_offsets(&self) -> bool {
+        !matches!(self.kind, BranchKind::Scalar(_))
+    }
+
+    //Contents of a basket holding the column: the data, and where each entry starts in it (for entries which vary in size)
+    fn fill_basket(&mut self, column: &Series) -> Result<(Vec<u8>, Option<Vec<usize>>), PolarsError> {
+        let mut data: Vec<u8> = vec![];
+        match self.kind {
+            BranchKind::Scalar(leaf) => {
+                leaf.put_values(column, &mut data)?;
+                Ok((data, None))
+            }
+            BranchKind::Text => {
+                let mut offsets: Vec<usize> = Vec::with_capacity(column.len());
+                for value in column.utf8()?.into_iter() {
+                    let value = value.unwrap_or("");
+                    offsets.push(data.len());
+                    if value.len() < 255 {
+                        data.push(value.len() as u8);
+                    } else {
+                        data.push(255);
+                        data.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                    }
+                    data.extend_from_slice(value.as_bytes());
+                    self.max_text_length = self.max_text_length.max(value.len());
+                }
+                Ok((data, Some(offsets)))
+            }
+            BranchKind::Vector(leaf) => {
+                let size = leaf.get_size();
+                let mut offsets: Vec<usize> = Vec::with_capacity(column.len());
+                for array in column.list()?.downcast_iter() {
+                    let mut values = Series::try_from(("", array.values().clone()))?;
+                    if let Some(field) = &self.field {
+                        values = values.struct_()?.field_by_name(field)?;
+                    }
+                    let mut encoded: Vec<u8> = vec![];
+                    leaf.put_values(&values, &mut encoded)?;
+                    for row in 0..array.len() {
+                        let (start, end) = if array.is_valid(row) { array.offsets().start_end(row) } else { (0, 0) };
+                        let length = end - start;
+                        offsets.push(data.len());
+                        //Each vector is written as an object: byte count, version, then the size and the elements
+                        data.extend_from_slice(&(BYTE_COUNT_MASK | (6 + length * size) as u32).to_be_bytes());
+                        data.extend_from_slice(&STL_VERSION.to_be_bytes());
+                        data.extend_from_slice(&(length as i32).to_be_bytes());
+                        data.extend_from_slice(&encoded[(start * size)..(end * size)]);
+                    }
+                }
+                Ok((data, Some(offsets)))
+            }
+        }
+    }
+}
+
+//Branches for the columns of a dataframe. Lists of structs (i.e. the SABRE hits) get a vector branch for each field,
+//<Column>_<Field>, the same as the CSV columns.
+fn make_branches(schema: &Schema) -> Result<Vec<Branch>, PolarsError> {
+    let unsupported = |name: &str, dtype: &DataType| {
+        PolarsError::InvalidOperation(format!("Column {} of type {} cannot be written to a ROOT tree", name, dtype).into())
+    };
+    let get_vector_type = |name: &str, dtype: &DataType| {
+        match LeafType::from_dtype(dtype) {
+            Some(leaf) if leaf.get_vector_element().is_some() => Ok(leaf),
+            _ => Err(unsupported(name, dtype))
+        }
+    };
+
+    let mut branches: Vec<Branch> = vec![];
+    for (name, dtype) in schema.iter() {
+        match dtype {
+            DataType::Utf8 => branches.push(Branch::new(name.to_string(), name, None, BranchKind::Text)),
+            DataType::List(inner) => match inner.as_ref() {
+                DataType::Struct(fields) => {
+                    for field in fields.iter() {
+                        let branch_name = format!("{}_{}", name, field.name());
+                        let leaf = get_vector_type(&branch_name, field.data_type())?;
+                        branches.push(Branch::new(branch_name, name, Some(field.name()), BranchKind::Vector(leaf)));
+                    }
+                }
+                inner => branches.push(Branch::new(name.to_string(), name, None, BranchKind::Vector(get_vector_type(name, inner)?)))
+            },
+            dtype => match LeafType::from_dtype(dtype) {
+                Some(leaf) => branches.push(Branch::new(name.to_string(), name, None, BranchKind::Scalar(leaf))),
+                None => return Err(unsupported(name, dtype))
+            }
+        }
+    }
+    Ok(branches)
+}
+
+//Data in ROOT's serialization format. Objects reference earlier objects and classes by their position in the record,
+//which includes the key header in front of the data.
+struct RootBuffer {
+    data: Vec<u8>,
+    key_length: usize,
+    classes: HashMap<&'static str, u32>
+}
+
+impl RootBuffer {
+    fn new(key_length: usize) -> Self {
+        RootBuffer { data: vec![], key_length, classes: HashMap::new() }
+    }
+
+    fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn put_i16(&mut self, value: i16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_i32(&mut self, value: i32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_i64(&mut self, value: i64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_f64(&mut self, value: f64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    //TString: the length in one byte (or 255 and then four bytes, for long strings), then the characters
+    fn put_string(&mut self, value: &str) {
+        if value.len() < 255 {
+            self.put_u8(value.len() as u8);
+        } else {
+            self.put_u8(255);
+            self.put_i32(value.len() as i32);
+        }
+        self.put_bytes(value.as_bytes());
+    }
+
+    //Reserve the byte count of an object, filled in by end_count once the object is written
+    fn start_count(&mut self) -> usize {
+        let position = self.data.len();
+        self.put_u32(0);
+        position
+    }
+
+    fn end_count(&mut self, position: usize) {
+        let count = (self.data.len() - position - 4) as u32 | BYTE_COUNT_MASK;
+        self.data[position..(position + 4)].copy_from_slice(&count.to_be_bytes());
+    }
+
+    //Byte count and class version, which start the data of most classes
+    fn start_versioned(&mut self, version: i16) -> usize {
+        let position = self.start_count();
+        self.put_i16(version);
+        position
+    }
+
+    //An object written through a pointer: byte count, then the class, by name the first time and then by reference.
+    //Returns the position of the byte count, and the reference to the object for later pointers to it.
+    fn start_object(&mut self, class: &'static str) -> (usize, u32) {
+        let position = self.start_count();
+        let reference = (position + self.key_length + MAP_OFFSET) as u32;
+        match self.classes.get(class) {
+            Some(tag) => self.put_u32(tag | CLASS_MASK),
+            None => {
+                let tag = (self.data.len() + self.key_length + MAP_OFFSET) as u32;
+                self.put_u32(NEW_CLASS_TAG);
+                self.put_bytes(class.as_bytes());
+                self.put_u8(0);
+                self.classes.insert(class, tag);
+            }
+        }
+        (position, reference)
+    }
+
+    fn put_object(&mut self) {
+        self.put_i16(1);
+        self.put_u32(0);
+        self.put_u32(OBJECT_BITS);
+    }
+
+    fn put_named(&mut self, name: &str, title: &str) {
+        let named = self.start_versioned(1);
+        self.put_object();
+        self.put_string(name);
+        self.put_string(title);
+        self.end_count(named);
+    }
+
+    fn put_line_attributes(&mut self) {
+        let attributes = self.start_versioned(2);
+        self.put_i16(1); //color
+        self.put_i16(1); //style
+        self.put_i16(1); //width
+        self.end_count(attributes);
+    }
+
+    fn put_fill_attributes(&mut self) {
+        let attributes = self.start_versioned(2);
+        self.put_i16(0); //color
+        self.put_i16(1001); //style
+        self.end_count(attributes);
+    }
+
+    fn put_marker_attributes(&mut self) {
+        let attributes = self.start_versioned(2);
+        self.put_i16(1); //color
+        self.put_i16(1); //style
+        self.put_f32(1.0); //size
+        self.end_count(attributes);
+    }
+
+    //Start a TObjArray of n objects, which the caller writes before ending the count
+    fn start_array(&mut self, n: usize) -> usize {
+        let array = self.start_versioned(OBJECT_ARRAY_VERSION);
+        self.put_object();
+        self.put_string("");
+        self.put_i32(n as i32);
+        self.put_i32(0); //lower bound
+        array
+    }
+
+    //Array member with a length given by another member: a flag that it is present, then the values
+    fn put_counted_i32(&mut self, values: &[i32]) {
+        self.put_u8(1);
+        values.iter().for_each(|v| self.put_i32(*v));
+    }
+
+    fn put_counted_i64(&mut self, values: &[i64]) {
+        self.put_u8(1);
+        values.iter().for_each(|v| self.put_i64(*v));
+    }
+}
+
+fn get_string_length(value: &str) -> usize {
+    if value.len() < 255 { value.len() + 1 } else { value.len() + 5 }
+}
+
+fn get_key_length(class_name: &str, name: &str, title: &str) -> usize {
+    KEY_FIXED_SIZE + get_string_length(class_name) + get_string_length(name) + get_string_length(title)
+}
+
+//Header of a record (TKey) in the file
+struct KeyHeader<'a> {
+    class_name: &'a str,
+    name: &'a str,
+    title: &'a str,
+    key_length: usize,
+    object_length: usize,
+    stored_length: usize,
+    seek: u64,
+    directory: u64
+}
+
+impl KeyHeader<'_> {
+    fn write(&self, buffer: &mut RootBuffer, datime: u32) {
+        buffer.put_i32((self.key_length + self.stored_length) as i32);
+        buffer.put_i16(KEY_VERSION);
+        buffer.put_i32(self.object_length as i32);
+        buffer.put_u32(datime);
+        buffer.put_i16(self.key_length as i16);
+        buffer.put_i16(1); //cycle
+        buffer.put_i64(self.seek as i64);
+        buffer.put_i64(self.directory as i64);
+        buffer.put_string(self.class_name);
+        buffer.put_string(self.name);
+        buffer.put_string(self.title);
+    }
+}
+
+//A record written to the file, with its header (which the directory's list of keys repeats)
+struct KeyRecord {
+    seek: u64,
+    bytes: usize,
+    header: Vec<u8>
+}
+
+//Compress the object in ROOT's format: blocks of up to 16 MB, each with a header of "ZL", the method (8, deflate), and
+//the compressed and uncompressed sizes (3 bytes each, little endian). None if compressing does not make it smaller.
+fn compress(object: &[u8]) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut stored: Vec<u8> = Vec::with_capacity(object.len() / 2);
+    for block in object.chunks(MAX_BLOCK_SIZE) {
+        let mut encoder = ZlibEncoder::new(Vec::with_capacity(block.len() / 2), ZlibLevel::new(ZLIB_LEVEL));
+        encoder.write_all(block)?;
+        let compressed = encoder.finish()?;
+        if compressed.len() > MAX_BLOCK_SIZE {
+            return Ok(None);
+        }
+        stored.extend_from_slice(b"ZL");
+        stored.push(8);
+        stored.extend_from_slice(&(compressed.len() as u32).to_le_bytes()[..3]);
+        stored.extend_from_slice(&(block.len() as u32).to_le_bytes()[..3]);
+        stored.extend_from_slice(&compressed);
+    }
+    if stored.len() >= object.len() {
+        return Ok(None);
+    }
+    Ok(Some(stored))
+}
+
+//Date and time packed the way ROOT stores it
+fn get_datime() -> u32 {
+    let now = Local::now();
+    ((now.year() - 1995) as u32) << 26 | now.month() << 22 | now.day() << 17 | now.hour() << 12 | now.minute() << 6 | now.second()
+}
+
+/// Writes the columns of dataframes to a TTree in a ROOT file, without needing a ROOT installation.
+///
+/// Each column is a branch: numeric columns are branches of a single leaf (i.e. `Xavg/D`), strings are `Name/C`, and
+/// lists are `std::vector` branches, where a list of structs (the SABRE and hit lists) gets a vector branch for each
+/// field, `<Column>_<Field>`. Nulls are written as 0 (or an empty string or vector). Each batch is written as one
+/// basket of each branch, compressed with zlib; the tree itself is written when the writer is finished.
+pub struct TreeWriter {
+    file: File,
+    file_name: String,
+    tree_name: String,
+    title: String,
+    branches: Vec<Branch>,
+    entries: i64,
+    end: u64,
+    datime: u32,
+    uuid: [u8; 16]
+}
+
+impl TreeWriter {
+    /// Start a ROOT file with a tree for dataframes with the given schema. The file should be new (empty).
+    pub fn new(mut file: File, file_name: &str, tree_name: &str, title: &str, schema: &Schema) -> Result<Self, PolarsError> {
+        let branches = make_branches(schema)?;
+        //The file header and the top directory are written once the rest of the file is known
+        let reserved = BEGIN as usize + get_key_length("TFile", file_name, title) + get_string_length(file_name)
+                       + get_string_length(title) + DIRECTORY_SIZE;
+        file.write_all(&vec![0; reserved])?;
+        Ok(TreeWriter {
+            file,
+            file_name: file_name.to_string(),
+            tree_name: tree_name.to_string(),
+            title: title.to_string(),
+            branches,
+            entries: 0,
+            end: reserved as u64,
+            datime: get_datime(),
+            uuid: rand::thread_rng().gen()
+        })
+    }
+
+    /// Write the rows of the dataframe as one basket of each branch
+    pub fn write_batch(&mut self, df: &DataFrame) -> Result<(), PolarsError> {
+        if df.height() == 0 {
+            return Ok(());
+        }
+        for index in 0..self.branches.len() {
+            let column = df.column(&self.branches[index].column)?;
+            let (data, offsets) = self.branches[index].fill_basket(column)?;
+            self.write_basket(index, data, offsets, df.height())?;
+        }
+        self.entries += df.height() as i64;
+        Ok(())
+    }
+
+    /// Write the tree, the directory, and the file header
+    pub fn finish(&mut self) -> Result<(), PolarsError> {
+        let tree_key_length = get_key_length("TTree", &self.tree_name, &self.title);
+        let tree = self.serialize_tree(tree_key_length);
+        let tree_key = self.write_key("TTree", &self.tree_name.clone(), &self.title.clone(), &tree, true)?;
+
+        //The classes of the tree are all ones which ROOT knows, so the streamer information is left empty. This has only
+        //been checked with the reader of the tests, not with ROOT, which is why the format is still experimental.
+        let mut info = RootBuffer::new(get_key_length("TList", "StreamerInfo", "Doubly linked list"));
+        let list = info.start_versioned(LIST_VERSION);
+        info.put_object();
+        info.put_string("");
+        info.put_i32(0);
+        info.end_count(list);
+        let info_key = self.write_key("TList", "StreamerInfo", "Doubly linked list", &info.data, true)?;
+
+        let file_name = self.file_name.clone();
+        let title = self.title.clone();
+        let mut keys = RootBuffer::new(0);
+        keys.put_i32(1);
+        keys.put_bytes(&tree_key.header);
+        let keys_key = self.write_key("TFile", &file_name, &title, &keys.data, false)?;
+
+        //The free segment is everything past the end of the file, which ends with the record of the free segment
+        let free_start = self.end + (get_key_length("TFile", &file_name, &title) + 18) as u64;
+        let mut free = RootBuffer::new(0);
+        free.put_i16(FREE_VERSION);
+        free.put_i64(free_start as i64);
+        free.put_i64(free_start.max(2_000_000_000) as i64);
+        let free_key = self.write_key("TFile", &file_name, &title, &free.data, false)?;
+
+        let mut header = RootBuffer::new(0);
+        header.put_bytes(b"root");
+        header.put_i32(ROOT_VERSION);
+        header.put_i32(BEGIN as i32);
+        header.put_i64(self.end as i64);
+        header.put_i64(free_key.seek as i64);
+        header.put_i32(free_key.bytes as i32);
+        header.put_i32(1); //number of free segments
+        let name_length = get_key_length("TFile", &file_name, &title) + get_string_length(&file_name) + get_string_length(&title);
+        header.put_i32(name_length as i32);
+        header.put_u8(SEEK_UNITS);
+        header.put_i32(COMPRESSION_SETTING);
+        header.put_i64(info_key.seek as i64);
+        header.put_i32(info_key.bytes as i32);
+        header.put_i16(UUID_VERSION);
+        header.put_bytes(&self.uuid);
+        header.data.resize(BEGIN as usize, 0);
+
+        //The top directory: a key for the file, its name and title, and the directory itself
+        let directory_key = KeyHeader {
+            class_name: "TFile",
+            name: &file_name,
+            title: &title,
+            key_length: get_key_length("TFile", &file_name, &title),
+            object_length: name_length - get_key_length("TFile", &file_name, &title) + DIRECTORY_SIZE,
+            stored_length: name_length - get_key_length("TFile", &file_name, &title) + DIRECTORY_SIZE,
+            seek: BEGIN,
+            directory: 0
+        };
+        directory_key.write(&mut header, self.datime);
+        header.put_string(&file_name);
+        header.put_string(&title);
+        header.put_i16(DIRECTORY_VERSION);
+        header.put_u32(self.datime);
+        header.put_u32(self.datime);
+        header.put_i32(keys_key.bytes as i32);
+        header.put_i32(name_length as i32);
+        header.put_i64(BEGIN as i64);
+        header.put_i64(0); //parent
+        header.put_i64(keys_key.seek as i64);
+        header.put_i16(UUID_VERSION);
+        header.put_bytes(&self.uuid);
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header.data)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> Result<(), std::io::Error> {
+        self.file.write_all(record)?;
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn write_key(&mut self, class_name: &str, name: &str, title: &str, object: &[u8], compressed: bool) -> Result<KeyRecord, std::io::Error> {
+        let compressed_object = if compressed { compress(object)? } else { None };
+        let stored = compressed_object.as_deref().unwrap_or(object);
+        let key = KeyHeader {
+            class_name,
+            name,
+            title,
+            key_length: get_key_length(class_name, name, title),
+            object_length: object.len(),
+            stored_length: stored.len(),
+            seek: self.end,
+            directory: BEGIN
+        };
+        let mut record = RootBuffer::new(0);
+        key.write(&mut record, self.datime);
+        let header = record.data.clone();
+        record.put_bytes(stored);
+        self.write_record(&record.data)?;
+        Ok(KeyRecord { seek: key.seek, bytes: record.data.len(), header })
+    }
+
+    //A basket is a key followed by the basket fields, then the data. Entries which vary in size are followed by the
+    //position of each entry (counted from the start of the key).
+    fn write_basket(&mut self, index: usize, data: Vec<u8>, offsets: Option<Vec<usize>>, n_entries: usize) -> Result<(), std::io::Error> {
+        let branch = &self.branches[index];
+        let key_length = get_key_length("TBasket", &branch.name, &self.tree_name) + BASKET_HEADER_SIZE;
+        let last = key_length + data.len();
+        let mut object = RootBuffer { data, key_length, classes: HashMap::new() };
+        let entry_size = match (&offsets, branch.kind) {
+            (Some(offsets), _) => {
+                object.put_i32(n_entries as i32 + 1);
+                offsets.iter().for_each(|offset| object.put_i32((key_length + offset) as i32));
+                object.put_i32(0);
+                (n_entries + 1).max(ENTRY_OFFSET_LEN as usize)
+            }
+            (None, BranchKind::Scalar(leaf)) => leaf.get_size(),
+            (None, _) => 0
+        };
+
+        let compressed = compress(&object.data)?;
+        let stored = compressed.as_deref().unwrap_or(&object.data);
+        let key = KeyHeader {
+            class_name: "TBasket",
+            name: &branch.name,
+            title: &self.tree_name,
+            key_length,
+            object_length: object.data.len(),
+            stored_length: stored.len(),
+            seek: self.end,
+            directory: BEGIN
+        };
+        let mut record = RootBuffer::new(0);
+        key.write(&mut record, self.datime);
+        record.put_i16(BASKET_VERSION);
+        record.put_i32((key_length + object.data.len()) as i32); //buffer size
+        record.put_i32(entry_size as i32);
+        record.put_i32(n_entries as i32);
+        record.put_i32(last as i32);
+        record.put_u8(0); //flag: the basket is written on its own
+        record.put_bytes(stored);
+
+        let basket = BasketRecord { seek: self.end, bytes: record.data.len() as i32, first_entry: self.entries };
+        let total_bytes = (key_length + object.data.len()) as i64;
+        self.write_record(&record.data)?;
+        let branch = &mut self.branches[index];
+        branch.total_bytes += total_bytes;
+        branch.zipped_bytes += basket.bytes as i64;
+        branch.baskets.push(basket);
+        Ok(())
+    }
+
+    fn serialize_tree(&self, key_length: usize) -> Vec<u8> {
+        let total_bytes: i64 = self.branches.iter().map(|b| b.total_bytes).sum();
+        let zipped_bytes: i64 = self.branches.iter().map(|b| b.zipped_bytes).sum();
+        let mut buffer = RootBuffer::new(key_length);
+        let tree = buffer.start_versioned(TREE_VERSION);
+        buffer.put_named(&self.tree_name, &self.title);
+        buffer.put_line_attributes();
+        buffer.put_fill_attributes();
+        buffer.put_marker_attributes();
+        buffer.put_i64(self.entries);
+        buffer.put_i64(total_bytes);
+        buffer.put_i64(zipped_bytes);
+        buffer.put_i64(zipped_bytes); //saved bytes
+        buffer.put_i64(zipped_bytes); //flushed bytes
+        buffer.put_f64(1.0); //weight
+        buffer.put_i32(0); //timer interval
+        buffer.put_i32(25); //scan field
+        buffer.put_i32(0); //update
+        buffer.put_i32(ENTRY_OFFSET_LEN);
+        buffer.put_i32(0); //number of cluster ranges
+        buffer.put_i64(1_000_000_000_000); //max entries
+        buffer.put_i64(1_000_000_000_000); //max entry loop
+        buffer.put_i64(0); //max virtual size
+        buffer.put_i64(-300_000_000); //auto save
+        buffer.put_i64(0); //auto flush: batches vary in size, so there is no fixed cluster size
+        buffer.put_i64(1_000_000); //estimate
+        buffer.put_u8(0); //no cluster range ends
+        buffer.put_u8(0); //no cluster sizes
+        buffer.put_bytes(&IO_FEATURES);
+
+        let branches = buffer.start_array(self.branches.len());
+        let leaves: Vec<u32> = self.branches.iter().map(|branch| self.put_branch(&mut buffer, branch)).collect();
+        buffer.end_count(branches);
+        //The tree's list of leaves refers back to the leaves of the branches
+        let leaf_array = buffer.start_array(leaves.len());
+        leaves.iter().for_each(|leaf| buffer.put_u32(*leaf));
+        buffer.end_count(leaf_array);
+
+        buffer.put_u32(0); //aliases
+        buffer.put_i32(0); //index values
+        buffer.put_i32(0); //index
+        buffer.put_u32(0); //tree index
+        buffer.put_u32(0); //friends
+        buffer.put_u32(0); //user info
+        buffer.put_u32(0); //branch ref
+        buffer.end_count(tree);
+        buffer.data
+    }
+
+    //Write a branch, returning the reference to its leaf
+    fn put_branch(&self, buffer: &mut RootBuffer, branch: &Branch) -> u32 {
+        let vector_element = match branch.kind {
+            BranchKind::Vector(leaf) => leaf.get_vector_element(),
+            _ => None
+        };
+        let (object, _) = buffer.start_object(if vector_element.is_some() { "TBranchElement" } else { "TBranch" });
+        let element = vector_element.map(|_| buffer.start_versioned(BRANCH_ELEMENT_VERSION));
+
+        let n_baskets = branch.baskets.len();
+        let max_baskets = (n_baskets + 1).max(MIN_MAX_BASKETS);
+        let base = buffer.start_versioned(BRANCH_VERSION);
+        buffer.put_named(&branch.name, &branch.get_title());
+        buffer.put_fill_attributes();
+        buffer.put_i32(COMPRESSION_SETTING);
+        buffer.put_i32(BASKET_SIZE);
+        buffer.put_i32(if branch.has_entry_offsets() { ENTRY_OFFSET_LEN } else { 0 });
+        buffer.put_i32(n_baskets as i32); //write basket
+        buffer.put_i64(self.entries); //entry number
+        buffer.put_bytes(&IO_FEATURES);
+        buffer.put_i32(0); //offset
+        buffer.put_i32(max_baskets as i32);
+        buffer.put_i32(0); //split level
+        buffer.put_i64(self.entries);
+        buffer.put_i64(0); //first entry
+        buffer.put_i64(branch.total_bytes);
+        buffer.put_i64(branch.zipped_bytes);
+
+        let sub_branches = buffer.start_array(0);
+        buffer.end_count(sub_branches);
+        let leaves = buffer.start_array(1);
+        let leaf = self.put_leaf(buffer, branch);
+        buffer.end_count(leaves);
+        //All of the baskets are in the file rather than in the tree
+        let baskets = buffer.start_array(0);
+        buffer.end_count(baskets);
+
+        let mut basket_bytes = vec![0; max_baskets];
+        let mut basket_entries = vec![0; max_baskets];
+        let mut basket_seeks = vec![0; max_baskets];
+        for (i, basket) in branch.baskets.iter().enumerate() {
+            basket_bytes[i] = basket.bytes;
+            basket_entries[i] = basket.first_entry;
+            basket_seeks[i] = basket.seek as i64;
+        }
+        basket_entries[n_baskets] = self.entries;
+        buffer.put_counted_i32(&basket_bytes);
+        buffer.put_counted_i64(&basket_entries);
+        buffer.put_counted_i64(&basket_seeks);
+        buffer.put_string(""); //file name, this file
+        buffer.end_count(base);
+
+        if let (Some(element), Some(element_type)) = (element, vector_element) {
+            buffer.put_string(&format!("vector<{}>", element_type));
+            buffer.put_string(""); //parent
+            buffer.put_string(""); //clones
+            buffer.put_u32(0); //checksum
+            buffer.put_i16(STL_VERSION);
+            buffer.put_i32(-1); //id: the whole object
+            buffer.put_i32(0); //type: top level
+            buffer.put_i32(-1); //streamer type
+            buffer.put_i32(0); //maximum
+            buffer.put_u32(0); //branch count
+            buffer.put_u32(0); //branch count 2
+            buffer.end_count(element);
+        }
+        buffer.end_count(object);
+        leaf
+    }
+
+    fn put_leaf(&self, buffer: &mut RootBuffer, branch: &Branch) -> u32 {
+        let class = match branch.kind {
+            BranchKind::Scalar(leaf) => leaf.get_leaf_class(),
+            BranchKind::Text => "TLeafC",
+            BranchKind::Vector(_) => "TLeafElement"
+        };
+        let (object, reference) = buffer.start_object(class);
+        let outer = buffer.start_versioned(LEAF_TYPE_VERSION);
+        let leaf = buffer.start_versioned(LEAF_VERSION);
+        buffer.put_named(&branch.name, &branch.name);
+        let (length, length_type, unsigned) = match branch.kind {
+            BranchKind::Scalar(leaf) => (1, leaf.get_size(), leaf.is_unsigned()),
+            BranchKind::Text => (branch.max_text_length + 1, 1, false),
+            BranchKind::Vector(_) => (1, 0, false)
+        };
+        buffer.put_i32(length as i32);
+        buffer.put_i32(length_type as i32);
+        buffer.put_i32(0); //offset
+        buffer.put_u8(0); //is range
+        buffer.put_u8(unsigned as u8);
+        buffer.put_u32(0); //leaf count
+        buffer.end_count(leaf);
+        match branch.kind {
+            //Minimum and maximum, which are not tracked
+            BranchKind::Scalar(leaf) => buffer.put_bytes(&vec![0; 2 * leaf.get_size()]),
+            BranchKind::Text => {
+                buffer.put_i32(0);
+                buffer.put_i32(branch.max_text_length as i32 + 1);
+            }
+            //The id and type of the element, -1 for the whole vector
+            BranchKind::Vector(_) => {
+                buffer.put_i32(-1);
+                buffer.put_i32(-1);
+            }
+        }
+        buffer.end_count(outer);
+        buffer.end_count(object);
+        reference
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+    use flate2::read::ZlibDecoder;
+    use polars::chunked_array::builder::get_list_builder;
+
+    //A minimal reader of the files written here, which follows the file header and the directory to the tree, and
+    //the branches of the tree to their baskets
+    struct Reader<'a> {
+        data: &'a [u8],
+        position: usize
+    }
+
+    impl<'a> Reader<'a> {
+        fn new(data: &'a [u8], position: usize) -> Self {
+            Reader { data, position }
+        }
+
+        fn take(&mut self, n: usize) -> &'a [u8] {
+            let bytes = &self.data[self.position..(self.position + n)];
+            self.position += n;
+            bytes
+        }
+
+        fn u8(&mut self) -> u8 {
+            self.take(1)[0]
+        }
+
+        fn i16(&mut self) -> i16 {
+            i16::from_be_bytes(self.take(2).try_into().unwrap())
+        }
+
+        fn i32(&mut self) -> i32 {
+            i32::from_be_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_be_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn i64(&mut self) -> i64 {
+            i64::from_be_bytes(self.take(8).try_into().unwrap())
+        }
+
+        fn string(&mut self) -> String {
+            let mut length = self.u8() as usize;
+            if length == 255 {
+                length = self.i32() as usize;
+            }
+            String::from_utf8(self.take(length).to_vec()).unwrap()
+        }
+
+        //Byte count and version; returns the end of the object
+        fn versioned(&mut self, version: i16) -> usize {
+            let count = self.u32();
+            assert_ne!(count & BYTE_COUNT_MASK, 0);
+            let end = self.position + (count & !BYTE_COUNT_MASK) as usize;
+            assert_eq!(self.i16(), version);
+            end
+        }
+
+        fn skip_to(&mut self, end: usize) {
+            assert!(self.position <= end);
+            self.position = end;
+        }
+
+        fn named(&mut self) -> (String, String) {
+            let end = self.versioned(1);
+            self.take(10);
+            let named = (self.string(), self.string());
+            assert_eq!(self.position, end);
+            named
+        }
+
+        fn array_header(&mut self) -> (usize, usize) {
+            let end = self.versioned(OBJECT_ARRAY_VERSION);
+            self.take(10);
+            self.string();
+            let n = self.i32() as usize;
+            assert_eq!(self.i32(), 0);
+            (end, n)
+        }
+
+        //Class of an object written through a pointer, resolving references to earlier classes
+        fn class(&mut self, key_length: usize, classes: &mut HashMap<u32, String>) -> String {
+            let tag_position = (self.position + key_length + MAP_OFFSET) as u32;
+            let tag = self.u32();
+            if tag == NEW_CLASS_TAG {
+                let start = self.position;
+                while self.data[self.position] != 0 {
+                    self.position += 1;
+                }
+                let class = String::from_utf8(self.data[start..self.position].to_vec()).unwrap();
+                self.position += 1;
+                classes.insert(tag_position, class.clone());
+                class
+            } else {
+                assert_ne!(tag & CLASS_MASK, 0);
+                classes[&(tag & !CLASS_MASK)].clone()
+            }
+        }
+    }
+
+    struct Key {
+        class_name: String,
+        name: String,
+        key_length: usize,
+        object_length: usize,
+        seek: u64,
+        bytes: usize
+    }
+
+    fn read_key(data: &[u8], position: usize) -> Key {
+        let mut reader = Reader::new(data, position);
+        let bytes = reader.i32() as usize;
+        assert_eq!(reader.i16(), KEY_VERSION);
+        let object_length = reader.i32() as usize;
+        reader.u32();
+        let key_length = reader.i16() as usize;
+        assert_eq!(reader.i16(), 1);
+        let seek = reader.i64() as u64;
+        reader.i64();
+        let class_name = reader.string();
+        let name = reader.string();
+        reader.string();
+        Key { class_name, name, key_length, object_length, seek, bytes }
+    }
+
+    fn decompress(stored: &[u8], length: usize) -> Vec<u8> {
+        if stored.len() == length {
+            return stored.to_vec();
+        }
+        let mut object = vec![];
+        let mut position = 0;
+        while position < stored.len() {
+            assert_eq!(&stored[position..(position + 3)], b"ZL\x08");
+            let size = u32::from_le_bytes([stored[position + 3], stored[position + 4], stored[position + 5], 0]) as usize;
+            ZlibDecoder::new(&stored[(position + 9)..(position + 9 + size)]).read_to_end(&mut object).unwrap();
+            position += 9 + size;
+        }
+        assert_eq!(object.len(), length);
+        object
+    }
+
+    fn read_object(data: &[u8], key: &Key, skip: usize) -> Vec<u8> {
+        let start = key.seek as usize + key.key_length;
+        decompress(&data[(start + skip)..(key.seek as usize + key.bytes)], key.object_length)
+    }
+
+    #[derive(Debug)]
+    struct ReadBranch {
+        class: String,
+        name: String,
+        title: String,
+        leaf_class: String,
+        entries: i64,
+        baskets: Vec<(i32, i64, i64)>
+    }
+
+    fn read_branch(reader: &mut Reader, key_length: usize, classes: &mut HashMap<u32, String>) -> (ReadBranch, u32) {
+        let object_end = reader.versioned_object();
+        let class = reader.class(key_length, classes);
+        let element_end = if class == "TBranchElement" { Some(reader.versioned(BRANCH_ELEMENT_VERSION)) } else { None };
+        let end = reader.versioned(BRANCH_VERSION);
+        let (name, title) = reader.named();
+        let fill = reader.versioned(2);
+        reader.skip_to(fill);
+        assert_eq!(reader.i32(), COMPRESSION_SETTING);
+        reader.i32();
+        reader.i32();
+        let n_baskets = reader.i32() as usize;
+        reader.i64();
+        assert_eq!(reader.take(IO_FEATURES.len()), IO_FEATURES);
+        reader.i32();
+        let max_baskets = reader.i32() as usize;
+        reader.i32();
+        let entries = reader.i64();
+        reader.take(24);
+        let (sub_end, n_sub) = reader.array_header();
+        assert_eq!(n_sub, 0);
+        reader.skip_to(sub_end);
+
+        let (leaves_end, n_leaves) = reader.array_header();
+        assert_eq!(n_leaves, 1);
+        let leaf_reference = (reader.position + key_length + MAP_OFFSET) as u32;
+        let leaf_end = reader.versioned_object();
+        let leaf_class = reader.class(key_length, classes);
+        reader.skip_to(leaf_end);
+        reader.skip_to(leaves_end);
+        let (baskets_end, _) = reader.array_header();
+        reader.skip_to(baskets_end);
+
+        assert_eq!(reader.u8(), 1);
+        let bytes: Vec<i32> = (0..max_baskets).map(|_| reader.i32()).collect();
+        assert_eq!(reader.u8(), 1);
+        let first_entries: Vec<i64> = (0..max_baskets).map(|_| reader.i64()).collect();
+        assert_eq!(reader.u8(), 1);
+        let seeks: Vec<i64> = (0..max_baskets).map(|_| reader.i64()).collect();
+        assert_eq!(first_entries[n_baskets], entries);
+        reader.string();
+        assert_eq!(reader.position, end);
+        if let Some(element_end) = element_end {
+            assert_eq!(reader.string(), title_to_vector(&title));
+            reader.skip_to(element_end);
+        }
+        assert_eq!(reader.position, object_end);
+        let baskets = (0..n_baskets).map(|i| (bytes[i], first_entries[i], seeks[i])).collect();
+        (ReadBranch { class, name, title, leaf_class, entries, baskets }, leaf_reference)
+    }
+
+    //The vector branches written in the test are all named by their element type
+    fn title_to_vector(title: &str) -> String {
+        match title {
+            "SabreRing_Energy" => "vector<double>".to_string(),
+            "SabreRing_Channel" => "vector<int>".to_string(),
+            "SabreRing_Flags" => "vector<unsigned int>".to_string(),
+            "Samples" => "vector<unsigned short>".to_string(),
+            _ => panic!("unexpected vector branch {}", title)
+        }
+    }
+
+    impl Reader<'_> {
+        //Byte count of an object written through a pointer; returns the end of the object
+        fn versioned_object(&mut self) -> usize {
+            let count = self.u32();
+            assert_ne!(count & BYTE_COUNT_MASK, 0);
+            self.position + (count & !BYTE_COUNT_MASK) as usize
+        }
+    }
+
+    fn read_tree(data: &[u8]) -> (i64, Vec<ReadBranch>) {
+        let mut header = Reader::new(data, 0);
+        assert_eq!(header.take(4), b"root");
+        assert_eq!(header.i32(), ROOT_VERSION);
+        assert_eq!(header.i32(), BEGIN as i32);
+        assert_eq!(header.i64() as usize, data.len());
+
+        let directory = read_key(data, BEGIN as usize);
+        assert_eq!(directory.class_name, "TFile");
+        assert_eq!(directory.name, "run_1.root");
+        let mut reader = Reader::new(data, BEGIN as usize + directory.key_length);
+        reader.string();
+        reader.string();
+        assert_eq!(reader.i16(), DIRECTORY_VERSION);
+        reader.take(8);
+        reader.i32();
+        reader.i32();
+        assert_eq!(reader.i64(), BEGIN as i64);
+        reader.i64();
+        let keys_seek = reader.i64() as usize;
+
+        let keys = read_key(data, keys_seek);
+        let mut key_list = Reader::new(data, keys_seek + keys.key_length);
+        assert_eq!(key_list.i32(), 1);
+        let tree_key = read_key(data, key_list.position);
+        assert_eq!(tree_key.class_name, "TTree");
+        assert_eq!(tree_key.name, TREE_NAME);
+
+        let tree = read_object(data, &tree_key, 0);
+        let mut reader = Reader::new(&tree, 0);
+        let end = reader.versioned(TREE_VERSION);
+        assert_eq!(reader.named(), (TREE_NAME.to_string(), TREE_TITLE.to_string()));
+        for _ in 0..3 {
+            let attributes = reader.versioned(2);
+            reader.skip_to(attributes);
+        }
+        let entries = reader.i64();
+        reader.take(4 * 8 + 8 + 5 * 4 + 6 * 8 + 2);
+        assert_eq!(reader.take(IO_FEATURES.len()), IO_FEATURES);
+
+        let mut classes: HashMap<u32, String> = HashMap::new();
+        let (branches_end, n_branches) = reader.array_header();
+        let mut branches = vec![];
+        let mut leaf_references = vec![];
+        for _ in 0..n_branches {
+            let (branch, leaf) = read_branch(&mut reader, tree_key.key_length, &mut classes);
+            branches.push(branch);
+            leaf_references.push(leaf);
+        }
+        assert_eq!(reader.position, branches_end);
+        let (leaves_end, n_leaves) = reader.array_header();
+        assert_eq!(n_leaves, n_branches);
+        for leaf in leaf_references {
+            assert_eq!(reader.u32(), leaf);
+        }
+        assert_eq!(reader.position, leaves_end);
+        reader.take(4 * 7);
+        assert_eq!(reader.position, end);
+        assert_eq!(end, tree.len());
+        (entries, branches)
+    }
+
+    //Entries of the baskets of a branch: the bytes of each entry
+    fn read_entries(data: &[u8], branch: &ReadBranch, entry_size: usize) -> Vec<Vec<u8>> {
+        let mut entries = vec![];
+        for (bytes, _, seek) in branch.baskets.iter() {
+            let key = read_key(data, *seek as usize);
+            assert_eq!(key.class_name, "TBasket");
+            assert_eq!(key.name, branch.name);
+            assert_eq!(key.bytes, *bytes as usize);
+            let mut fields = Reader::new(data, *seek as usize + key.key_length - BASKET_HEADER_SIZE);
+            assert_eq!(fields.i16(), BASKET_VERSION);
+            fields.i32();
+            fields.i32();
+            let n = fields.i32() as usize;
+            let last = fields.i32() as usize - key.key_length;
+            let object = read_object(data, &key, 0);
+            if entry_size > 0 {
+                assert_eq!(last, n * entry_size);
+                entries.extend(object[..last].chunks(entry_size).map(|e| e.to_vec()));
+            } else {
+                let mut offsets = Reader::new(&object, last);
+                assert_eq!(offsets.i32() as usize, n + 1);
+                let mut starts: Vec<usize> = (0..n).map(|_| offsets.i32() as usize - key.key_length).collect();
+                starts.push(last);
+                entries.extend(starts.windows(2).map(|w| object[w[0]..w[1]].to_vec()));
+            }
+        }
+        entries
+    }
+
+    fn read_vector(entry: &[u8], size: usize) -> Vec<Vec<u8>> {
+        let mut reader = Reader::new(entry, 0);
+        let end = reader.versioned(STL_VERSION);
+        let n = reader.i32() as usize;
+        assert_eq!(end, entry.len());
+        assert_eq!(entry.len(), 10 + n * size);
+        entry[10..].chunks(size).map(|e| e.to_vec()).collect()
+    }
+
+    fn read_text(entry: &[u8]) -> String {
+        let mut reader = Reader::new(entry, 0);
+        let text = reader.string();
+        assert_eq!(reader.position, entry.len());
+        text
+    }
+
+    //Energy, channel and flags of each hit in a row, None for a row without hits
+    type SabreRow = Option<Vec<(f64, i32, u32)>>;
+
+    fn sabre_series(rows: &[SabreRow]) -> Series {
+        let mut builder = get_list_builder(&DataType::Struct(vec![Field::new("Energy", DataType::Float64),
+                                                                    Field::new("Channel", DataType::Int32),
+                                                                    Field::new("Flags", DataType::UInt32)]), 0, 0, "SabreRing").unwrap();
+        for row in rows {
+            let hits = row.as_ref().map(|hits| {
+                let energy = Series::new("Energy", hits.iter().map(|h| h.0).collect::<Vec<f64>>());
+                let channel = Series::new("Channel", hits.iter().map(|h| h.1).collect::<Vec<i32>>());
+                let flags = Series::new("Flags", hits.iter().map(|h| h.2).collect::<Vec<u32>>());
+                StructChunked::new("", &[energy, channel, flags]).unwrap().into_series()
+            });
+            builder.append_opt_series(hits.as_ref());
+        }
+        builder.finish().into_series()
+    }
+
+    fn samples_series(rows: &[Vec<u16>]) -> Series {
+        let mut builder = get_list_builder(&DataType::UInt16, 0, 0, "Samples").unwrap();
+        for row in rows {
+            builder.append_opt_series(Some(&Series::new("", row)));
+        }
+        builder.finish().into_series()
+    }
+
+    fn make_batch(xavg: &[f64], flags: &[Option<u32>], detector: &[&str], ring: &[SabreRow], samples: &[Vec<u16>]) -> DataFrame {
+        DataFrame::new(vec![Series::new("Xavg", xavg), Series::new("AnodeFrontFlags", flags), Series::new("Detector", detector),
+                            sabre_series(ring), samples_series(samples)]).unwrap()
+    }
+
+    //Write the example tree (three entries in two batches, plus an empty batch) to run_1.root in dir
+    fn write_example_tree(dir: &Path, long_name: &str) -> PathBuf {
+        let batches = [
+            make_batch(&[1.5, -2.0], &[Some(3), None], &["anode", ""],
+                       &[Some(vec![(10.0, 1, 0), (11.5, 2, 4)]), None], &[vec![1, 2, 3], vec![]]),
+            make_batch(&[7.25], &[Some(u32::MAX)], &[long_name], &[Some(vec![(3.0, -1, 1)])], &[vec![65535]])
+        ];
+
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("run_1.root");
+        let mut writer = TreeWriter::new(File::create(&path).unwrap(), "run_1.root", TREE_NAME, TREE_TITLE, &batches[0].schema()).unwrap();
+        for batch in batches.iter() {
+            writer.write_batch(batch).unwrap();
+        }
+        writer.write_batch(&batches[0].head(Some(0))).unwrap();
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let long_name = "a".repeat(300);
+        let dir = std::env::temp_dir().join(format!("spsevb_root_writer_{}", std::process::id()));
+        let data = std::fs::read(write_example_tree(&dir, &long_name)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (entries, branches) = read_tree(&data);
+        assert_eq!(entries, 3);
+        let names: Vec<&str> = branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["Xavg", "AnodeFrontFlags", "Detector", "SabreRing_Energy", "SabreRing_Channel", "SabreRing_Flags", "Samples"]);
+        let titles: Vec<&str> = branches.iter().map(|b| b.title.as_str()).collect();
+        assert_eq!(titles[..3], ["Xavg/D", "AnodeFrontFlags/i", "Detector/C"]);
+        let leaves: Vec<&str> = branches.iter().map(|b| b.leaf_class.as_str()).collect();
+        assert_eq!(leaves, ["TLeafD", "TLeafI", "TLeafC", "TLeafElement", "TLeafElement", "TLeafElement", "TLeafElement"]);
+        assert_eq!(branches[0].class, "TBranch");
+        assert_eq!(branches[3].class, "TBranchElement");
+        for branch in branches.iter() {
+            assert_eq!(branch.entries, 3);
+            assert_eq!(branch.baskets.iter().map(|b| b.1).collect::<Vec<i64>>(), [0, 2]);
+        }
+
+        let xavg: Vec<f64> = read_entries(&data, &branches[0], 8).iter().map(|e| f64::from_be_bytes(e[..].try_into().unwrap())).collect();
+        assert_eq!(xavg, [1.5, -2.0, 7.25]);
+        let flags: Vec<u32> = read_entries(&data, &branches[1], 4).iter().map(|e| u32::from_be_bytes(e[..].try_into().unwrap())).collect();
+        assert_eq!(flags, [3, 0, u32::MAX]);
+        let detector: Vec<String> = read_entries(&data, &branches[2], 0).iter().map(|e| read_text(e)).collect();
+        assert_eq!(detector, ["anode".to_string(), String::new(), long_name]);
+
+        let energy: Vec<Vec<f64>> = read_entries(&data, &branches[3], 0).iter()
+            .map(|e| read_vector(e, 8).iter().map(|v| f64::from_be_bytes(v[..].try_into().unwrap())).collect()).collect();
+        assert_eq!(energy, [vec![10.0, 11.5], vec![], vec![3.0]]);
+        let channel: Vec<Vec<i32>> = read_entries(&data, &branches[4], 0).iter()
+            .map(|e| read_vector(e, 4).iter().map(|v| i32::from_be_bytes(v[..].try_into().unwrap())).collect()).collect();
+        assert_eq!(channel, [vec![1, 2], vec![], vec![-1]]);
+        let ring_flags: Vec<Vec<u32>> = read_entries(&data, &branches[5], 0).iter()
+            .map(|e| read_vector(e, 4).iter().map(|v| u32::from_be_bytes(v[..].try_into().unwrap())).collect()).collect();
+        assert_eq!(ring_flags, [vec![0, 4], vec![], vec![1]]);
+        let samples: Vec<Vec<u16>> = read_entries(&data, &branches[6], 0).iter()
+            .map(|e| read_vector(e, 2).iter().map(|v| u16::from_be_bytes(v[..].try_into().unwrap())).collect()).collect();
+        assert_eq!(samples, [vec![1, 2, 3], vec![], vec![65535]]);
+    }
+
+    //Reads every branch of the tree with uproot and prints them as JSON
+    const UPROOT_SCRIPT: &str = "import sys, json, uproot
+tree = uproot.open(sys.argv[1])['SPSTree']
+print(json.dumps({name: tree[name].array().tolist() for name in tree.keys()}))";
+
+    //Independent check of the files against uproot, which reads ROOT files from the format specification rather than
+    //from the same assumptions as the reader above. Needs python3 with uproot (pip install uproot awkward).
+    #[test]
+    #[ignore = "needs python3 with uproot, run with --ignored"]
+    fn reads_back_with_uproot() {
+        let long_name = "a".repeat(300);
+        let dir = std::env::temp_dir().join(format!("spsevb_root_uproot_{}", std::process::id()));
+        let path = write_example_tree(&dir, &long_name);
+        let output = std::process::Command::new("python3").arg("-c").arg(UPROOT_SCRIPT).arg(&path).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success(), "uproot failed to read the file: {}", String::from_utf8_lossy(&output.stderr));
+
+        //JSON is a subset of YAML
+        let expected = format!("{{Xavg: [1.5, -2.0, 7.25], AnodeFrontFlags: [3, 0, {}], Detector: [anode, '', {}],
+                                SabreRing_Energy: [[10.0, 11.5], [], [3.0]], SabreRing_Channel: [[1, 2], [], [-1]],
+                                SabreRing_Flags: [[0, 4], [], [1]], Samples: [[1, 2, 3], [], [65535]]}}", u32::MAX, long_name);
+        let expected: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(&expected).unwrap();
+        let read: HashMap<String, serde_yaml::Value> = serde_yaml::from_slice(&output.stdout).unwrap();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn rejects_unsupported_columns() {
+        let mut builder = get_list_builder(&DataType::Boolean, 0, 0, "Bits").unwrap();
+        builder.append_opt_series(Some(&Series::new("", &[true, false])));
+        let df = DataFrame::new(vec![builder.finish().into_series()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("spsevb_root_unsupported_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let result = TreeWriter::new(File::create(dir.join("bits.root")).unwrap(), "bits.root", TREE_NAME, TREE_TITLE, &df.schema());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(PolarsError::InvalidOperation(_))));
+    }
+
+    #[test]
+    fn compresses_in_root_blocks() {
+        let object: Vec<u8> = (0..100_000).map(|i| (i % 7) as u8).collect();
+        let stored = compress(&object).unwrap().unwrap();
+        assert!(stored.len() < object.len());
+        assert_eq!(decompress(&stored, object.len()), object);
+        //Data which does not compress is stored as is
+        assert!(compress(&[1, 2, 3]).unwrap().is_none());
+    }
+}
@@ -24,38 +24,46 @@ impl UsedSize for SabreField {
 #[derive(Debug, Clone, Hash, Eq, PartialOrd, Ord, PartialEq, EnumIter, AsRefStr)]
 pub enum SabreSubField {
     Energy,
+    EnergyCal,
     Time,
     Channel,
-    DetID
+    DetID,
+    Flags
 }
 
 #[derive(Debug, Clone)]
 pub struct SabreData {
     pub energies: Vec<f64>,
+    pub energies_calibrated: Vec<f64>,
     pub times: Vec<f64>,
     pub channels: Vec<i32>,
-    pub det_ids: Vec<i32>
+    pub det_ids: Vec<i32>,
+    pub flags: Vec<u32>
 }
 
 impl UsedSize for SabreData {
     fn get_used_size(&self) -> usize {
         self.energies.get_used_size()+
+        self.energies_calibrated.get_used_size() +
         self.times.get_used_size() +
         self.channels.get_used_size() + 
-        self.det_ids.get_used_size()
+        self.det_ids.get_used_size() +
+        self.flags.get_used_size()
     }
 }
 
 impl SabreData {
     pub fn new() -> SabreData {
-        SabreData { energies: vec![], times: vec![], channels: vec![], det_ids: vec![] }
+        SabreData { energies: vec![], energies_calibrated: vec![], times: vec![], channels: vec![], det_ids: vec![], flags: vec![] }
     }
 
-    pub fn push(&mut self, energy: f64, time: f64, channel: i32, det_id: i32) {
+    pub fn push(&mut self, energy: f64, energy_calibrated: f64, time: f64, channel: i32, det_id: i32, flags: u32) {
         self.energies.push(energy);
+        self.energies_calibrated.push(energy_calibrated);
         self.times.push(time);
         self.channels.push(channel);
         self.det_ids.push(det_id);
+        self.flags.push(flags);
     }
 
     pub fn len(&self) -> usize {
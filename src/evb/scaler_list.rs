@@ -1,6 +1,6 @@
 use std::fs::File;
 use std::path::Path;
-use std::io::{BufReader, BufRead, BufWriter, Write};
+use std::io::{BufReader, BufRead, BufWriter, Read, Write};
 
 use super::compass_file::CompassFile;
 
@@ -21,6 +21,7 @@ impl Default for Scaler {
     }
 }
 
+/// List of channels which are only counted (scalers) rather than event built
 #[derive(Debug, Clone)]
 pub struct ScalerList {
     list: Vec<Scaler>
@@ -53,26 +54,40 @@ impl ScalerList {
         Ok(scalers)
     }
 
+    //Find the scaler whose file pattern matches the given file name, if any
+    fn find_scaler(&mut self, file_name: &str) -> Option<&mut Scaler> {
+        self.list.iter_mut().find(|scaler| file_name.starts_with(&scaler.file_pattern))
+    }
+
     //Check if file is a scaler, read counts if yes
     pub fn read_scaler(&mut self, filepath: &Path) -> bool {
-        for scaler in self.list.iter_mut() {
-            match filepath.file_name() {
-                Some(file_name) => {
-                    if file_name.to_str()
-                                .expect("Could not parse file name at ScalerList::read_scaler")
-                                .starts_with(&scaler.file_pattern)
-                    {
-                        if let Ok(compass_rep) = CompassFile::new(filepath, &None) {
-                            scaler.value = compass_rep.get_number_of_hits();
-                            return true
-                        }
-                    }
-                    else {
-                        continue
-                    }
-                },
-                None => continue
-            };
+        let file_name = match filepath.file_name() {
+            Some(name) => name.to_str().expect("Could not parse file name at ScalerList::read_scaler"),
+            None => return false
+        };
+
+        if let Some(scaler) = self.find_scaler(file_name) {
+            if let Ok(compass_rep) = CompassFile::new(filepath, &None) {
+                scaler.value = compass_rep.get_number_of_hits();
+                return true
+            }
+        }
+
+        return false;
+    }
+
+    //Check if a file name matches one of the scalers
+    pub fn is_scaler(&self, file_name: &str) -> bool {
+        self.list.iter().any(|scaler| file_name.starts_with(&scaler.file_pattern))
+    }
+
+    //Same as read_scaler, but for a file read from any reader (i.e. out of an archive), where size is the size of the file in bytes
+    pub fn read_scaler_reader(&mut self, file_name: &str, reader: Box<dyn Read + Send>, size: u64) -> bool {
+        if let Some(scaler) = self.find_scaler(file_name) {
+            if let Ok(compass_rep) = CompassFile::from_reader(reader, size, &None) {
+                scaler.value = compass_rep.get_number_of_hits();
+                return true
+            }
         }
 
         return false;
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use log::{info, warn};
+
+use super::channel_map::{ChannelMap, SPSChannelType};
+use super::compass_data::{CompassData, decompose_uuid_to_board_channel};
+use super::compass_run::{ProcessParams, clean_up_unpack_dir, open_run_archive};
+use super::hit_merger::HitMerger;
+use super::event_builder::EventBuilder;
+use super::error::EVBError;
+use super::scaler_list::ScalerList;
+
+//Number of bins on either side of the maximum used to calculate the peak centroid
+const CENTROID_HALF_WIDTH: usize = 2;
+
+//Histogram of the timestamp difference (ns) between a channel and the reference, spanning [-window, window)
+#[derive(Debug, Clone)]
+struct TimeHistogram {
+    min: f64,
+    bin_width: f64,
+    counts: Vec<u64>
+}
+
+impl TimeHistogram {
+    fn new(window: f64, bin_width: f64) -> Self {
+        let n_bins = (2.0 * window / bin_width).ceil() as usize;
+        TimeHistogram { min: -window, bin_width, counts: vec![0; n_bins.max(1)] }
+    }
+
+    fn fill(&mut self, value: f64) {
+        if value < self.min {
+            return;
+        }
+        let bin = ((value - self.min) / self.bin_width) as usize;
+        if let Some(count) = self.counts.get_mut(bin) {
+            *count += 1;
+        }
+    }
+
+    fn get_bin_center(&self, bin: usize) -> f64 {
+        self.min + (bin as f64 + 0.5) * self.bin_width
+    }
+
+    //Find the maximum bin, then refine with the centroid of the neighboring bins. The FWHM is estimated by
+    //walking out from the maximum until the counts drop below half. Empty and flat histograms have no peak.
+    fn find_peak(&self) -> Option<(u64, f64, f64)> {
+        let total: u64 = self.counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let (max_bin, max_counts) = self.counts.iter()
+                                        .enumerate()
+                                        .max_by_key(|(_, c)| **c)
+                                        .map(|(i, c)| (i, *c))?;
+        if self.counts.iter().all(|c| *c == max_counts) {
+            return None;
+        }
+
+        let low = max_bin.saturating_sub(CENTROID_HALF_WIDTH);
+        let high = (max_bin + CENTROID_HALF_WIDTH).min(self.counts.len() - 1);
+        let (mut sum, mut weighted_sum) = (0.0, 0.0);
+        for bin in low..=high {
+            sum += self.counts[bin] as f64;
+            weighted_sum += self.counts[bin] as f64 * self.get_bin_center(bin);
+        }
+        let centroid = weighted_sum / sum;
+
+        let half_max = max_counts as f64 * 0.5;
+        let mut left = max_bin;
+        while left > 0 && self.counts[left - 1] as f64 >= half_max {
+            left -= 1;
+        }
+        let mut right = max_bin;
+        while right < self.counts.len() - 1 && self.counts[right + 1] as f64 >= half_max {
+            right += 1;
+        }
+        let fwhm = (right - left + 1) as f64 * self.bin_width;
+
+        Some((total, centroid, fwhm))
+    }
+}
+
+/// Fitted time difference peak of one channel relative to the reference channel
+#[derive(Debug, Clone)]
+pub struct ChannelPeak {
+    pub board: u32,
+    pub channel: u32,
+    pub detector: String,
+    pub counts: u64,
+    pub centroid: f64,
+    pub fwhm: f64
+}
+
+impl ChannelPeak {
+    /// Time shift (ns) which moves the peak to zero
+    pub fn get_timeshift(&self) -> f64 {
+        -self.centroid
+    }
+}
+
+/// Result of a time shift calibration: one peak per channel which was found in coincidence with the reference
+#[derive(Debug, Clone, Default)]
+pub struct ShiftCalibration {
+    pub reference: String,
+    pub peaks: Vec<ChannelPeak>
+}
+
+impl ShiftCalibration {
+    /// Write the shifts in the ShiftMap file format
+    pub fn write_shift_map(&self, path: &Path) -> Result<(), EVBError> {
+        let mut file = File::create(path)?;
+        writeln!(file, "Board Channel TimeShift(ns)")?;
+        for peak in self.peaks.iter() {
+            writeln!(file, "{} {} {}", peak.board, peak.channel, peak.get_timeshift())?;
+        }
+        Ok(())
+    }
+
+    /// Write a report of the fitted peaks, for checking the calibration
+    pub fn write_report(&self, path: &Path) -> Result<(), EVBError> {
+        let mut file = File::create(path)?;
+        writeln!(file, "Reference: {}", self.reference)?;
+        writeln!(file, "{:<6} {:<8} {:<16} {:>10} {:>14} {:>10} {:>14}", "Board", "Channel", "Detector", "Counts", "Centroid(ns)", "FWHM(ns)", "TimeShift(ns)")?;
+        for peak in self.peaks.iter() {
+            writeln!(file, "{:<6} {:<8} {:<16} {:>10} {:>14.3} {:>10.3} {:>14.3}",
+                     peak.board, peak.channel, peak.detector, peak.counts, peak.centroid, peak.fwhm, peak.get_timeshift())?;
+        }
+        Ok(())
+    }
+}
+
+//Histogram every channel in the event relative to the first hit of the reference type
+fn fill_event(event: &[CompassData], reference: &SPSChannelType, map: &ChannelMap, window: f64, bin_width: f64,
+              histograms: &mut BTreeMap<u32, TimeHistogram>) {
+    let is_reference = |hit: &&CompassData| match map.get_channel_data(&hit.uuid) {
+        Some(data) => data.channel_type == *reference,
+        None => false
+    };
+    let ref_hit = match event.iter().find(is_reference) {
+        Some(hit) => hit,
+        None => return
+    };
+
+    for hit in event.iter() {
+        histograms.entry(hit.uuid)
+                  .or_insert_with(|| TimeHistogram::new(window, bin_width))
+                  .fill(hit.timestamp - ref_hit.timestamp);
+    }
+}
+
+/// Determine the time shift of every channel relative to a reference detector from the runs in [run_min, run_max).
+/// Hits are event built from the raw (unshifted) timestamps using the coincidence window, and for each event
+/// containing the reference, the time difference of each hit to the reference is histogrammed with the given bin
+/// width (ns). The peak of each histogram gives the shift for that channel.
+pub fn calibrate_shifts(params: &ProcessParams, reference: &SPSChannelType, bin_width: f64) -> Result<ShiftCalibration, EVBError> {
+    let channel_map = ChannelMap::new(&params.channel_map_filepath)?;
+    let no_shifts = None;
+    let mut histograms: BTreeMap<u32, TimeHistogram> = BTreeMap::new();
+    for run in params.run_min..params.run_max {
+        let archive_path = params.archive_dir.join(format!("run_{}.tar.gz", run));
+        if !archive_path.exists() {
+            continue;
+        }
+        info!("Histogramming run {}", run);
+
+        let mut scaler_list = match &params.scaler_list_filepath {
+            Some(path) => Some(ScalerList::new(path)?),
+            None => None
+        };
+        //Read the run the same way as event building, as chosen by the archive mode
+        let (files, is_unpacked) = open_run_archive(&archive_path, &params.unpack_dir, params.archive_mode,
+                                                    params.stream_memory_mb as u64 * 1_000_000, &no_shifts, false, &mut scaler_list)?;
+        let mut hits = HitMerger::new(files)?;
+        let mut evb = EventBuilder::new(&params.coincidence_window);
+        while let Some(hit) = hits.pop_earliest_hit()? {
+            evb.push_hit(&hit);
+            if evb.is_event_ready() {
+                fill_event(&evb.get_ready_event(), reference, &channel_map, params.coincidence_window, bin_width, &mut histograms);
+            }
+        }
+        if let Some(event) = evb.take_open_event() {
+            fill_event(&event, reference, &channel_map, params.coincidence_window, bin_width, &mut histograms);
+        }
+
+        drop(hits);
+        if is_unpacked {
+            clean_up_unpack_dir(&params.unpack_dir)?;
+        }
+    }
+
+    let mut calibration = ShiftCalibration { reference: reference.as_ref().to_string(), peaks: vec![] };
+    for (uuid, histogram) in histograms.iter() {
+        let (board, channel) = decompose_uuid_to_board_channel(uuid);
+        let detector = match channel_map.get_channel_data(uuid) {
+            Some(data) => data.channel_type.as_ref().to_string(),
+            None => SPSChannelType::None.as_ref().to_string()
+        };
+        match histogram.find_peak() {
+            Some((counts, centroid, fwhm)) => calibration.peaks.push(ChannelPeak { board, channel, detector, counts, centroid, fwhm }),
+            None => warn!("No peak in the time differences to the reference for board {} channel {}", board, channel)
+        }
+    }
+
+    if calibration.peaks.is_empty() {
+        warn!("No events contained the reference {}", calibration.reference);
+    }
+    calibration.peaks.sort_by_key(|p| (p.board, p.channel));
+    Ok(calibration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evb::compass_data::generate_board_channel_uuid;
+
+    //Histogram of a peak of the given counts per bin, centered on center (ns)
+    fn make_peak(center: f64, shape: &[u64]) -> TimeHistogram {
+        let mut histogram = TimeHistogram::new(50.0, 1.0);
+        let half = (shape.len() / 2) as f64;
+        for (i, count) in shape.iter().enumerate() {
+            let value = center + (i as f64 - half) * histogram.bin_width;
+            (0..*count).for_each(|_| histogram.fill(value));
+        }
+        histogram
+    }
+
+    fn make_hit(board: u32, channel: u32, timestamp: f64) -> CompassData {
+        CompassData { uuid: generate_board_channel_uuid(&board, &channel), timestamp, ..Default::default() }
+    }
+
+    #[test]
+    fn finds_peak_centroid_within_a_bin() {
+        for center in [-12.3, 0.0, 7.6, 30.2] {
+            let histogram = make_peak(center, &[2, 10, 40, 100, 40, 10, 2]);
+            let (counts, centroid, fwhm) = histogram.find_peak().unwrap();
+            assert_eq!(counts, 204);
+            assert!((centroid - center).abs() < histogram.bin_width, "centroid {} for a peak at {}", centroid, center);
+            assert!(fwhm >= histogram.bin_width && fwhm <= 3.0 * histogram.bin_width);
+        }
+    }
+
+    #[test]
+    fn empty_and_flat_histograms_have_no_peak() {
+        let empty = TimeHistogram::new(50.0, 1.0);
+        assert!(empty.find_peak().is_none());
+
+        let mut flat = TimeHistogram::new(50.0, 1.0);
+        for bin in 0..flat.counts.len() {
+            let value = flat.get_bin_center(bin);
+            (0..5).for_each(|_| flat.fill(value));
+        }
+        assert!(flat.find_peak().is_none());
+    }
+
+    #[test]
+    fn shift_moves_channel_onto_reference() {
+        let map = ChannelMap::new(Path::new("etc/ChannelMap.txt")).unwrap();
+        //ScintLeft is board 8 channel 1 and ScintRight is board 8 channel 0 in the default map
+        let mut histograms: BTreeMap<u32, TimeHistogram> = BTreeMap::new();
+        for event in 0..100 {
+            let start = event as f64 * 10_000.0;
+            let jitter = (event % 3) as f64 - 1.0;
+            let hits = [make_hit(8, 1, start), make_hit(8, 0, start + 12.0 + jitter)];
+            fill_event(&hits, &SPSChannelType::ScintLeft, &map, 50.0, 1.0, &mut histograms);
+        }
+        //Events without the reference are not histogrammed
+        fill_event(&[make_hit(8, 0, 5.0e6)], &SPSChannelType::ScintLeft, &map, 50.0, 1.0, &mut histograms);
+
+        let (counts, centroid, _) = histograms[&generate_board_channel_uuid(&8, &0)].find_peak().unwrap();
+        assert_eq!(counts, 100);
+        let peak = ChannelPeak { board: 8, channel: 0, detector: "ScintRight".to_string(), counts, centroid, fwhm: 0.0 };
+        //ScintRight comes 12 ns after the reference, so it has to be shifted earlier
+        assert!((peak.get_timeshift() + 12.0).abs() < 1.0, "shift {}", peak.get_timeshift());
+
+        //The reference itself is at zero, so it is not shifted
+        let (_, reference, _) = histograms[&generate_board_channel_uuid(&8, &1)].find_peak().unwrap();
+        assert!(reference.abs() < 1.0);
+    }
+}
@@ -47,6 +47,7 @@ impl std::error::Error for ShiftError {
 
 }
 
+/// Map of digitizer board/channel to a timestamp shift in ns, read from a shift map file
 #[derive(Debug, Clone)]
 pub struct ShiftMap {
     map: HashMap<u32, f64>
@@ -1,7 +1,12 @@
 #[allow(unused_imports)]
-use super::compass_data::{CompassData, decompose_uuid_to_board_channel};
+use super::compass_data::{CompassData, decompose_uuid_to_board_channel, INVALID_VALUE};
 use super::{channel_map::{ChannelMap, SPSChannelType}, sabre_fields::{SabreField, SabreData, SabreSubField}};
 use super::used_size::UsedSize;
+use super::calibration_map::CalibrationMap;
+use super::geometry::DetectorGeometry;
+use super::hit_list::{HitListField, HitListData, HitListSubField};
+use super::hit_policy::HitPolicy;
+use super::excitation::ExReconstructor;
 
 use std::collections::BTreeMap;
 use std::hash::Hash;
@@ -10,42 +15,80 @@ use strum::IntoEnumIterator;
 use strum_macros::{EnumIter, EnumCount, AsRefStr};
 
 use polars::prelude::*;
+use polars::chunked_array::builder::get_list_builder;
 
-const INVALID_VALUE: f64 = -1.0e6;
+
+const FOCAL_PLANE_COMPONENTS: [SPSChannelType; 9] = [
+    SPSChannelType::AnodeFront, SPSChannelType::AnodeBack, SPSChannelType::ScintLeft, SPSChannelType::ScintRight,
+    SPSChannelType::Cathode, SPSChannelType::DelayFrontLeft, SPSChannelType::DelayFrontRight,
+    SPSChannelType::DelayBackLeft, SPSChannelType::DelayBackRight
+];
 
 #[derive(Debug, Clone, Hash, Eq, PartialOrd, Ord, PartialEq, EnumIter, EnumCount, AsRefStr)]
 pub enum SPSDataField {
     AnodeFrontEnergy,
+    AnodeFrontEnergyCal,
     AnodeFrontShort,
     AnodeFrontTime,
+    AnodeFrontFlags,
+    AnodeFrontMult,
     AnodeBackEnergy,
+    AnodeBackEnergyCal,
     AnodeBackShort,
     AnodeBackTime,
+    AnodeBackFlags,
+    AnodeBackMult,
     ScintLeftEnergy,
+    ScintLeftEnergyCal,
     ScintLeftShort,
     ScintLeftTime,
+    ScintLeftFlags,
+    ScintLeftMult,
     ScintRightEnergy,
+    ScintRightEnergyCal,
     ScintRightShort,
     ScintRightTime,
+    ScintRightFlags,
+    ScintRightMult,
     CathodeEnergy,
+    CathodeEnergyCal,
     CathodeShort,
     CathodeTime,
+    CathodeFlags,
+    CathodeMult,
     DelayFrontLeftEnergy,
+    DelayFrontLeftEnergyCal,
     DelayFrontLeftShort,
     DelayFrontLeftTime,
+    DelayFrontLeftFlags,
+    DelayFrontLeftMult,
     DelayFrontRightEnergy,
+    DelayFrontRightEnergyCal,
     DelayFrontRightShort,
     DelayFrontRightTime,
+    DelayFrontRightFlags,
+    DelayFrontRightMult,
     DelayBackLeftEnergy,
+    DelayBackLeftEnergyCal,
     DelayBackLeftShort,
     DelayBackLeftTime,
+    DelayBackLeftFlags,
+    DelayBackLeftMult,
     DelayBackRightEnergy,
+    DelayBackRightEnergyCal,
     DelayBackRightShort,
     DelayBackRightTime,
+    DelayBackRightFlags,
+    DelayBackRightMult,
+    SabreRingMult,
+    SabreWedgeMult,
     X1,
     X2,
     Xavg,
-    Theta
+    Theta,
+    Rho,
+    EjectileKE,
+    Ex
 }
 
 impl SPSDataField {
@@ -53,6 +96,13 @@ impl SPSDataField {
     pub fn get_field_vec() -> Vec<SPSDataField> {
         SPSDataField::iter().collect()
     }
+
+    //The flag words are bitmasks, so they are stored as unsigned integers rather than floats
+    pub fn is_flags(&self) -> bool {
+        matches!(self, SPSDataField::AnodeFrontFlags | SPSDataField::AnodeBackFlags | SPSDataField::ScintLeftFlags |
+                       SPSDataField::ScintRightFlags | SPSDataField::CathodeFlags | SPSDataField::DelayFrontLeftFlags |
+                       SPSDataField::DelayFrontRightFlags | SPSDataField::DelayBackLeftFlags | SPSDataField::DelayBackRightFlags)
+    }
 }
 
 impl UsedSize for SPSDataField {
@@ -61,32 +111,203 @@ impl UsedSize for SPSDataField {
     }
 }
 
+//The columns filled by a single focal plane detector component
+struct ComponentFields {
+    energy: SPSDataField,
+    energy_cal: SPSDataField,
+    short: SPSDataField,
+    time: SPSDataField,
+    flags: SPSDataField,
+    mult: SPSDataField,
+    hits: HitListField
+}
+
+fn get_component_fields(channel_type: &SPSChannelType) -> Option<ComponentFields> {
+    match channel_type {
+        SPSChannelType::AnodeFront => Some(ComponentFields {
+            energy: SPSDataField::AnodeFrontEnergy,
+            energy_cal: SPSDataField::AnodeFrontEnergyCal,
+            short: SPSDataField::AnodeFrontShort,
+            time: SPSDataField::AnodeFrontTime,
+            flags: SPSDataField::AnodeFrontFlags,
+            mult: SPSDataField::AnodeFrontMult,
+            hits: HitListField::AnodeFrontHits
+        }),
+        SPSChannelType::AnodeBack => Some(ComponentFields {
+            energy: SPSDataField::AnodeBackEnergy,
+            energy_cal: SPSDataField::AnodeBackEnergyCal,
+            short: SPSDataField::AnodeBackShort,
+            time: SPSDataField::AnodeBackTime,
+            flags: SPSDataField::AnodeBackFlags,
+            mult: SPSDataField::AnodeBackMult,
+            hits: HitListField::AnodeBackHits
+        }),
+        SPSChannelType::ScintLeft => Some(ComponentFields {
+            energy: SPSDataField::ScintLeftEnergy,
+            energy_cal: SPSDataField::ScintLeftEnergyCal,
+            short: SPSDataField::ScintLeftShort,
+            time: SPSDataField::ScintLeftTime,
+            flags: SPSDataField::ScintLeftFlags,
+            mult: SPSDataField::ScintLeftMult,
+            hits: HitListField::ScintLeftHits
+        }),
+        SPSChannelType::ScintRight => Some(ComponentFields {
+            energy: SPSDataField::ScintRightEnergy,
+            energy_cal: SPSDataField::ScintRightEnergyCal,
+            short: SPSDataField::ScintRightShort,
+            time: SPSDataField::ScintRightTime,
+            flags: SPSDataField::ScintRightFlags,
+            mult: SPSDataField::ScintRightMult,
+            hits: HitListField::ScintRightHits
+        }),
+        SPSChannelType::Cathode => Some(ComponentFields {
+            energy: SPSDataField::CathodeEnergy,
+            energy_cal: SPSDataField::CathodeEnergyCal,
+            short: SPSDataField::CathodeShort,
+            time: SPSDataField::CathodeTime,
+            flags: SPSDataField::CathodeFlags,
+            mult: SPSDataField::CathodeMult,
+            hits: HitListField::CathodeHits
+        }),
+        SPSChannelType::DelayFrontLeft => Some(ComponentFields {
+            energy: SPSDataField::DelayFrontLeftEnergy,
+            energy_cal: SPSDataField::DelayFrontLeftEnergyCal,
+            short: SPSDataField::DelayFrontLeftShort,
+            time: SPSDataField::DelayFrontLeftTime,
+            flags: SPSDataField::DelayFrontLeftFlags,
+            mult: SPSDataField::DelayFrontLeftMult,
+            hits: HitListField::DelayFrontLeftHits
+        }),
+        SPSChannelType::DelayFrontRight => Some(ComponentFields {
+            energy: SPSDataField::DelayFrontRightEnergy,
+            energy_cal: SPSDataField::DelayFrontRightEnergyCal,
+            short: SPSDataField::DelayFrontRightShort,
+            time: SPSDataField::DelayFrontRightTime,
+            flags: SPSDataField::DelayFrontRightFlags,
+            mult: SPSDataField::DelayFrontRightMult,
+            hits: HitListField::DelayFrontRightHits
+        }),
+        SPSChannelType::DelayBackLeft => Some(ComponentFields {
+            energy: SPSDataField::DelayBackLeftEnergy,
+            energy_cal: SPSDataField::DelayBackLeftEnergyCal,
+            short: SPSDataField::DelayBackLeftShort,
+            time: SPSDataField::DelayBackLeftTime,
+            flags: SPSDataField::DelayBackLeftFlags,
+            mult: SPSDataField::DelayBackLeftMult,
+            hits: HitListField::DelayBackLeftHits
+        }),
+        SPSChannelType::DelayBackRight => Some(ComponentFields {
+            energy: SPSDataField::DelayBackRightEnergy,
+            energy_cal: SPSDataField::DelayBackRightEnergyCal,
+            short: SPSDataField::DelayBackRightShort,
+            time: SPSDataField::DelayBackRightTime,
+            flags: SPSDataField::DelayBackRightFlags,
+            mult: SPSDataField::DelayBackRightMult,
+            hits: HitListField::DelayBackRightHits
+        }),
+        _ => None
+    }
+}
+
+//Element type of the SABRE list columns
+fn get_sabre_dtype() -> DataType {
+    DataType::Struct(vec![
+        Field::new(SabreSubField::Energy.as_ref(), DataType::Float64),
+        Field::new(SabreSubField::EnergyCal.as_ref(), DataType::Float64),
+        Field::new(SabreSubField::Time.as_ref(), DataType::Float64),
+        Field::new(SabreSubField::Channel.as_ref(), DataType::Int32),
+        Field::new(SabreSubField::DetID.as_ref(), DataType::Int32),
+        Field::new(SabreSubField::Flags.as_ref(), DataType::UInt32)
+    ])
+}
+
+//Element type of the hit list columns
+fn get_hit_list_dtype() -> DataType {
+    DataType::Struct(vec![
+        Field::new(HitListSubField::Energy.as_ref(), DataType::Float64),
+        Field::new(HitListSubField::EnergyCal.as_ref(), DataType::Float64),
+        Field::new(HitListSubField::Short.as_ref(), DataType::Float64),
+        Field::new(HitListSubField::Time.as_ref(), DataType::Float64),
+        Field::new(HitListSubField::Flags.as_ref(), DataType::UInt32)
+    ])
+}
+
+//Build a list column with a fixed element type. Letting polars infer the type would give a different
+//column type when every row is empty, which breaks writing the data in batches to one file.
+fn new_list_series(name: &str, dtype: &DataType, rows: impl Iterator<Item = Option<Series>>) -> Series {
+    let mut builder = get_list_builder(dtype, 0, 0, name).expect("List columns have a valid element type");
+    rows.for_each(|row| builder.append_opt_series(row.as_ref()));
+    builder.finish().into_series()
+}
+
+/// Everything other than the hits needed to turn an event into a row. It is built once per run and passed to
+/// [`SPSData::append_event`] for each event.
+#[derive(Debug, Clone, Copy)]
+pub struct EventContext<'a> {
+    /// Assigns hits to detector columns
+    pub channel_map: &'a ChannelMap,
+    /// Xavg weights from `calculate_weights`. If not set, Xavg is not calculated.
+    pub weights: Option<(f64, f64)>,
+    /// If set, fills the calibrated energies of the channels it contains
+    pub calibration: Option<&'a CalibrationMap>,
+    /// Delay line constants for X1, X2, and Theta
+    pub geometry: &'a DetectorGeometry,
+    /// If set, Rho, EjectileKE, and Ex are calculated from Xavg
+    pub reconstructor: Option<&'a ExReconstructor>
+}
+
+impl<'a> EventContext<'a> {
+    /// Context with only the channel map and geometry; set the other fields to add Xavg, calibration, and Ex
+    pub fn new(channel_map: &'a ChannelMap, geometry: &'a DetectorGeometry) -> Self {
+        EventContext { channel_map, weights: None, calibration: None, geometry, reconstructor: None }
+    }
+}
+
+/// Column oriented storage of event built data, one row per event, ready to be converted to a dataframe
 #[derive(Debug, Clone)]
 pub struct SPSData {
     //Columns must always come in same order, so use sorted map
     pub fields: BTreeMap<SPSDataField, Vec<f64>>,
+    //The ...Flags columns, null where the component has no hit
+    pub flags: BTreeMap<SPSDataField, Vec<Option<u32>>>,
     pub sabre: BTreeMap<SabreField, Vec<SabreData>>,
+    pub hits: BTreeMap<HitListField, Vec<HitListData>>,
+    pub hit_policy: HitPolicy,
     pub rows: usize
 }
 
 impl Default for SPSData {
     fn default() -> Self {
-        let fields = SPSDataField::get_field_vec();
-        let sabre_fields = SabreField::get_field_vec();
-        let mut data = SPSData { fields: BTreeMap::new(), sabre: BTreeMap::new(), rows: 0 };
-        fields.into_iter().for_each(|f| { data.fields.insert(f, vec![]); });
-        sabre_fields.into_iter().for_each(|f| { data.sabre.insert(f, vec![]); });
-        return data;
+        SPSData::new(HitPolicy::default())
     }
 }
 
 impl UsedSize for SPSData {
     fn get_used_size(&self) -> usize {
-        self.fields.get_used_size() + self.sabre.get_used_size()
+        self.fields.get_used_size() + self.flags.get_used_size() + self.sabre.get_used_size() + self.hits.get_used_size()
     }
 }
 
 impl SPSData {
+    /// Create empty data, using the hit policy to handle multiple hits in a focal plane channel.
+    /// The hit list columns are only created if the policy keeps all hits.
+    pub fn new(hit_policy: HitPolicy) -> Self {
+        let fields = SPSDataField::get_field_vec();
+        let sabre_fields = SabreField::get_field_vec();
+        let mut data = SPSData { fields: BTreeMap::new(), flags: BTreeMap::new(), sabre: BTreeMap::new(), hits: BTreeMap::new(), hit_policy, rows: 0 };
+        fields.into_iter().for_each(|f| {
+            if f.is_flags() {
+                data.flags.insert(f, vec![]);
+            } else {
+                data.fields.insert(f, vec![]);
+            }
+        });
+        sabre_fields.into_iter().for_each(|f| { data.sabre.insert(f, vec![]); });
+        if data.hit_policy.keep_all_hits {
+            HitListField::get_field_vec().into_iter().for_each(|f| { data.hits.insert(f, vec![]); });
+        }
+        return data;
+    }
 
     //To keep columns all same length, push invalid values as necessary
     fn push_defaults(&mut self) {
@@ -96,11 +317,23 @@ impl SPSData {
             }
         }
 
+        for field in self.flags.iter_mut() {
+            if field.1.len() < self.rows {
+                field.1.push(None)
+            }
+        }
+
         for field in self.sabre.iter_mut() {
             if field.1.len() < self.rows {
                 field.1.push(SabreData::new())
             }
         }
+
+        for field in self.hits.iter_mut() {
+            if field.1.len() < self.rows {
+                field.1.push(HitListData::default())
+            }
+        }
     }
 
     //Update the last element to the given value
@@ -112,96 +345,111 @@ impl SPSData {
         }
     }
 
-    fn append_sabre(&mut self, field: &SabreField, energy: f64, time: f64, channel: i32, det_id: i32) {
+    fn set_flags(&mut self, field: &SPSDataField, value: u32) {
+        if let Some(list) = self.flags.get_mut(field) {
+            if let Some(back) = list.last_mut() {
+                *back = Some(value);
+            }
+        }
+    }
+
+    fn append_sabre(&mut self, field: &SabreField, hit: &CompassData, channel: i32, det_id: i32) {
         if let Some(list) = self.sabre.get_mut(field) {
             if let Some(sublist) = list.last_mut() {
-                sublist.push(energy, time, channel, det_id)
+                sublist.push(hit.energy, hit.energy_calibrated, hit.timestamp, channel, det_id, hit.flags.bits())
+            }
+        }
+    }
+
+    fn append_hit_list(&mut self, field: &HitListField, hits: &[&CompassData]) {
+        if let Some(list) = self.hits.get_mut(field) {
+            if let Some(sublist) = list.last_mut() {
+                hits.iter().for_each(|hit| sublist.push(hit));
             }
         }
     }
 
-    pub fn append_event(&mut self, event: Vec<CompassData>, map: &ChannelMap, weights: Option<(f64, f64)>) {
+    /// Add an event as a new row, using the context to assign hits to columns and calculate the physics
+    /// (see [`EventContext`]). When a focal plane channel has more than one hit, the hit policy selects which fills the columns.
+    pub fn append_event(&mut self, mut event: Vec<CompassData>, context: &EventContext) {
+        let geometry = context.geometry;
 
         self.rows += 1;
         self.push_defaults();
 
+        if let Some(cal_map) = context.calibration {
+            event.iter_mut().for_each(|hit| cal_map.calibrate(hit));
+        }
 
-        let mut dfl_time = INVALID_VALUE;
-        let mut dfr_time = INVALID_VALUE;
-        let mut dbl_time = INVALID_VALUE;
-        let mut dbr_time = INVALID_VALUE;
-        
+        //Group the focal plane hits by component, in time order
+        let mut fp_hits: Vec<(SPSChannelType, Vec<&CompassData>)> = vec![];
+        let mut sabre_ring_mult = 0;
+        let mut sabre_wedge_mult = 0;
         for hit in event.iter() {
             //Fill out detector fields using channel map
-            let channel_data = match map.get_channel_data(&hit.uuid) {
+            let channel_data = match context.channel_map.get_channel_data(&hit.uuid) {
                 Some(data) => data,
                 None => continue
             };
             match channel_data.channel_type {
-                SPSChannelType::ScintLeft => {
-                    self.set_value(&SPSDataField::ScintLeftEnergy, hit.energy);
-                    self.set_value(&SPSDataField::ScintLeftShort, hit.energy_short);
-                    self.set_value(&SPSDataField::ScintLeftTime, hit.timestamp);
-                }
-
-                SPSChannelType::ScintRight => {
-                    self.set_value(&SPSDataField::ScintRightEnergy, hit.energy);
-                    self.set_value(&SPSDataField::ScintRightShort, hit.energy_short);
-                    self.set_value(&SPSDataField::ScintRightTime, hit.timestamp);
-                }
-
-                SPSChannelType::Cathode => {
-                    self.set_value(&SPSDataField::CathodeEnergy, hit.energy);
-                    self.set_value(&SPSDataField::CathodeShort, hit.energy_short);
-                    self.set_value(&SPSDataField::CathodeTime, hit.timestamp);
+                SPSChannelType::SabreRing => {
+                    self.append_sabre(&SabreField::SabreRing, hit, channel_data.local_channel, channel_data.local_det_id);
+                    sabre_ring_mult += 1;
                 }
-
-                SPSChannelType::DelayFrontRight => {
-                    self.set_value(&SPSDataField::DelayFrontRightEnergy, hit.energy);
-                    self.set_value(&SPSDataField::DelayFrontRightShort, hit.energy_short);
-                    self.set_value(&SPSDataField::DelayFrontRightTime, hit.timestamp);
-                    dfr_time = hit.timestamp;
+                SPSChannelType::SabreWedge => {
+                    self.append_sabre(&SabreField::SabreWedge, hit, channel_data.local_channel, channel_data.local_det_id);
+                    sabre_wedge_mult += 1;
                 }
-
-                SPSChannelType::DelayFrontLeft => {
-                    self.set_value(&SPSDataField::DelayFrontLeftEnergy, hit.energy);
-                    self.set_value(&SPSDataField::DelayFrontLeftShort, hit.energy_short);
-                    self.set_value(&SPSDataField::DelayFrontLeftTime, hit.timestamp);
-                    dfl_time = hit.timestamp;
+                SPSChannelType::None => continue,
+                _ => match fp_hits.iter_mut().find(|(channel_type, _)| *channel_type == channel_data.channel_type) {
+                    Some((_, hits)) => hits.push(hit),
+                    None => fp_hits.push((channel_data.channel_type.clone(), vec![hit]))
                 }
+            }
+        }
+        self.set_value(&SPSDataField::SabreRingMult, sabre_ring_mult as f64);
+        self.set_value(&SPSDataField::SabreWedgeMult, sabre_wedge_mult as f64);
 
-                SPSChannelType::DelayBackRight => {
-                    self.set_value(&SPSDataField::DelayBackRightEnergy, hit.energy);
-                    self.set_value(&SPSDataField::DelayBackRightShort, hit.energy_short);
-                    self.set_value(&SPSDataField::DelayBackRightTime, hit.timestamp);
-                    dbr_time = hit.timestamp;
-                }
+        let scint_time = fp_hits.iter()
+                                .find(|(channel_type, _)| *channel_type == SPSChannelType::ScintLeft)
+                                .map(|(_, hits)| hits[0].timestamp);
 
-                SPSChannelType::DelayBackLeft => {
-                    self.set_value(&SPSDataField::DelayBackLeftEnergy, hit.energy);
-                    self.set_value(&SPSDataField::DelayBackLeftShort, hit.energy_short);
-                    self.set_value(&SPSDataField::DelayBackLeftTime, hit.timestamp);
-                    dbl_time = hit.timestamp;
-                }
+        //Multiplicity is always valid, components without hits have zero
+        for channel_type in FOCAL_PLANE_COMPONENTS.iter() {
+            if let Some(fields) = get_component_fields(channel_type) {
+                self.set_value(&fields.mult, 0.0);
+            }
+        }
 
-                SPSChannelType::AnodeFront => {
-                    self.set_value(&SPSDataField::AnodeFrontEnergy, hit.energy);
-                    self.set_value(&SPSDataField::AnodeFrontShort, hit.energy_short);
-                    self.set_value(&SPSDataField::AnodeFrontTime, hit.timestamp);
-                }
+        let mut dfl_time = INVALID_VALUE;
+        let mut dfr_time = INVALID_VALUE;
+        let mut dbl_time = INVALID_VALUE;
+        let mut dbr_time = INVALID_VALUE;
+        for (channel_type, hits) in fp_hits.iter() {
+            let fields = match get_component_fields(channel_type) {
+                Some(fields) => fields,
+                None => continue
+            };
+            self.set_value(&fields.mult, hits.len() as f64);
+            if self.hit_policy.keep_all_hits {
+                self.append_hit_list(&fields.hits, hits);
+            }
 
-                SPSChannelType::AnodeBack => {
-                    self.set_value(&SPSDataField::AnodeBackEnergy, hit.energy);
-                    self.set_value(&SPSDataField::AnodeBackShort, hit.energy_short);
-                    self.set_value(&SPSDataField::AnodeBackTime, hit.timestamp);
-                }
-                SPSChannelType::SabreRing => {
-                    self.append_sabre(&SabreField::SabreRing, hit.energy, hit.timestamp, channel_data.local_channel, channel_data.local_det_id);
-                }
-                SPSChannelType::SabreWedge => {
-                    self.append_sabre(&SabreField::SabreWedge, hit.energy, hit.timestamp, channel_data.local_channel, channel_data.local_det_id);
-                }
-                _ =>  continue
+            let hit = match self.hit_policy.selection.select(hits, scint_time) {
+                Some(hit) => hit,
+                None => continue
+            };
+            self.set_value(&fields.energy, hit.energy);
+            self.set_value(&fields.energy_cal, hit.energy_calibrated);
+            self.set_value(&fields.short, hit.energy_short);
+            self.set_value(&fields.time, hit.timestamp);
+            self.set_flags(&fields.flags, hit.flags.bits());
+            match channel_type {
+                SPSChannelType::DelayFrontLeft => dfl_time = hit.timestamp,
+                SPSChannelType::DelayFrontRight => dfr_time = hit.timestamp,
+                SPSChannelType::DelayBackLeft => dbl_time = hit.timestamp,
+                SPSChannelType::DelayBackRight => dbr_time = hit.timestamp,
+                _ => ()
             }
         }
 
@@ -209,57 +457,133 @@ impl SPSData {
         let mut x1 = INVALID_VALUE;
         let mut x2 = INVALID_VALUE;
         if dfr_time != INVALID_VALUE && dfl_time != INVALID_VALUE {
-            x1 = (dfl_time - dfr_time) * 0.5 * 1.0/2.1;
+            x1 = (dfl_time - dfr_time) * 0.5 / geometry.x1_delay_ns_per_mm;
             self.set_value(&SPSDataField::X1, x1);
         }
         if dbr_time != INVALID_VALUE && dbl_time != INVALID_VALUE {
-            x2 = (dbl_time - dbr_time) * 0.5 * 1.0/1.98;
+            x2 = (dbl_time - dbr_time) * 0.5 / geometry.x2_delay_ns_per_mm;
             self.set_value(&SPSDataField::X2, x2);
         }
         if x1 != INVALID_VALUE && x2 != INVALID_VALUE {
             let diff = x2 -x1;
             if diff > 0.0 {
-                self.set_value(&SPSDataField::Theta, (diff/36.0).atan());
+                self.set_value(&SPSDataField::Theta, (diff/geometry.delay_line_spacing).atan());
             } else if diff < 0.0 {
-                self.set_value(&SPSDataField::Theta, std::f64::consts::PI + (diff/36.0).atan());
+                self.set_value(&SPSDataField::Theta, std::f64::consts::PI + (diff/geometry.delay_line_spacing).atan());
             } else {
                 self.set_value(&SPSDataField::Theta, std::f64::consts::PI * 0.5);
             }
 
-            match weights {
-               Some(w) => self.set_value(&SPSDataField::Xavg, w.0 * x1 + w.1 * x2),
-               None => self.set_value(&SPSDataField::Xavg, INVALID_VALUE)
+            let xavg = match context.weights {
+               Some(w) => w.0 * x1 + w.1 * x2,
+               None => INVALID_VALUE
             };
+            self.set_value(&SPSDataField::Xavg, xavg);
+
+            if xavg != INVALID_VALUE {
+                if let Some(recon) = context.reconstructor {
+                    let result = recon.reconstruct(xavg);
+                    self.set_value(&SPSDataField::Rho, result.rho);
+                    self.set_value(&SPSDataField::EjectileKE, result.ejectile_ke);
+                    if result.ex.is_finite() {
+                        self.set_value(&SPSDataField::Ex, result.ex);
+                    }
+                }
+            }
         }
 
     }
 
+    /// Convert the data to polars Series, one for each column
     pub fn convert_to_series(self) -> Vec<Series> {
-        let mut sps_cols: Vec<Series> = self.fields.into_iter()
-                    .map(|field| -> Series {
-                        Series::new(field.0.as_ref(), field.1)
+        //Merge the float and flag columns back into field order
+        let mut keyed_cols: Vec<(SPSDataField, Series)> = self.fields.into_iter()
+                    .map(|field| {
+                        let series = Series::new(field.0.as_ref(), field.1);
+                        (field.0, series)
                     })
+                    .chain(self.flags.into_iter().map(|field| {
+                        let series = Series::new(field.0.as_ref(), field.1);
+                        (field.0, series)
+                    }))
                     .collect();
+        keyed_cols.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut sps_cols: Vec<Series> = keyed_cols.into_iter().map(|(_, series)| series).collect();
 
+        let sabre_dtype = get_sabre_dtype();
         let mut sabre_cols: Vec<Series>  = self.sabre.into_iter()
                     .map(|field| -> Series {
-                        Series::new(field.0.as_ref(), &field.1.into_iter()
+                        new_list_series(field.0.as_ref(), &sabre_dtype, field.1.into_iter()
                             .map(|data| -> Option<Series> {
                                 if data.len() == 0 {
                                     return None;
                                 }
                                 Some(StructChunked::new("list", &[
                                     Series::new(SabreSubField::Energy.as_ref(), data.energies),
+                                    Series::new(SabreSubField::EnergyCal.as_ref(), data.energies_calibrated),
                                     Series::new(SabreSubField::Time.as_ref(), data.times),
                                     Series::new(SabreSubField::Channel.as_ref(), data.channels),
-                                    Series::new(SabreSubField::DetID.as_ref(), data.det_ids)
+                                    Series::new(SabreSubField::DetID.as_ref(), data.det_ids),
+                                    Series::new(SabreSubField::Flags.as_ref(), data.flags)
+                                ]).unwrap().into_series())
+                            })
+                        )
+                    })
+                    .collect();
+        let hit_dtype = get_hit_list_dtype();
+        let mut hit_cols: Vec<Series> = self.hits.into_iter()
+                    .map(|field| -> Series {
+                        new_list_series(field.0.as_ref(), &hit_dtype, field.1.into_iter()
+                            .map(|data| -> Option<Series> {
+                                if data.is_empty() {
+                                    return None;
+                                }
+                                Some(StructChunked::new("list", &[
+                                    Series::new(HitListSubField::Energy.as_ref(), data.energies),
+                                    Series::new(HitListSubField::EnergyCal.as_ref(), data.energies_calibrated),
+                                    Series::new(HitListSubField::Short.as_ref(), data.energies_short),
+                                    Series::new(HitListSubField::Time.as_ref(), data.times),
+                                    Series::new(HitListSubField::Flags.as_ref(), data.flags)
                                 ]).unwrap().into_series())
                             })
-                            .collect::<Vec<Option<Series>>>()
                         )
                     })
                     .collect();
         sps_cols.append(&mut sabre_cols);
+        sps_cols.append(&mut hit_cols);
         return sps_cols
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use super::super::compass_data::{CompassFlags, generate_board_channel_uuid};
+
+    fn make_hit(board: u32, channel: u32, timestamp: f64, flags: CompassFlags) -> CompassData {
+        CompassData { uuid: generate_board_channel_uuid(&board, &channel), energy: 100.0, timestamp, flags, ..Default::default() }
+    }
+
+    #[test]
+    fn flags_are_unsigned_columns() {
+        let map = ChannelMap::new(Path::new("etc/ChannelMap.txt")).unwrap();
+        let geometry = DetectorGeometry::default();
+        let context = EventContext::new(&map, &geometry);
+        let mut data = SPSData::default();
+        //Anode front is board 8 channel 13 in etc/ChannelMap.txt
+        data.append_event(vec![make_hit(8, 13, 10.0, CompassFlags::PILE_UP | CompassFlags::FAKE_EVENT)], &context);
+
+        let columns = data.convert_to_series();
+        let names: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+        let fields = SPSDataField::get_field_vec();
+        let expected: Vec<&str> = fields.iter().map(|field| field.as_ref()).collect();
+        assert_eq!(names[..expected.len()], expected[..]);
+
+        let anode_flags = columns.iter().find(|column| column.name() == "AnodeFrontFlags").unwrap();
+        assert_eq!(anode_flags.dtype(), &DataType::UInt32);
+        assert_eq!(anode_flags.u32().unwrap().get(0), Some(0x8008));
+        let cathode_flags = columns.iter().find(|column| column.name() == "CathodeFlags").unwrap();
+        assert_eq!(cathode_flags.dtype(), &DataType::UInt32);
+        assert_eq!(cathode_flags.u32().unwrap().get(0), None);
+    }
+}
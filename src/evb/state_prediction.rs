@@ -0,0 +1,147 @@
+use std::fmt::Display;
+use std::num::ParseFloatError;
+use std::path::Path;
+
+use super::excitation::FocalPlaneCalibration;
+use super::geometry::DetectorGeometry;
+use super::energy_loss::TargetError;
+use super::kinematics::{KineParameters, ReactionData, apply_ejectile_loss, calculate_ejectile_ke, calculate_rho, calculate_z_offset};
+use super::nuclear_data::MassMap;
+
+/// Targets (Z, A) commonly found as contaminants on SPS targets
+pub const COMMON_CONTAMINANTS: [(u32, u32); 2] = [(6, 12), (8, 16)];
+
+#[derive(Debug)]
+pub enum PredictionError {
+    FileError(std::io::Error),
+    ParseError(ParseFloatError),
+    InvalidReaction(String),
+    TargetError(TargetError)
+}
+
+impl From<std::io::Error> for PredictionError {
+    fn from(value: std::io::Error) -> Self {
+        PredictionError::FileError(value)
+    }
+}
+
+impl From<ParseFloatError> for PredictionError {
+    fn from(value: ParseFloatError) -> Self {
+        PredictionError::ParseError(value)
+    }
+}
+
+impl From<TargetError> for PredictionError {
+    fn from(value: TargetError) -> Self {
+        PredictionError::TargetError(value)
+    }
+}
+
+impl Display for PredictionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PredictionError::FileError(x) => write!(f, "State prediction had an IO error: {}", x),
+            PredictionError::ParseError(x) => write!(f, "State prediction could not parse excitation energies: {}", x),
+            PredictionError::InvalidReaction(x) => write!(f, "State prediction cannot be done for the reaction {}", x),
+            PredictionError::TargetError(x) => write!(f, "State prediction cannot be done for the target: {}", x)
+        }
+    }
+}
+
+impl std::error::Error for PredictionError {
+
+}
+
+/// Parse a list of excitation energies (MeV) separated by commas or whitespace
+pub fn parse_levels(levels: &str) -> Result<Vec<f64>, PredictionError> {
+    let mut energies: Vec<f64> = vec![];
+    for entry in levels.split(|c: char| c == ',' || c.is_whitespace()).filter(|e| !e.is_empty()) {
+        energies.push(entry.parse()?);
+    }
+    Ok(energies)
+}
+
+/// Read a level scheme file. Each line has the excitation energy (MeV) of a state in the first column; any other
+/// columns (i.e. spin-parity) are ignored, as are empty lines and lines starting with #.
+pub fn read_levels(path: &Path) -> Result<Vec<f64>, PredictionError> {
+    let text = std::fs::read_to_string(path)?;
+    let mut energies: Vec<f64> = vec![];
+    for line in text.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(entry) = line.split_whitespace().next() {
+            energies.push(entry.parse()?);
+        }
+    }
+    Ok(energies)
+}
+
+/// How the focal plane position is predicted from rho. With a focal plane calibration the calibration is inverted;
+/// otherwise the position is estimated from the dispersion, relative to the rho of the central trajectory (cm).
+#[derive(Debug, Clone)]
+pub enum PositionModel {
+    Calibration(FocalPlaneCalibration),
+    Dispersion(f64)
+}
+
+impl PositionModel {
+    //Xavg in mm
+    fn get_xavg(&self, rho: f64, geometry: &DetectorGeometry) -> Option<f64> {
+        match self {
+            PositionModel::Calibration(cal) => cal.get_xavg(rho),
+            PositionModel::Dispersion(central_rho) => Some(10.0 * geometry.dispersion * (rho - central_rho))
+        }
+    }
+}
+
+/// Where a state is expected to land on the focal plane
+#[derive(Debug, Clone)]
+pub struct StatePrediction {
+    pub reaction: String,
+    pub ex: f64, //MeV
+    pub ejectile_ke: f64, //MeV, at the reaction point
+    pub exit_ke: f64, //MeV, after leaving the target
+    pub rho: f64, //cm
+    pub z_offset: f64, //cm
+    pub xavg: Option<f64> //mm
+}
+
+/// Predict the ejectile energy, rho, and focal plane position for each excitation energy (MeV) of the residual.
+/// States which are not kinematically allowed are left out.
+pub fn predict_states(params: &KineParameters, nuc_map: &MassMap, geometry: &DetectorGeometry, model: &PositionModel, levels: &[f64]) -> Result<Vec<StatePrediction>, PredictionError> {
+    let reaction = params.generate_rxn_eqn(nuc_map);
+    let rxn = match ReactionData::new(params, nuc_map) {
+        Some(data) => data,
+        None => return Err(PredictionError::InvalidReaction(reaction))
+    };
+    params.target.validate(params.sps_angle)?;
+
+    let mut predictions: Vec<StatePrediction> = vec![];
+    for ex in levels.iter() {
+        let ejectile_ke = match calculate_ejectile_ke(params, &rxn, *ex) {
+            Some(ke) => ke,
+            None => continue
+        };
+        let exit_ke = apply_ejectile_loss(params, &rxn, ejectile_ke);
+        let rho = calculate_rho(params, &rxn, exit_ke);
+        predictions.push(StatePrediction {
+            reaction: reaction.clone(),
+            ex: *ex,
+            ejectile_ke,
+            exit_ke,
+            rho,
+            z_offset: calculate_z_offset(params, nuc_map, geometry, *ex).unwrap_or(0.0),
+            xavg: model.get_xavg(rho, geometry)
+        });
+    }
+    Ok(predictions)
+}
+
+/// The same reaction and spectrograph settings on a different target (Z, A), i.e. a contaminant
+pub fn get_contaminant_kinematics(params: &KineParameters, target: (u32, u32)) -> KineParameters {
+    let mut contaminant = params.clone();
+    (contaminant.target_z, contaminant.target_a) = target;
+    contaminant.reaction = None;
+    contaminant
+}
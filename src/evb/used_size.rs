@@ -28,6 +28,18 @@ impl UsedSize for i32 {
     }
 }
 
+impl UsedSize for u32 {
+    fn get_used_size(&self) -> usize {
+        std::mem::size_of::<u32>()
+    }
+}
+
+impl UsedSize for Option<u32> {
+    fn get_used_size(&self) -> usize {
+        std::mem::size_of::<Option<u32>>()
+    }
+}
+
 impl UsedSize for f64 {
     fn get_used_size(&self) -> usize {
         std::mem::size_of::<f64>()
@@ -0,0 +1,83 @@
+use polars::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::channel_map::ChannelMap;
+use super::compass_data::{CompassData, decompose_uuid_to_board_channel};
+use super::used_size::UsedSize;
+
+const UNMAPPED_DETECTOR: &str = "None";
+
+/// What to do with the waveforms in CoMPASS files which have them
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum WaveformMode {
+    /// Parse past the samples, but do not keep them
+    #[default]
+    Skip,
+    /// Keep the samples and write them to a separate waveform file (`run_<number>_waves.parquet`),
+    /// keyed by the event number (the row of the event in the run's event built output)
+    Store
+}
+
+/// Column oriented storage of waveforms, one row per hit with a waveform
+#[derive(Debug, Clone, Default)]
+pub struct WaveformData {
+    pub events: Vec<u64>,
+    pub boards: Vec<u32>,
+    pub channels: Vec<u32>,
+    pub detectors: Vec<String>,
+    pub timestamps: Vec<f64>,
+    pub probes: Vec<u32>,
+    pub samples: Vec<Vec<u16>>
+}
+
+impl UsedSize for WaveformData {
+    fn get_used_size(&self) -> usize {
+        let n_samples: usize = self.samples.iter().map(|s| s.len()).sum();
+        self.events.len() * (std::mem::size_of::<u64>() + 3 * std::mem::size_of::<u32>() + std::mem::size_of::<f64>()
+                              + std::mem::size_of::<String>() + std::mem::size_of::<Vec<u16>>())
+            + n_samples * std::mem::size_of::<u16>()
+    }
+}
+
+impl WaveformData {
+    /// Add the waveforms (if any) of the hits in an event
+    pub fn append_event(&mut self, event_number: u64, event: &[CompassData], map: &ChannelMap) {
+        for hit in event.iter() {
+            if let Some(wave) = &hit.waveform {
+                let (board, channel) = decompose_uuid_to_board_channel(&hit.uuid);
+                self.events.push(event_number);
+                self.boards.push(board);
+                self.channels.push(channel);
+                self.detectors.push(match map.get_channel_data(&hit.uuid) {
+                    Some(data) => data.channel_type.as_ref().to_string(),
+                    None => UNMAPPED_DETECTOR.to_string()
+                });
+                self.timestamps.push(hit.timestamp);
+                self.probes.push(wave.probe as u32);
+                self.samples.push(wave.samples.clone());
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Convert the data to polars Series, one for each column
+    pub fn convert_to_series(self) -> Vec<Series> {
+        let samples: Vec<Series> = self.samples.into_iter().map(|s| Series::new("", s)).collect();
+        vec![
+            Series::new("Event", self.events),
+            Series::new("Board", self.boards),
+            Series::new("Channel", self.channels),
+            Series::new("Detector", self.detectors),
+            Series::new("Timestamp", self.timestamps),
+            Series::new("Probe", self.probes),
+            Series::new("Samples", samples)
+        ]
+    }
+}
@@ -8,6 +8,7 @@ const RAW_BINARY: &str = "raw_binary";
 const TEMP_BINARY: &str = "temp_binary";
 const BUILT: &str = "built";
 const SCALERS: &str = "scalers";
+const LOGS: &str = "logs";
 
 #[derive(Debug, Clone)]
 pub enum WorkspaceError {
@@ -100,6 +101,18 @@ impl Workspace {
         }
     }
 
+    //Job logs, created when first needed so that older workspaces still work
+    pub fn get_log_dir(&self) -> Result<PathBuf, WorkspaceError> {
+        let log_dir = self.parent_dir.join(LOGS);
+        if !log_dir.exists() {
+            match fs::create_dir(&log_dir) {
+                Ok(_) => (),
+                Err(_) => return Err(WorkspaceError::SubdirectoryError),
+            };
+        }
+        Ok(log_dir)
+    }
+
     fn init_workspace(&self) -> Result<(), WorkspaceError> {
         let raw_binary = self.parent_dir.join(RAW_BINARY);
         let temp_binary = self.parent_dir.join(TEMP_BINARY);
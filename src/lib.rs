@@ -0,0 +1,81 @@
+//! spsevb is an event builder for CAEN CoMPASS data from the Super-Enge Split-Pole Spectrograph (SPS) at FSU.
+//!
+//! The library exposes the same machinery used by the `spsevb-gui` and `spsevb-cli` binaries, so that
+//! it can be embedded in other tools (online monitoring, simulation, etc.). The typical flow is
+//!
+//! 1. Open a run: unpack a `run_<number>.tar.gz` archive with [`unpack_run_archive`] and open the binary
+//!    files with [`open_compass_files`], or read the files straight out of the archive with [`open_compass_archive`].
+//!    [`open_run_archive`] does either, as set by an [`ArchiveMode`]. Individual files can also be opened with
+//!    [`CompassFile::new`] or [`CompassFile::from_buffer`].
+//! 2. Iterate hits in time order: a [`HitMerger`] merges the files, returning one [`CompassData`] at a time.
+//! 3. Build events: push hits into an [`EventBuilder`], which groups them using a coincidence window.
+//! 4. Convert events: [`SPSData::append_event`] uses an [`EventContext`] (the [`ChannelMap`], geometry, and
+//!    optionally the calibration and reconstruction) to turn each event into a row.
+//! 5. Write output: [`write_dataframe`] writes the rows to a parquet, Arrow IPC or CSV file.
+//!
+//! [`process_runs`] does all of the above for a range of runs, which is what the binaries use.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use spsevb::{ChannelMap, DetectorGeometry, EventBuilder, EventContext, HitMerger, SPSData, EVBError};
+//! use spsevb::{unpack_run_archive, open_compass_files, write_dataframe};
+//!
+//! fn main() -> Result<(), EVBError> {
+//!     let channel_map = ChannelMap::new(Path::new("etc/ChannelMap.txt"))?;
+//!     let shift_map = None;
+//!     let geometry = DetectorGeometry::default();
+//!     let context = EventContext::new(&channel_map, &geometry);
+//!     let unpack_dir = Path::new("workspace/temp_binary");
+//!
+//!     unpack_run_archive(Path::new("workspace/raw_binary/run_1.tar.gz"), unpack_dir)?;
+//!     let mut hits = HitMerger::new(open_compass_files(unpack_dir, &shift_map, false, &mut None)?)?;
+//!
+//!     let mut evb = EventBuilder::new(&3000.0);
+//!     let mut data = SPSData::default();
+//!     while let Some(hit) = hits.pop_earliest_hit()? {
+//!         evb.push_hit(&hit);
+//!         if evb.is_event_ready() {
+//!             data.append_event(evb.get_ready_event(), &context);
+//!         }
+//!     }
+//!     //The last event is still open once the hits run out
+//!     if let Some(event) = evb.take_open_event() {
+//!         data.append_event(event, &context);
+//!     }
+//!
+//!     write_dataframe(data, Path::new("workspace/built/run_1.parquet"))?;
+//!     Ok(())
+//! }
+//! ```
+
+mod evb;
+
+pub use evb::calibration_map::{CalibrationMap, CalibrationError};
+pub use evb::channel_map::{ChannelMap, ChannelMapError, SPSChannelType};
+pub use evb::compass_data::{CompassData, CompassDataError, CompassFlags, RawCompassData};
+pub use evb::compass_file::CompassFile;
+pub use evb::compass_run::{process_runs, ProcessParams, ArchiveMode, unpack_run_archive, open_compass_files, open_compass_archive, open_run_archive, write_dataframe, write_waveforms};
+pub use evb::config::{AppParams, ConfigError};
+pub use evb::energy_loss::{TargetLayer, TargetElement, TargetError};
+pub use evb::error::EVBError;
+pub use evb::event_builder::EventBuilder;
+pub use evb::hit_merger::HitMerger;
+pub use evb::excitation::{ExReconstructor, ExcitationError, FocalPlaneCalibration};
+pub use evb::flag_policy::{FlagPolicies, FlagAction};
+pub use evb::fp_fit::{fit_focal_plane, read_calibration_points};
+pub use evb::geometry::DetectorGeometry;
+pub use evb::hit_policy::{HitPolicy, HitSelection};
+pub use evb::job_control::JobControl;
+pub use evb::kinematics::{KineParameters, ReactionData, calculate_weights};
+pub use evb::log_capture::{LogCapture, LogRecord, LogStore, get_job_log_name, write_records};
+pub use evb::nuclear_data::{MassMap, MassError};
+pub use evb::output_writer::{BatchWriter, Compression, OutputFormat, OutputSettings};
+pub use evb::progress::{Progress, format_duration};
+pub use evb::reaction::{ParsedReaction, ReactionError, parse_reaction};
+pub use evb::scaler_list::ScalerList;
+pub use evb::shift_calibration::calibrate_shifts;
+pub use evb::shift_map::{ShiftMap, ShiftError};
+pub use evb::sps_data::{SPSData, SPSDataField, EventContext};
+pub use evb::state_prediction::{PositionModel, StatePrediction, COMMON_CONTAMINANTS, get_contaminant_kinematics, parse_levels, predict_states, read_levels};
+pub use evb::waveform_data::{WaveformData, WaveformMode};
+pub use evb::ws::Workspace;
@@ -1,18 +1,26 @@
-mod evb;
 mod ui;
 
+use std::sync::Arc;
 use crate::ui::app::EVBApp;
 use log::error;
+use spsevb::{LogCapture, LogStore};
+
+//Number of log records kept for the log console
+const LOG_CAPACITY: usize = 10_000;
 
 fn main() {
-    simplelog::TermLogger::init(simplelog::LevelFilter::Info, 
-                                simplelog::Config::default(),
-                                simplelog::TerminalMode::Mixed, 
-                                simplelog::ColorChoice::Auto)
-                            .unwrap();
+    //Log to the terminal and to the log console in the app
+    let log_store = Arc::new(LogStore::new(LOG_CAPACITY));
+    simplelog::CombinedLogger::init(vec![
+        simplelog::TermLogger::new(simplelog::LevelFilter::Info,
+                                   simplelog::Config::default(),
+                                   simplelog::TerminalMode::Mixed,
+                                   simplelog::ColorChoice::Auto),
+        LogCapture::new(simplelog::LevelFilter::Info, log_store.clone())
+    ]).unwrap();
     let mut native_options = eframe::NativeOptions::default();
-    native_options.initial_window_size = Some(eframe::epaint::Vec2 { x: 600.0, y: 430.0 });
-    match eframe::run_native("SPS Event Builder", native_options, Box::new(|cc| Box::new( EVBApp::new(cc) ))) {
+    native_options.initial_window_size = Some(eframe::epaint::Vec2 { x: 640.0, y: 720.0 });
+    match eframe::run_native("SPS Event Builder", native_options, Box::new(|cc| Box::new( EVBApp::new(cc, log_store) ))) {
         Ok(_) => (),
         Err(x) => error!("Recieved eframe error: {}", x)
     };
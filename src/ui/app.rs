@@ -5,84 +5,82 @@ use native_dialog;
 use log::{error, info};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use serde::{Serialize, Deserialize};
-
-use std::path::{PathBuf, Path};
-use std::fs::File;
-use std::io::Write;
-
-use crate::evb::compass_run::{process_runs, ProcessParams};
-use crate::evb::error::EVBError;
-use crate::evb::nuclear_data::MassMap;
-use crate::evb::kinematics::KineParameters;
-use super::ws::{Workspace, WorkspaceError};
-
-#[derive(Debug, Serialize, Deserialize)]
-struct AppParams {
-    pub workspace: Option<Workspace>,
-    pub channel_map: Option<PathBuf>,
-    pub scaler_list: Option<PathBuf>,
-    pub shift_map: Option<PathBuf>,
-    pub kinematics: KineParameters,
-    pub coincidence_window: f64,
-    pub run_min: i32,
-    pub run_max: i32
-}
+use std::time::Duration;
+
+use std::path::Path;
+
+use spsevb::{
+    process_runs, ArchiveMode, AppParams, EVBError, FlagAction, HitSelection, Compression, OutputFormat, TargetLayer,
+    TargetElement, MassMap, Progress, format_duration, JobControl, LogStore, get_job_log_name, WaveformMode,
+    Workspace
+};
+
+use super::calculator::KinematicsCalculator;
+use super::log_console::LogConsole;
+
+const RUN_TABLE_HEIGHT: f32 = 200.0;
+const RUN_BAR_WIDTH: f32 = 120.0;
+const PROGRESS_REPAINT_MS: u64 = 250;
 
-impl Default for AppParams {
-    fn default() -> Self {
-        AppParams { workspace: None, channel_map: None, scaler_list: None, shift_map: None, kinematics: KineParameters::default(), coincidence_window: 3.0e3, run_min: 0, run_max: 0 }
+fn format_optional_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(d) => format_duration(d),
+        None => String::from("--:--:--")
     }
 }
 
 #[derive(Debug, Default)]
 pub struct EVBApp {
-    progress: Arc<Mutex<f32>>,
+    progress: Arc<Mutex<Progress>>,
+    control: Arc<JobControl>,
 
     parameters: AppParams,
 
     rxn_eqn: String,
+    rxn_input: String,
     mass_map: MassMap,
+    calculator: KinematicsCalculator,
+    log_console: LogConsole,
     thread_handle: Option<JoinHandle<Result<(), EVBError>>>
 }
 
 impl EVBApp {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(_cc: &eframe::CreationContext<'_>, log_store: Arc<LogStore>) -> Self {
         EVBApp {
-            progress: Arc::new(Mutex::new(0.0)),
+            progress: Arc::new(Mutex::new(Progress::default())),
+            control: Arc::new(JobControl::default()),
             parameters: AppParams::default(),
             rxn_eqn: String::from("None"),
-            mass_map: MassMap::new().expect("Could not open amdc data, shutting down!"),
+            rxn_input: String::new(),
+            mass_map: MassMap::new().expect("Could not read built-in mass table, shutting down!"),
+            calculator: KinematicsCalculator::default(),
+            log_console: LogConsole::new(log_store),
             thread_handle: None
         }
     }
 
-    fn check_and_startup_processing_thread(&mut self) -> Result<(), WorkspaceError> {
-        if self.thread_handle.is_none() && self.parameters.workspace.is_some() 
-           && self.parameters.channel_map.is_some() && self.parameters.scaler_list.is_some() {
-            let prog = self.progress.clone();
-            let r_params = ProcessParams {
-                archive_dir: self.parameters.workspace.as_ref().unwrap().get_archive_dir()?,
-                unpack_dir: self.parameters.workspace.as_ref().unwrap().get_unpack_dir()?,
-                output_dir: self.parameters.workspace.as_ref().unwrap().get_output_dir()?,
-                channel_map_filepath: self.parameters.channel_map.as_ref().unwrap().clone(),
-                scaler_list_filepath: self.parameters.scaler_list.clone(),
-                shift_map_filepath: self.parameters.shift_map.clone(),
-                coincidence_window: self.parameters.coincidence_window,
-                run_min: self.parameters.run_min,
-                run_max: self.parameters.run_max + 1, //Make it [run_min, run_max]
-            };
-
-            match self.progress.lock() {
-                Ok(mut x) => *x = 0.0,
-                Err(_) => error!("Could not aquire lock at starting processor..."),
-            };
-            let k_params = self.parameters.kinematics.clone();
-            self.thread_handle = Some(std::thread::spawn(|| process_runs(r_params, k_params, prog)));
-        } else {
-            error!("Cannot run event builder without all filepaths specified");
+    fn check_and_startup_processing_thread(&mut self) {
+        if self.thread_handle.is_some() {
+            return;
         }
-        Ok(())
+        let r_params = match self.parameters.get_process_params() {
+            Ok(params) => params,
+            Err(e) => {
+                error!("Cannot run event builder: {}", e);
+                return;
+            }
+        };
+        let prog = self.progress.clone();
+
+        match self.progress.lock() {
+            Ok(mut x) => x.reset(r_params.run_min, r_params.run_max),
+            Err(_) => error!("Could not aquire lock at starting processor..."),
+        };
+        self.start_job_log();
+        let k_params = self.parameters.kinematics.clone();
+        self.control = Arc::new(JobControl::default());
+        let control = self.control.clone();
+        self.thread_handle = Some(std::thread::spawn(|| process_runs(r_params, k_params, prog, control)));
     }
 
     fn check_and_shutdown_processing_thread(&mut self) {
@@ -92,6 +90,7 @@ impl EVBApp {
                     Ok(result) => {
                         match result {
                             Ok(_) => info!("Finished processing the run"),
+                            Err(EVBError::JobCancelled(runs)) => info!("Processing cancelled, completed runs: {:?}", runs),
                             Err(x) => error!(
                                 "An error occured while processing the run: {x}. Job stopped."
                             ),
@@ -99,240 +98,617 @@ impl EVBApp {
                     }
                     Err(_) => error!("An error occured in joining the processing thread!"),
                 };
+                self.log_console.get_store().stop_job_log();
             }
         }
     }
 
+    //Copy the log of the job to a file in the workspace
+    fn start_job_log(&self) {
+        let log_dir = match self.parameters.workspace.as_ref().map(|ws| ws.get_log_dir()) {
+            Some(Ok(dir)) => dir,
+            Some(Err(x)) => {
+                error!("Unable to create job log directory: {}", x);
+                return;
+            }
+            None => return
+        };
+        let path = log_dir.join(get_job_log_name());
+        match self.log_console.get_store().start_job_log(&path) {
+            Ok(_) => info!("Writing job log to {}", path.display()),
+            Err(x) => error!("Unable to create job log {}: {}", path.display(), x)
+        };
+    }
+
     fn write_params_to_file(&self, path: &Path) {
-        if let Ok(mut config) = File::create(path) {
-            match serde_yaml::to_string(&self.parameters) {
-                Ok(yaml_str) => match config.write(yaml_str.as_bytes()){
-                    Ok(_) => (),
-                    Err(x) => error!("Error writing config to file{}: {}", path.display(), x)
-                },
-                Err(x) => error!("Unable to write configuration to file, serializer error: {}",x)
-            };
-        } else {
-            error!("Could not open file {} for config write", path.display());
-        }
+        match self.parameters.write_to_file(path) {
+            Ok(_) => (),
+            Err(x) => error!("Unable to write configuration to file {}: {}", path.display(), x)
+        };
     }
 
     fn read_params_from_file(&mut self, path: &Path) {
-        let yaml_str = match std::fs::read_to_string(path) {
-            Ok(s) => s,
+        match AppParams::read_from_file(path) {
+            Ok(params) => self.parameters = params,
+            Err(x) => error!("Unable to read configuration from file {}: {}", path.display(), x)
+        };
+        self.load_mass_map();
+        self.rxn_input = self.parameters.kinematics.reaction.clone().unwrap_or_default();
+        self.set_kinematics();
+    }
+
+    //Load the mass file if one is given, otherwise the built-in table
+    fn load_mass_map(&mut self) {
+        match MassMap::load(&self.parameters.mass_file) {
+            Ok(map) => self.mass_map = map,
             Err(x) => {
-                error!("Unable to open and read config file {} with error {}", path.display(), x);
-                return
+                error!("Unable to read mass file, using the built-in table: {}", x);
+                self.parameters.mass_file = None;
+                self.mass_map = MassMap::new().expect("Could not read built-in mass table!");
             }
         };
-        
-        match serde_yaml::from_str::<AppParams>(&yaml_str) {
-            Ok(params) => self.parameters = params,
-            Err(x) => error!("Unable to write configuration to file, serializer error: {}",x)
-        };
+        info!("Using nuclear masses from {}", self.mass_map.get_evaluation());
+        self.rxn_eqn = self.parameters.kinematics.generate_rxn_eqn(&self.mass_map);
+    }
+
+    //Use the reaction equation if one was entered, otherwise the Z, A values
+    fn set_kinematics(&mut self) {
+        let input = self.rxn_input.trim();
+        self.parameters.kinematics.reaction = if input.is_empty() { None } else { Some(input.to_string()) };
+        if let Err(x) = self.parameters.kinematics.resolve_reaction(&self.mass_map) {
+            error!("Unable to set reaction {}: {}", input, x);
+            self.parameters.kinematics.reaction = None;
+        }
+        self.rxn_eqn = self.parameters.kinematics.generate_rxn_eqn(&self.mass_map);
     }
 }
 
 impl App for EVBApp {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-
+        let max_workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        //Menus on top, the run controls and progress at the bottom, and the settings scroll in between
+        //so that the run controls stay visible however many settings there are
+        egui::TopBottomPanel::top("MenuPanel").show(ctx, |ui| {
             //Menus
-            ui.menu_button("File", |ui| {
-                if ui.button("Open Config...").clicked() {
-                    let result = native_dialog::FileDialog::new()
-                               .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
-                               .add_filter("YAML file", &["yaml"])
-                               .show_open_single_file();
-                    match result {
-                        Ok(path) => match path {
-                            Some(real_path) => self.read_params_from_file(&real_path),
-                            None => ()
+            ui.horizontal(|ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Open Config...").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                   .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                   .add_filter("YAML file", &["yaml"])
+                                   .show_open_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.read_params_from_file(&real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
                         }
-                        Err(_) => error!("File dialog error!")
                     }
-                }
-                if ui.button("Save Config...").clicked() {
-                    let result = native_dialog::FileDialog::new()
-                               .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
-                               .add_filter("YAML file", &["yaml"])
-                               .show_save_single_file();
-                    match result {
-                        Ok(path) => match path {
-                            Some(real_path) => self.write_params_to_file(&real_path),
-                            None => ()
+                    if ui.button("Save Config...").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                   .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                   .add_filter("YAML file", &["yaml"])
+                                   .show_save_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.write_params_to_file(&real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
                         }
-                        Err(_) => error!("File dialog error!")
+                    }
+                });
+                ui.menu_button("Tools", |ui| {
+                    if ui.button("Kinematics Calculator").clicked() {
+                        self.calculator.open = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Log Console").clicked() {
+                        self.log_console.open = true;
+                        ui.close_menu();
+                    }
+                });
+            });
+        });
+
+        egui::TopBottomPanel::bottom("RunPanel").show(ctx, |ui| {
+            ui.add_space(4.0);
+            match self.progress.lock() {
+                Ok(prog) => {
+                    ui.add(egui::widgets::ProgressBar::new(prog.get_total_fraction())
+                           .text(format!("Runs finished: {}/{}", prog.get_number_finished(), prog.runs.len())));
+                    ui.label(format!("Hits: {} ({:.0} hits/s) | Events: {} | Elapsed: {} | ETA: {}",
+                                     prog.get_hits_processed(), prog.get_hit_rate(), prog.get_events(),
+                                     format_duration(prog.get_elapsed()), format_optional_duration(prog.get_eta())));
+                    egui::CollapsingHeader::new("Run Status").show(ui, |ui| {
+                        egui::ScrollArea::vertical().max_height(RUN_TABLE_HEIGHT).show(ui, |ui| {
+                            egui::Grid::new("RunStatusGrid").striped(true).show(ui, |ui| {
+                                for header in ["Run", "Status", "Progress", "Hits", "Events", "Hits/s", "Elapsed", "ETA"] {
+                                    ui.label(RichText::new(header).color(Color32::LIGHT_BLUE));
+                                }
+                                ui.end_row();
+
+                                for run in prog.runs.iter() {
+                                    ui.label(run.run_number.to_string());
+                                    ui.label(run.status.to_string());
+                                    ui.add(egui::widgets::ProgressBar::new(run.get_fraction()).desired_width(RUN_BAR_WIDTH).show_percentage());
+                                    ui.label(format!("{}/{}", run.hits_processed, run.total_hits));
+                                    ui.label(run.events.to_string());
+                                    ui.label(format!("{:.0}", run.get_hit_rate()));
+                                    ui.label(format_duration(run.get_elapsed()));
+                                    ui.label(format_optional_duration(run.get_eta()));
+                                    ui.end_row();
+                                }
+                            });
+                        });
+                    });
+                }
+                Err(_) => {
+                    ui.add(egui::widgets::ProgressBar::new(0.0));
+                }
+            };
+
+            let is_running = self.thread_handle.is_some();
+            let mut start_clicked = false;
+            ui.horizontal(|ui| {
+                start_clicked = ui.add_enabled(!is_running, egui::widgets::Button::new("Run")).clicked();
+                let pause_text = if self.control.is_paused() { "Resume" } else { "Pause" };
+                if ui.add_enabled(is_running && !self.control.is_cancelled(), egui::widgets::Button::new(pause_text)).clicked() {
+                    if self.control.is_paused() {
+                        info!("Resuming processor...");
+                        self.control.resume();
+                    } else {
+                        info!("Pausing processor...");
+                        self.control.pause();
                     }
                 }
+                if ui.add_enabled(is_running && !self.control.is_cancelled(), egui::widgets::Button::new("Cancel")).clicked() {
+                    info!("Cancelling processor, cleaning up the runs in progress...");
+                    self.control.cancel();
+                }
+                if is_running && self.control.is_cancelled() {
+                    ui.label("Cancelling...");
+                } else if is_running && self.control.is_paused() {
+                    ui.label("Paused");
+                }
             });
 
-            //Files/Workspace
-            ui.separator();
-            ui.label(RichText::new("Run Information").color(Color32::LIGHT_BLUE).size(18.0));
-            egui::Grid::new("RunGrid").show(ui,|ui| {
-                ui.label("Workspace: ");
-                ui.label(match &self.parameters.workspace {
-                    Some(ws) => ws.get_parent_str(),
-                    None => "None"
-                });
-                if ui.button("Open").clicked() {
-                    let result = native_dialog::FileDialog::new()
-                                 .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
-                                 .show_open_single_dir();
-                    match result {
-                        Ok(path) => match path {
-                            Some(real_path) => self.parameters.workspace = match Workspace::new(&real_path) {
-                                Ok(ws) => Some(ws),
-                                Err(e) => {
-                                    error!("Error creating workspace: {}", e);
-                                    None
+            if start_clicked {
+                info!("Starting processor...");
+                self.check_and_startup_processing_thread();
+            } else {
+                self.check_and_shutdown_processing_thread();
+            }
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                //Files/Workspace
+                ui.label(RichText::new("Run Information").color(Color32::LIGHT_BLUE).size(18.0));
+                egui::Grid::new("RunGrid").show(ui,|ui| {
+                    ui.label("Workspace: ");
+                    ui.label(match &self.parameters.workspace {
+                        Some(ws) => ws.get_parent_str(),
+                        None => "None"
+                    });
+                    if ui.button("Open").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                     .show_open_single_dir();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.parameters.workspace = match Workspace::new(&real_path) {
+                                    Ok(ws) => Some(ws),
+                                    Err(e) => {
+                                        error!("Error creating workspace: {}", e);
+                                        None
+                                    }
+                                },
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Channel Map: ");
+                    ui.label(match &self.parameters.channel_map {
+                        Some(real_path) => real_path.as_path().to_str().expect("Cannot display channel map!"),
+                        None => "None"
+                    });
+                    if ui.button("Open").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                     .add_filter("Text File", &["txt"])
+                                     .show_open_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.parameters.channel_map = Some(real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Scaler List: ");
+                    ui.label(match &self.parameters.scaler_list {
+                        Some(real_path) => real_path.as_path().to_str().expect("Cannot display scaler list!"),
+                        None => "None"
+                    });
+                    if ui.button("Open").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                     .add_filter("Text File", &["txt"])
+                                     .show_open_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.parameters.scaler_list = Some(real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Shift Map: ");
+                    ui.label(match &self.parameters.shift_map {
+                        Some(real_path) => real_path.as_path().to_str().expect("Cannot display shift map!"),
+                        None => "None"
+                    });
+                    if ui.button("Open").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                     .add_filter("Text File", &["txt"])
+                                     .show_open_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.parameters.shift_map = Some(real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Calibration Map: ");
+                    ui.label(match &self.parameters.calibration_map {
+                        Some(real_path) => real_path.as_path().to_str().expect("Cannot display calibration map!"),
+                        None => "None"
+                    });
+                    if ui.button("Open").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                     .add_filter("Text File", &["txt"])
+                                     .add_filter("YAML File", &["yaml", "yml"])
+                                     .show_open_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.parameters.calibration_map = Some(real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Focal Plane Calibration: ");
+                    ui.label(match &self.parameters.fp_calibration {
+                        Some(real_path) => real_path.as_path().to_str().expect("Cannot display focal plane calibration!"),
+                        None => "None"
+                    });
+                    if ui.button("Open").clicked() {
+                        let result = native_dialog::FileDialog::new()
+                                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                     .add_filter("YAML File", &["yaml", "yml"])
+                                     .show_open_single_file();
+                        match result {
+                            Ok(path) => match path {
+                                Some(real_path) => self.parameters.fp_calibration = Some(real_path),
+                                None => ()
+                            }
+                            Err(_) => error!("File dialog error!")
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Mass File: ");
+                    ui.label(self.mass_map.get_evaluation());
+                    ui.horizontal(|ui| {
+                        if ui.button("Open").clicked() {
+                            let result = native_dialog::FileDialog::new()
+                                         .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                                         .show_open_single_file();
+                            match result {
+                                Ok(path) => match path {
+                                    Some(real_path) => {
+                                        self.parameters.mass_file = Some(real_path);
+                                        self.load_mass_map();
+                                    },
+                                    None => ()
                                 }
-                            },
-                            None => ()
+                                Err(_) => error!("File dialog error!")
+                            }
                         }
-                        Err(_) => error!("File dialog error!")
+                        if ui.button("Built-in").clicked() {
+                            self.parameters.mass_file = None;
+                            self.load_mass_map();
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Coincidence Window (ns)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.coincidence_window).speed(100).custom_formatter(|n, _| {
+                        format!("{:e}", n)
+                    }));
+                    ui.end_row();
+
+                    ui.label("Run Min");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.run_min).speed(1));
+                    ui.end_row();
+
+                    ui.label("Run Max");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.run_max).speed(1));
+                    ui.end_row();
+
+                    ui.label("Workers");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.n_workers).speed(1).clamp_range(1..=max_workers));
+                    ui.end_row();
+
+                    ui.label("Archive Mode");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.parameters.archive_mode, ArchiveMode::Unpack, "Unpack to disk");
+                        ui.radio_value(&mut self.parameters.archive_mode, ArchiveMode::Stream, "Stream from archive");
+                    });
+                    ui.end_row();
+
+                    if self.parameters.archive_mode == ArchiveMode::Stream {
+                        ui.label("Stream Memory (MB)");
+                        ui.add(egui::widgets::DragValue::new(&mut self.parameters.stream_memory_mb).speed(10).clamp_range(1..=usize::MAX));
+                        ui.end_row();
                     }
-                }
-                ui.end_row();
 
-                ui.label("Channel Map: ");
-                ui.label(match &self.parameters.channel_map {
-                    Some(real_path) => real_path.as_path().to_str().expect("Cannot display channel map!"),
-                    None => "None"
+                    ui.label("Waveforms");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.parameters.waveform_mode, WaveformMode::Skip, "Skip");
+                        ui.radio_value(&mut self.parameters.waveform_mode, WaveformMode::Store, "Store to waveform file");
+                    });
                 });
-                if ui.button("Open").clicked() {
-                    let result = native_dialog::FileDialog::new()
-                                 .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
-                                 .add_filter("Text File", &["txt"])
-                                 .show_open_single_file();
-                    match result {
-                        Ok(path) => match path {
-                            Some(real_path) => self.parameters.channel_map = Some(real_path),
-                            None => ()
-                        }
-                        Err(_) => error!("File dialog error!")
+
+                //Flag policy elements
+                ui.separator();
+                ui.label(RichText::new("Hit Flags").color(Color32::LIGHT_BLUE).size(18.0));
+                egui::Grid::new("FlagGrid").show(ui,|ui| {
+                    let policies = &mut self.parameters.flag_policies;
+                    for (label, action) in [("Pile-up", &mut policies.pile_up),
+                                            ("Saturation", &mut policies.saturation),
+                                            ("Lost Trigger", &mut policies.lost_trigger),
+                                            ("Fake Event", &mut policies.fake_event)] {
+                        ui.label(label);
+                        egui::ComboBox::from_id_source(label)
+                            .selected_text(action.to_string())
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(action, FlagAction::Keep, FlagAction::Keep.to_string());
+                                ui.selectable_value(action, FlagAction::DropHit, FlagAction::DropHit.to_string());
+                                ui.selectable_value(action, FlagAction::DropEvent, FlagAction::DropEvent.to_string());
+                            });
+                        ui.end_row();
                     }
-                }
-                ui.end_row();
+                });
+
+                //Hit policy elements
+                ui.separator();
+                ui.label(RichText::new("Multiple Hits").color(Color32::LIGHT_BLUE).size(18.0));
+                egui::Grid::new("HitGrid").show(ui,|ui| {
+                    let selection = &mut self.parameters.hit_policy.selection;
+                    ui.label("Selected Hit");
+                    egui::ComboBox::from_id_source("HitSelection")
+                        .selected_text(selection.to_string())
+                        .show_ui(ui, |ui| {
+                            for option in [HitSelection::First, HitSelection::Last, HitSelection::LargestEnergy, HitSelection::ClosestToScint] {
+                                ui.selectable_value(selection, option, option.to_string());
+                            }
+                        });
+                    ui.end_row();
 
-                ui.label("Scaler List: ");
-                ui.label(match &self.parameters.scaler_list {
-                    Some(real_path) => real_path.as_path().to_str().expect("Cannot display scaler list!"),
-                    None => "None"
+                    ui.label("Keep All Hits");
+                    ui.checkbox(&mut self.parameters.hit_policy.keep_all_hits, "Write hit list columns");
+                    ui.end_row();
                 });
-                if ui.button("Open").clicked() {
-                    let result = native_dialog::FileDialog::new()
-                                 .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
-                                 .add_filter("Text File", &["txt"])
-                                 .show_open_single_file();
-                    match result {
-                        Ok(path) => match path {
-                            Some(real_path) => self.parameters.scaler_list = Some(real_path),
-                            None => ()
+
+                //Output elements
+                ui.separator();
+                ui.label(RichText::new("Output").color(Color32::LIGHT_BLUE).size(18.0));
+                egui::Grid::new("OutputGrid").show(ui,|ui| {
+                    let output = &mut self.parameters.output;
+                    ui.label("Batch Size (events)");
+                    ui.add(egui::widgets::DragValue::new(&mut output.batch_size).speed(1000).clamp_range(1..=usize::MAX));
+                    ui.label("Max Batch Memory (MB)");
+                    ui.add(egui::widgets::DragValue::new(&mut output.max_batch_memory_mb).speed(10).clamp_range(1..=usize::MAX));
+                    ui.end_row();
+
+                    ui.label("Format");
+                    egui::ComboBox::from_id_source("OutputFormat")
+                        .selected_text(output.format.to_string())
+                        .show_ui(ui, |ui| {
+                            #[allow(unused_mut)]
+                            let mut options = vec![OutputFormat::Parquet, OutputFormat::Ipc, OutputFormat::Csv];
+                            #[cfg(feature = "root-experimental")]
+                            options.push(OutputFormat::Root);
+                            for option in options {
+                                ui.selectable_value(&mut output.format, option, option.to_string());
+                            }
+                        });
+                    ui.label("Fragment Files");
+                    ui.checkbox(&mut output.fragment_files, "Write each batch to its own file");
+                    ui.end_row();
+
+                    //Compression, statistics and row groups only apply to parquet
+                    if output.format != OutputFormat::Parquet {
+                        return;
+                    }
+
+                    ui.label("Compression");
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_id_source("Compression")
+                            .selected_text(output.compression.to_string())
+                            .show_ui(ui, |ui| {
+                                for option in [Compression::Uncompressed, Compression::Snappy, Compression::Lz4, Compression::Zstd] {
+                                    ui.selectable_value(&mut output.compression, option, option.to_string());
+                                }
+                            });
+                        if let Some(range) = output.compression.get_level_range() {
+                            ui.label("Level");
+                            ui.add(egui::widgets::DragValue::new(&mut output.compression_level).speed(1).clamp_range(range));
                         }
-                        Err(_) => error!("File dialog error!")
+                    });
+                    ui.label("Statistics");
+                    ui.checkbox(&mut output.statistics, "Write column statistics");
+                    ui.end_row();
+
+                    ui.label("Row Group Size");
+                    ui.horizontal(|ui| {
+                        let mut is_limited = output.row_group_size.is_some();
+                        ui.checkbox(&mut is_limited, "Limit");
+                        match (is_limited, output.row_group_size) {
+                            (true, None) => output.row_group_size = Some(output.batch_size),
+                            (false, Some(_)) => output.row_group_size = None,
+                            _ => ()
+                        };
+                        match &mut output.row_group_size {
+                            Some(size) => { ui.add(egui::widgets::DragValue::new(size).speed(1000).clamp_range(1..=usize::MAX)); },
+                            None => { ui.label("One per batch"); }
+                        };
+                    });
+                    ui.end_row();
+                });
+
+                //Detector geometry elements
+                ui.separator();
+                ui.label(RichText::new("Detector Geometry").color(Color32::LIGHT_BLUE).size(18.0));
+                egui::Grid::new("GeometryGrid").show(ui,|ui| {
+                    ui.label("X1 Delay (ns/mm)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.geometry.x1_delay_ns_per_mm).speed(0.01));
+                    ui.label("X2 Delay (ns/mm)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.geometry.x2_delay_ns_per_mm).speed(0.01));
+                    ui.end_row();
+
+                    ui.label("Delay Line Spacing (mm)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.geometry.delay_line_spacing).speed(0.1));
+                    ui.label("Anode Wire Distance (cm)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.geometry.anode_wire_distance).speed(0.01));
+                    ui.end_row();
+
+                    ui.label("Dispersion");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.geometry.dispersion).speed(0.01));
+                    ui.label("Magnification");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.geometry.magnification).speed(0.01));
+                    ui.end_row();
+                });
+
+                //Kinematics elements
+                ui.separator();
+                ui.label(RichText::new("Kinematics").color(Color32::LIGHT_BLUE).size(18.0));
+                let mut za_changed = false;
+                egui::Grid::new("KineGrid").show(ui,|ui| {
+                    ui.label("Target Z     ");
+                    za_changed |= ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.target_z).speed(1)).changed();
+                    ui.label("Target A     ");
+                    za_changed |= ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.target_a).speed(1)).changed();
+                    ui.end_row();
+
+                    ui.label("Projectile Z");
+                    za_changed |= ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.projectile_z).speed(1)).changed();
+                    ui.label("Projectile A");
+                    za_changed |= ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.projectile_a).speed(1)).changed();
+                    ui.end_row();
+
+                    ui.label("Ejectile Z   ");
+                    za_changed |= ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.ejectile_z).speed(1)).changed();
+                    ui.label("Ejectile A   ");
+                    za_changed |= ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.ejectile_a).speed(1)).changed();
+                    ui.end_row();
+
+                    ui.label("Magnetic Field(kG)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.b_field).speed(10.0));
+                    ui.label("SPS Angle(deg)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.sps_angle).speed(1.0));
+                    ui.label("Projectile KE(MeV)");
+                    ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.projectile_ke).speed(0.01));
+                    ui.end_row();
+
+                    ui.label("Reaction Equation");
+                    ui.text_edit_singleline(&mut self.rxn_input);
+                    ui.label(&self.rxn_eqn);
+                    if ui.button("Set Kinematics").clicked() {
+                        self.set_kinematics();
                     }
+                });
+                //Editing Z, A by hand replaces the reaction equation
+                if za_changed {
+                    self.rxn_input.clear();
+                    self.parameters.kinematics.reaction = None;
                 }
-                ui.end_row();
 
-                ui.label("Shift Map: ");
-                ui.label(match &self.parameters.shift_map {
-                    Some(real_path) => real_path.as_path().to_str().expect("Cannot display shift map!"),
-                    None => "None"
+                //Target elements, for energy loss
+                ui.label(RichText::new("Target Layers").color(Color32::LIGHT_BLUE).size(14.0));
+                let target = &mut self.parameters.kinematics.target;
+                egui::Grid::new("TargetGrid").show(ui,|ui| {
+                    ui.label("Target Angle(deg)");
+                    ui.add(egui::widgets::DragValue::new(&mut target.angle).speed(1.0));
+                    ui.label("Reaction Layer");
+                    ui.add(egui::widgets::DragValue::new(&mut target.reaction_layer).speed(1).clamp_range(0..=target.layers.len().saturating_sub(1)));
+                    ui.end_row();
                 });
-                if ui.button("Open").clicked() {
-                    let result = native_dialog::FileDialog::new()
-                                 .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
-                                 .add_filter("Text File", &["txt"])
-                                 .show_open_single_file();
-                    match result {
-                        Ok(path) => match path {
-                            Some(real_path) => self.parameters.shift_map = Some(real_path),
-                            None => ()
+                let mut remove_layer: Option<usize> = None;
+                for (i, layer) in target.layers.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Layer {}", i));
+                        ui.label("Thickness(ug/cm^2)");
+                        ui.add(egui::widgets::DragValue::new(&mut layer.thickness).speed(1.0).clamp_range(0.0..=f64::MAX));
+                        if ui.button("Add Element").clicked() {
+                            layer.compound.push(TargetElement { z: 6, a: 12, count: 1 });
+                        }
+                        if ui.button("Remove Layer").clicked() {
+                            remove_layer = Some(i);
                         }
-                        Err(_) => error!("File dialog error!")
+                    });
+                    let mut remove_element: Option<usize> = None;
+                    for (j, element) in layer.compound.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label("    Z");
+                            ui.add(egui::widgets::DragValue::new(&mut element.z).speed(1));
+                            ui.label("A");
+                            ui.add(egui::widgets::DragValue::new(&mut element.a).speed(1));
+                            ui.label("Atoms");
+                            ui.add(egui::widgets::DragValue::new(&mut element.count).speed(1).clamp_range(1..=u32::MAX));
+                            if ui.button("Remove").clicked() {
+                                remove_element = Some(j);
+                            }
+                        });
+                    }
+                    if let Some(j) = remove_element {
+                        layer.compound.remove(j);
                     }
                 }
-                ui.end_row();
-
-                ui.label("Coincidence Window (ns)");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.coincidence_window).speed(100).custom_formatter(|n, _| {
-                    format!("{:e}", n)
-                }));
-                ui.end_row();
-
-                ui.label("Run Min");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.run_min).speed(1));
-                ui.end_row();
-
-                ui.label("Run Max");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.run_max).speed(1));
-            });
-
-            //Kinematics elements
-            ui.separator();
-            ui.label(RichText::new("Kinematics").color(Color32::LIGHT_BLUE).size(18.0));
-            egui::Grid::new("KineGrid").show(ui,|ui| {
-                ui.label("Target Z     ");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.target_z).speed(1));
-                ui.label("Target A     ");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.target_a).speed(1));
-                ui.end_row();
-
-                ui.label("Projectile Z");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.projectile_z).speed(1));
-                ui.label("Projectile A");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.projectile_a).speed(1));
-                ui.end_row();
-
-                ui.label("Ejectile Z   ");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.ejectile_z).speed(1));
-                ui.label("Ejectile A   ");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.ejectile_a).speed(1));
-                ui.end_row();
-
-                ui.label("Magnetic Field(kG)");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.b_field).speed(10.0));
-                ui.label("SPS Angle(deg)");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.sps_angle).speed(1.0));
-                ui.label("Projectile KE(MeV)");
-                ui.add(egui::widgets::DragValue::new(&mut self.parameters.kinematics.projectile_ke).speed(0.01));
-                ui.end_row();
-
-                ui.label("Reaction Equation");
-                ui.label(&self.rxn_eqn);
-                if ui.button("Set Kinematics").clicked() {
-                    self.rxn_eqn = self.parameters.kinematics.generate_rxn_eqn(&self.mass_map);
+                if let Some(i) = remove_layer {
+                    target.layers.remove(i);
+                }
+                if ui.button("Add Layer").clicked() {
+                    target.layers.push(TargetLayer::default());
                 }
             });
-
-            ui.separator();
-            ui.add(
-                egui::widgets::ProgressBar::new(match self.progress.lock() {
-                    Ok(x) => *x,
-                    Err(_) => 0.0,
-                })
-                .show_percentage(),
-            );
-
-            if ui
-                .add_enabled(
-                    self.thread_handle.is_none(),
-                    egui::widgets::Button::new("Run"),
-                )
-                .clicked()
-            {
-                info!("Starting processor...");
-                match self.check_and_startup_processing_thread() {
-                    Ok(_) => (),
-                    Err(e) => error!("Could not start processor, recieved the following error: {}", e)
-                };
-            } else {
-                self.check_and_shutdown_processing_thread();
-            }
         });
+
+        self.calculator.show(ctx, &self.parameters, &self.mass_map);
+        self.log_console.show(ctx);
+
+        //Keep the progress display updating while a job is running
+        if self.thread_handle.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(PROGRESS_REPAINT_MS));
+        }
     }
 
     
@@ -0,0 +1,169 @@
+use eframe::egui;
+use eframe::egui::{RichText, Color32};
+use log::{error, info};
+
+use spsevb::{
+    AppParams, FocalPlaneCalibration, MassMap, PositionModel, StatePrediction, COMMON_CONTAMINANTS,
+    get_contaminant_kinematics, parse_levels, predict_states, read_levels
+};
+
+//Window for predicting where states of the reaction (and of contaminant reactions) land on the focal plane
+#[derive(Debug)]
+pub struct KinematicsCalculator {
+    pub open: bool,
+    levels: String,
+    contaminant_levels: String,
+    show_contaminants: bool,
+    central_rho: f64, //cm
+    position_source: String,
+    results: Vec<StatePrediction>,
+    contaminant_results: Vec<StatePrediction>
+}
+
+impl Default for KinematicsCalculator {
+    fn default() -> Self {
+        KinematicsCalculator {
+            open: false,
+            levels: String::from("0.0"),
+            contaminant_levels: String::from("0.0"),
+            show_contaminants: false,
+            central_rho: 75.0,
+            position_source: String::new(),
+            results: vec![],
+            contaminant_results: vec![]
+        }
+    }
+}
+
+impl KinematicsCalculator {
+    pub fn show(&mut self, ctx: &egui::Context, params: &AppParams, nuc_map: &MassMap) {
+        let mut open = self.open;
+        egui::Window::new("Kinematics Calculator").open(&mut open).vscroll(true).show(ctx, |ui| {
+            egui::Grid::new("CalculatorGrid").show(ui, |ui| {
+                ui.label("Reaction");
+                ui.label(params.kinematics.generate_rxn_eqn(nuc_map));
+                ui.end_row();
+
+                ui.label("Excitation Energies (MeV)");
+                ui.text_edit_singleline(&mut self.levels);
+                if ui.button("Open Level File").clicked() {
+                    self.open_level_file();
+                }
+                ui.end_row();
+
+                ui.label("Central Rho (cm)");
+                ui.add_enabled(params.fp_calibration.is_none(), egui::widgets::DragValue::new(&mut self.central_rho).speed(0.1));
+                ui.label(if params.fp_calibration.is_some() { "Using focal plane calibration" } else { "Estimated from dispersion" });
+                ui.end_row();
+
+                ui.checkbox(&mut self.show_contaminants, "Contaminants (12C, 16O)");
+                ui.add_enabled(self.show_contaminants, egui::TextEdit::singleline(&mut self.contaminant_levels));
+                ui.end_row();
+            });
+
+            if ui.button("Calculate").clicked() {
+                self.calculate(params, nuc_map);
+            }
+
+            if !self.position_source.is_empty() {
+                ui.label(format!("Focal plane position from {}", self.position_source));
+            }
+            ui.separator();
+            egui::Grid::new("PredictionGrid").striped(true).show(ui, |ui| {
+                for header in ["Reaction", "Ex(MeV)", "EjectileKE(MeV)", "ExitKE(MeV)", "Rho(cm)", "Z-Offset(cm)", "Xavg(mm)"] {
+                    ui.label(RichText::new(header).color(Color32::LIGHT_BLUE));
+                }
+                ui.end_row();
+
+                let contaminants = self.contaminant_results.iter().map(|p| (p, true));
+                for (prediction, is_contaminant) in self.results.iter().map(|p| (p, false)).chain(contaminants) {
+                    let color = if is_contaminant { Color32::LIGHT_RED } else { ui.visuals().text_color() };
+                    ui.label(RichText::new(&prediction.reaction).color(color));
+                    ui.label(RichText::new(format!("{:.4}", prediction.ex)).color(color));
+                    ui.label(RichText::new(format!("{:.4}", prediction.ejectile_ke)).color(color));
+                    ui.label(RichText::new(format!("{:.4}", prediction.exit_ke)).color(color));
+                    ui.label(RichText::new(format!("{:.4}", prediction.rho)).color(color));
+                    ui.label(RichText::new(format!("{:.4}", prediction.z_offset)).color(color));
+                    ui.label(RichText::new(match prediction.xavg {
+                        Some(x) => format!("{:.2}", x),
+                        None => String::from("None")
+                    }).color(color));
+                    ui.end_row();
+                }
+            });
+        });
+        self.open = open;
+    }
+
+    fn open_level_file(&mut self) {
+        let result = native_dialog::FileDialog::new()
+                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                     .show_open_single_file();
+        match result {
+            Ok(Some(path)) => match read_levels(&path) {
+                Ok(levels) => self.levels = levels.iter().map(|ex| ex.to_string()).collect::<Vec<String>>().join(", "),
+                Err(x) => error!("Unable to read level file {}: {}", path.display(), x)
+            },
+            Ok(None) => (),
+            Err(_) => error!("File dialog error!")
+        }
+    }
+
+    fn get_position_model(&mut self, params: &AppParams) -> PositionModel {
+        if let Some(path) = &params.fp_calibration {
+            match FocalPlaneCalibration::new(path) {
+                Ok(cal) => {
+                    self.position_source = format!("the focal plane calibration {}", path.display());
+                    return PositionModel::Calibration(cal);
+                },
+                Err(x) => error!("Unable to read focal plane calibration, estimating positions from the dispersion: {}", x)
+            };
+        }
+        self.position_source = format!("the dispersion, relative to rho = {} cm", self.central_rho);
+        PositionModel::Dispersion(self.central_rho)
+    }
+
+    fn calculate(&mut self, params: &AppParams, nuc_map: &MassMap) {
+        self.results.clear();
+        self.contaminant_results.clear();
+        let model = self.get_position_model(params);
+
+        let levels = match parse_levels(&self.levels) {
+            Ok(levels) => levels,
+            Err(x) => {
+                error!("{}", x);
+                return;
+            }
+        };
+        match predict_states(&params.kinematics, nuc_map, &params.geometry, &model, &levels) {
+            Ok(predictions) => {
+                if predictions.len() < levels.len() {
+                    info!("{} of the states are not kinematically allowed", levels.len() - predictions.len());
+                }
+                self.results = predictions;
+            },
+            Err(x) => error!("{}", x)
+        };
+
+        if !self.show_contaminants {
+            return;
+        }
+        let contaminant_levels = match parse_levels(&self.contaminant_levels) {
+            Ok(levels) => levels,
+            Err(x) => {
+                error!("{}", x);
+                return;
+            }
+        };
+        for target in COMMON_CONTAMINANTS.iter() {
+            if *target == (params.kinematics.target_z, params.kinematics.target_a) {
+                continue;
+            }
+            let kinematics = get_contaminant_kinematics(&params.kinematics, *target);
+            match predict_states(&kinematics, nuc_map, &params.geometry, &model, &contaminant_levels) {
+                Ok(mut predictions) => self.contaminant_results.append(&mut predictions),
+                Err(x) => error!("{}", x)
+            };
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use eframe::egui;
+use eframe::egui::{RichText, Color32};
+use log::{error, Level, LevelFilter};
+
+use spsevb::{LogRecord, LogStore, write_records};
+
+const LEVELS: [LevelFilter; 3] = [LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info];
+
+fn get_level_color(level: Level) -> Color32 {
+    match level {
+        Level::Error => Color32::LIGHT_RED,
+        Level::Warn => Color32::YELLOW,
+        Level::Info => Color32::LIGHT_GRAY,
+        Level::Debug | Level::Trace => Color32::GRAY
+    }
+}
+
+//Window showing the captured log, with filtering by level and text, copy, and export.
+//Opens itself whenever a new error is logged, so that errors are not missed when there is no terminal.
+#[derive(Debug)]
+pub struct LogConsole {
+    pub open: bool,
+    store: Arc<LogStore>,
+    level: LevelFilter,
+    search: String,
+    errors_seen: usize
+}
+
+impl Default for LogConsole {
+    fn default() -> Self {
+        LogConsole::new(Arc::new(LogStore::default()))
+    }
+}
+
+impl LogConsole {
+    pub fn new(store: Arc<LogStore>) -> Self {
+        LogConsole { open: false, store, level: LevelFilter::Info, search: String::new(), errors_seen: 0 }
+    }
+
+    pub fn get_store(&self) -> &Arc<LogStore> {
+        &self.store
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let error_count = self.store.get_error_count();
+        if error_count > self.errors_seen {
+            self.errors_seen = error_count;
+            self.open = true;
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Log").open(&mut open).default_width(600.0).show(ctx, |ui| {
+            let records = self.store.get_records(self.level, &self.search);
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("LogLevel")
+                    .selected_text(self.level.to_string())
+                    .show_ui(ui, |ui| {
+                        for level in LEVELS {
+                            ui.selectable_value(&mut self.level, level, level.to_string());
+                        }
+                    });
+                ui.label("Search");
+                ui.text_edit_singleline(&mut self.search);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Copy").clicked() {
+                    let text = records.iter().map(|r| r.to_string()).collect::<Vec<String>>().join("\n");
+                    ui.output_mut(|o| o.copied_text = text);
+                }
+                if ui.button("Export...").clicked() {
+                    self.export(&records);
+                }
+                if ui.button("Clear").clicked() {
+                    self.store.clear();
+                }
+            });
+            ui.separator();
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::both().stick_to_bottom(true).auto_shrink([false, false]).show_rows(ui, row_height, records.len(), |ui, range| {
+                for record in records[range].iter() {
+                    ui.label(RichText::new(record.to_string()).monospace().color(get_level_color(record.level)));
+                }
+            });
+        });
+        self.open = open;
+    }
+
+    fn export(&self, records: &[LogRecord]) {
+        let result = native_dialog::FileDialog::new()
+                     .set_location(&std::env::current_dir().expect("Couldn't access runtime directory"))
+                     .add_filter("Log File", &["log", "txt"])
+                     .show_save_single_file();
+        match result {
+            Ok(Some(path)) => {
+                if let Err(x) = write_records(records, &path) {
+                    error!("Unable to export log to {}: {}", path.display(), x);
+                }
+            },
+            Ok(None) => (),
+            Err(_) => error!("File dialog error!")
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod app;
-mod ws;
\ No newline at end of file
+pub mod calculator;
+pub mod log_console;